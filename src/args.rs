@@ -1,18 +1,106 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 #[command(version, about)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     #[arg(short, long, default_value = "NONE")]
     pub file: String,
 
     #[arg(short, long, default_value = "false")]
     pub debug_inter: bool,
 
+    /// Seed for the random number generator, makes `rand` and friends deterministic
+    #[arg(long, global = true)]
+    pub seed: Option<u64>,
+
+    /// Forbid scripts from spawning processes, for running untrusted code
+    #[arg(long, default_value = "false", global = true)]
+    pub no_exec: bool,
+
+    /// Skip checking the declared stack effects of procs when they return
+    #[arg(long, default_value = "false", global = true)]
+    pub no_effect_checks: bool,
+
+    /// Optimize the program before running or building it, folding constants and removing
+    /// words that do nothing
+    #[arg(short = 'O', long, default_value = "false", global = true)]
+    pub optimize: bool,
+
     #[arg(trailing_var_arg = true, allow_hyphen_values = true, hide = true)]
     pub args: Vec<String>,
 }
 
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Run every `test_*` proc in the given files and directories
+    Test {
+        #[arg(required = true)]
+        paths: Vec<String>,
+    },
+    /// Check the stack effects of the `.seal` files in the given files and directories
+    Check {
+        #[arg(required = true)]
+        paths: Vec<String>,
+
+        /// Also check the types of the values on the stack
+        #[arg(long)]
+        types: bool,
+    },
+    /// Format the `.seal` files in the given files and directories
+    Fmt {
+        #[arg(required = true)]
+        paths: Vec<String>,
+
+        /// List the files that are not formatted instead of rewriting them, failing if any are
+        #[arg(long)]
+        check: bool,
+    },
+    /// Compile a `.seal` file to bytecode that runs without being lexed and linked again
+    Build {
+        file: String,
+
+        /// Where to write the bytecode, the file with a `.sealc` extension by default
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Transpile a `.seal` file to a C file that compiles to a native executable
+    EmitC {
+        file: String,
+
+        /// Where to write the C file, the file with a `.c` extension by default
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Time the interpreter on loop, recursion, variable and string workloads
+    Bench {
+        /// Another pinniped executable to compare with, both then run the workloads as files
+        #[arg(long)]
+        against: Option<String>,
+
+        /// How many times to run every workload, the median is reported
+        #[arg(long, default_value_t = 5)]
+        runs: usize,
+    },
+    /// Serve the Debug Adapter Protocol on stdin and stdout
+    Dap,
+    /// Serve the Language Server Protocol on stdin and stdout
+    Lsp {
+        /// Also report the types of the values on the stack that do not fit
+        #[arg(long)]
+        types: bool,
+    },
+}
+
+impl Default for Args {
+    /// The arguments of running `pinniped` with none given.
+    fn default() -> Self {
+        Args::parse_from(["pinniped"])
+    }
+}
+
 pub fn parse_args() -> Args {
     Args::parse()
 }
@@ -0,0 +1,170 @@
+use anyhow::{Context, Result};
+use colored::Colorize;
+use log::info;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::args::Args;
+use crate::engine::machine::Machine;
+use crate::engine::runtime::Captured;
+use crate::tokens::tokens;
+
+/// A program the benchmark runs, with the output that shows it computed the right result. The
+/// programs only use words the first release had, so it can be benchmarked against. Its `if` ran
+/// the last word of the body even when the condition was false, so `recursion` branches with a
+/// `while` that runs at most once instead.
+struct Workload {
+    name: &'static str,
+    source: &'static str,
+    output: &'static str,
+}
+
+const WORKLOADS: &[Workload] = &[
+    Workload {
+        name: "loop",
+        source: "(0) while : (200000) > do (1) + end . nl\n",
+        output: "200000\n",
+    },
+    Workload {
+        name: "recursion",
+        source: "(fib) proc\n  : (1) < while do\n    : (1) - {fib}\n    swp (2) - {fib} + (0)\n  \
+                 end\nret\n(20) {fib} . nl\n",
+        output: "6765\n",
+    },
+    Workload {
+        name: "variables",
+        source: "(0) >(a) (0) >(b) (0) >(c) (0) >(d) (0) >(e) (0) >(f) (0) >(g) (1) >(step)\n\
+                 (0) while : (100000) > do <(step) + end . nl\n",
+        output: "100000\n",
+    },
+    Workload {
+        name: "strings",
+        source: "(0) while : (20000) > do\n  (1) + (walrus) >(s) (seal) >(t)\n  \
+                 <(s) <(t) = >(same)\nend . <(same) . nl\n",
+        output: "20000false\n",
+    },
+];
+
+/// Fails unless `workload` printed its expected output.
+fn check_output(workload: &Workload, output: &[u8]) -> Result<()> {
+    let output = String::from_utf8_lossy(output);
+    if output != workload.output {
+        return Err(anyhow::anyhow!(
+            "Workload {} printed {:?} instead of {:?}",
+            workload.name,
+            output,
+            workload.output
+        ));
+    }
+    Ok(())
+}
+
+/// Runs the workload in this process, timing the interpreter without starting up and lexing.
+fn run_here(args: &Args, workload: &Workload) -> Result<Duration> {
+    let mut machine = Machine::new(args.clone());
+    machine.register_tokens(tokens());
+    let output = Captured::default();
+    machine.runtime().output = Box::new(output.clone());
+    let lexed = machine.lex_source(workload.source, workload.name)?;
+    let start = Instant::now();
+    machine.run_tokens(lexed)?;
+    let elapsed = start.elapsed();
+    check_output(workload, &output.take())?;
+    Ok(elapsed)
+}
+
+/// Runs a workload file with a pinniped executable, timing the whole process.
+fn run_with(binary: &Path, workload: &Workload, file: &Path) -> Result<Duration> {
+    let start = Instant::now();
+    let output = Command::new(binary)
+        .arg(file)
+        .stdin(Stdio::null())
+        .output()
+        .context(format!("Error running {}", binary.display()))?;
+    let elapsed = start.elapsed();
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "{} failed on {} ({}): {}",
+            binary.display(),
+            file.display(),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    check_output(workload, &output.stdout)?;
+    Ok(elapsed)
+}
+
+/// Median of `runs` timings.
+fn median(runs: usize, mut time: impl FnMut() -> Result<Duration>) -> Result<Duration> {
+    let mut times = (0..runs.max(1))
+        .map(|_| time())
+        .collect::<Result<Vec<Duration>>>()?;
+    times.sort();
+    Ok(times[times.len() / 2])
+}
+
+fn ms(duration: Duration) -> String {
+    format!("{:.1} ms", duration.as_secs_f64() * 1000.0)
+}
+
+/// Times the loop, recursion, variable and string workloads `runs` times each and prints the
+/// medians. With `against`, both this executable and the one at `against` run every workload as a
+/// file, so an older build of the interpreter can be compared with this one.
+pub fn run(args: &Args, against: Option<&str>, runs: usize) -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("pinniped-bench-{}", std::process::id()));
+    if against.is_some() {
+        std::fs::create_dir_all(&dir)?;
+    }
+    let result = bench(args, against, runs, &dir);
+    if against.is_some() {
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    result
+}
+
+fn bench(args: &Args, against: Option<&str>, runs: usize, dir: &Path) -> Result<()> {
+    info!("Running {} workloads {} times each", WORKLOADS.len(), runs);
+    for workload in WORKLOADS {
+        let Some(against) = against else {
+            let time = median(runs, || run_here(args, workload))?;
+            println!("bench {} ... {}", workload.name, ms(time).bold());
+            continue;
+        };
+        let file = dir.join(workload.name).with_extension("seal");
+        std::fs::write(&file, workload.source)?;
+        let current = std::env::current_exe()?;
+        let time = median(runs, || run_with(&current, workload, &file))?;
+        let other = median(runs, || run_with(&PathBuf::from(against), workload, &file))?;
+        let speedup = other.as_secs_f64() / time.as_secs_f64();
+        let speedup = format!("{:.2}x", speedup);
+        println!(
+            "bench {} ... {}, against {}: {}",
+            workload.name,
+            ms(time).bold(),
+            ms(other),
+            if time <= other {
+                speedup.green()
+            } else {
+                speedup.red()
+            }
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn workloads_succeed() {
+        let args = Args::default();
+        for workload in WORKLOADS {
+            if let Err(e) = run_here(&args, workload) {
+                panic!("{}: {}", workload.name, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,29 @@
+use anyhow::{Context, Result};
+use log::info;
+use std::path::{Path, PathBuf};
+
+use super::test::load;
+use crate::args::Args;
+
+/// Compiles the `.seal` file at `file` to bytecode, written to `output` or next to the file with a
+/// `.sealc` extension. With `-O` the program is optimized first.
+pub fn run(args: &Args, file: &str, output: Option<&str>) -> Result<()> {
+    let mut machine = load(args, Path::new(file))?;
+    if args.optimize {
+        machine.optimize()?;
+    }
+    let output = match output {
+        Some(output) => PathBuf::from(output),
+        None => Path::new(file).with_extension("sealc"),
+    };
+    let bytes = machine.bytecode();
+    std::fs::write(&output, &bytes)
+        .context(format!("Error writing bytecode: {}", output.display()))?;
+    info!(
+        "Wrote {} tokens to {} ({} bytes)",
+        machine.tokens().len(),
+        output.display(),
+        bytes.len()
+    );
+    Ok(())
+}
@@ -0,0 +1,52 @@
+use anyhow::Result;
+use colored::Colorize;
+use log::info;
+use std::path::Path;
+
+use super::test::{collect_files, load};
+use crate::args::Args;
+
+/// Checks the stack effects of the `.seal` files in `paths` without running them, and the types
+/// of the values with `typed`. Returns whether no problems were found.
+pub fn run(args: &Args, paths: &[String], typed: bool) -> Result<bool> {
+    let mut files = Vec::new();
+    for path in paths {
+        collect_files(Path::new(path), &mut files)?;
+    }
+    info!("Found {} files to check", files.len());
+
+    let mut problems = 0;
+    for file in &files {
+        let machine = match load(args, file) {
+            Ok(machine) => machine,
+            Err(e) => {
+                println!("{}: {}", file.display().to_string().blue().bold(), e);
+                problems += 1;
+                continue;
+            }
+        };
+        for problem in machine.check(typed) {
+            println!(
+                "{}:{}:{}: {}",
+                problem.file.blue().bold(),
+                problem.line.to_string().bright_black().bold(),
+                problem.col.to_string().bright_black().bold(),
+                problem.message.red()
+            );
+            problems += 1;
+        }
+    }
+
+    let result = if problems == 0 {
+        "ok".green()
+    } else {
+        "FAILED".red().bold()
+    };
+    println!(
+        "\ncheck result: {}. {} files checked; {} problems",
+        result,
+        files.len(),
+        problems
+    );
+    Ok(problems == 0)
+}
@@ -0,0 +1,450 @@
+use anyhow::Result;
+use log::info;
+use serde_json::{json, Value};
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use super::protocol::{read_message, write_message};
+use crate::args::Args;
+use crate::engine::debugger::{Breakpoint, Mode, StopReason};
+use crate::engine::input::Input;
+use crate::engine::machine::Machine;
+use crate::engine::runtime::{Captured, Exit};
+use crate::engine::stack::Stack;
+use crate::engine::variables::Variable;
+use crate::tokens::tokens;
+
+/// The machine runs on the adapter's only thread.
+const THREAD_ID: i64 = 1;
+
+/// Scopes with their variable references. They are the same for every frame, as the machine has
+/// one set of stacks and variables.
+const SCOPES: [(&str, i64); 5] = [
+    ("Stack", 1),
+    ("Return stack", 2),
+    ("Locals", 3),
+    ("Globals", 4),
+    ("Marks", 5),
+];
+
+/// How a request sets the program going once it is answered.
+enum Run {
+    Start,
+    Resume(Mode),
+}
+
+fn canonical(path: &str) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path))
+}
+
+fn stack_variables(stack: &Stack) -> Vec<Value> {
+    stack
+        .elements()
+        .iter()
+        .enumerate()
+        .map(|(i, element)| {
+            json!({
+                "name": i.to_string(),
+                "value": element.to_string(),
+                "type": element.type_name(),
+                "variablesReference": 0,
+            })
+        })
+        .collect()
+}
+
+fn named_variables(variables: &[&Variable]) -> Vec<Value> {
+    variables
+        .iter()
+        .map(|variable| {
+            json!({
+                "name": variable.name,
+                "value": variable.value.to_string(),
+                "type": variable.value.type_name(),
+                "variablesReference": 0,
+            })
+        })
+        .collect()
+}
+
+struct Session {
+    args: Args,
+    writer: Box<dyn Write>,
+    seq: i64,
+    machine: Option<Machine>,
+    /// Program output, sent to the client as `output` events. Stdout is the protocol channel, so
+    /// the program cannot print there.
+    output: Captured,
+    program: Option<PathBuf>,
+    stop_on_entry: bool,
+    /// Line breakpoints in the program and function breakpoints, kept across launches
+    lines: Vec<usize>,
+    procs: Vec<String>,
+}
+
+impl Session {
+    fn new(args: &Args, writer: Box<dyn Write>) -> Self {
+        Self {
+            args: args.clone(),
+            writer,
+            seq: 0,
+            machine: None,
+            output: Captured::default(),
+            program: None,
+            stop_on_entry: false,
+            lines: Vec::new(),
+            procs: Vec::new(),
+        }
+    }
+
+    fn send(&mut self, mut message: Value) -> Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        write_message(&mut self.writer, &message)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> Result<()> {
+        self.send(json!({"type": "event", "event": event, "body": body}))
+    }
+
+    fn machine(&self) -> Result<&Machine> {
+        match self.machine.as_ref() {
+            Some(machine) => Ok(machine),
+            None => Err(anyhow::anyhow!("No program is running")),
+        }
+    }
+
+    /// Answers one request. Returns false when the client disconnected.
+    fn handle(&mut self, request: &Value) -> Result<bool> {
+        let command = request["command"].as_str().unwrap_or("");
+        let arguments = &request["arguments"];
+        info!("DAP request: {}", command);
+        let mut run = None;
+        let body = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsFunctionBreakpoints": true,
+                "supportsEvaluateForHovers": true,
+            })),
+            "launch" => self.launch(arguments),
+            "setBreakpoints" => self.set_breakpoints(arguments),
+            "setFunctionBreakpoints" => self.set_function_breakpoints(arguments),
+            "setExceptionBreakpoints" => Ok(json!({"breakpoints": []})),
+            "configurationDone" => {
+                run = Some(Run::Start);
+                Ok(json!({}))
+            }
+            "threads" => Ok(json!({"threads": [{"id": THREAD_ID, "name": "main"}]})),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(json!({
+                "scopes": SCOPES
+                    .iter()
+                    .map(|(name, reference)| json!({
+                        "name": name,
+                        "variablesReference": reference,
+                        "expensive": false,
+                    }))
+                    .collect::<Vec<Value>>(),
+            })),
+            "variables" => self.variables(arguments),
+            "evaluate" => self.evaluate(arguments),
+            "continue" | "next" | "stepIn" | "stepOut" => self.machine().map(|machine| {
+                let depth = machine.return_stack().len();
+                run = Some(Run::Resume(match command {
+                    "next" => Mode::StepOver(depth),
+                    "stepIn" => Mode::Step,
+                    "stepOut" => Mode::Finish(depth),
+                    _ => Mode::Continue,
+                }));
+                json!({"allThreadsContinued": true})
+            }),
+            // The program only runs between requests, so it is always paused already
+            "pause" => Ok(json!({})),
+            "disconnect" | "terminate" => Ok(json!({})),
+            _ => Err(anyhow::anyhow!("Unsupported request: {}", command)),
+        };
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": command,
+            "success": body.is_ok(),
+        });
+        match body {
+            Ok(body) => response["body"] = body,
+            Err(e) => response["message"] = json!(e.to_string()),
+        }
+        self.send(response)?;
+        if matches!(command, "disconnect" | "terminate") {
+            return Ok(false);
+        }
+        if let Some(run) = run {
+            self.run(run)?;
+        }
+        Ok(true)
+    }
+
+    fn launch(&mut self, arguments: &Value) -> Result<Value> {
+        let program = match arguments["program"].as_str() {
+            Some(program) => program,
+            None => return Err(anyhow::anyhow!("Launch requires a program")),
+        };
+        let args = arguments["args"]
+            .as_array()
+            .map(|args| {
+                args.iter()
+                    .map(|arg| arg.as_str().map_or(arg.to_string(), str::to_string))
+                    .collect()
+            })
+            .unwrap_or_default();
+        self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+
+        let mut machine = Machine::new(Args {
+            command: None,
+            file: program.to_string(),
+            debug_inter: false,
+            args,
+            ..self.args.clone()
+        });
+        machine.register_tokens(tokens());
+        machine.preprocess()?;
+        machine.lex()?;
+        machine.after_lex()?;
+        machine.runtime().output = Box::new(self.output.clone());
+        machine.runtime().input = Input::from_reader(std::io::empty());
+        self.program = Some(canonical(program));
+        self.machine = Some(machine);
+        self.apply_breakpoints();
+        self.event("initialized", json!({}))?;
+        Ok(json!({}))
+    }
+
+    /// Hands the breakpoints the client set to the machine's debugger.
+    fn apply_breakpoints(&mut self) {
+        let machine = match self.machine.as_mut() {
+            Some(machine) => machine,
+            None => return,
+        };
+        let mut breakpoints = self
+            .lines
+            .iter()
+            .map(|line| Breakpoint::Line(*line))
+            .collect::<Vec<Breakpoint>>();
+        for name in &self.procs {
+            if let Ok(breakpoint @ Breakpoint::Proc { .. }) = machine.breakpoint(name) {
+                breakpoints.push(breakpoint);
+            }
+        }
+        machine.debugger().breakpoints = breakpoints;
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Value) -> Result<Value> {
+        let path = arguments["source"]["path"].as_str().unwrap_or("");
+        let lines = arguments["breakpoints"]
+            .as_array()
+            .map(|breakpoints| {
+                breakpoints
+                    .iter()
+                    .filter_map(|breakpoint| breakpoint["line"].as_u64())
+                    .map(|line| line as usize)
+                    .collect::<Vec<usize>>()
+            })
+            .unwrap_or_default();
+        // Only the program itself has lines to break on
+        let in_program = match &self.program {
+            Some(program) => canonical(path) == *program,
+            None => true,
+        };
+        if in_program {
+            self.lines = lines.clone();
+            self.apply_breakpoints();
+        }
+        let breakpoints = lines
+            .iter()
+            .map(|line| {
+                let verified = in_program
+                    && self.machine.as_ref().is_none_or(|machine| {
+                        machine.tokens().iter().any(|token| token.line == *line)
+                    });
+                json!({"verified": verified, "line": line})
+            })
+            .collect::<Vec<Value>>();
+        Ok(json!({"breakpoints": breakpoints}))
+    }
+
+    fn set_function_breakpoints(&mut self, arguments: &Value) -> Result<Value> {
+        self.procs = arguments["breakpoints"]
+            .as_array()
+            .map(|breakpoints| {
+                breakpoints
+                    .iter()
+                    .filter_map(|breakpoint| breakpoint["name"].as_str())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        self.apply_breakpoints();
+        let breakpoints = self
+            .procs
+            .iter()
+            .map(|name| {
+                let verified = self.machine.as_ref().is_none_or(|machine| {
+                    matches!(machine.breakpoint(name), Ok(Breakpoint::Proc { .. }))
+                });
+                json!({"verified": verified})
+            })
+            .collect::<Vec<Value>>();
+        Ok(json!({"breakpoints": breakpoints}))
+    }
+
+    fn frame(machine: &Machine, id: usize, pc: usize) -> Result<Value> {
+        let token = &machine.tokens()[pc];
+        let name = machine.proc_at(pc)?.unwrap_or("main".to_string());
+        let path = canonical(&token.file);
+        let file_name = Path::new(&*token.file)
+            .file_name()
+            .map_or(token.file.to_string(), |name| {
+                name.to_string_lossy().to_string()
+            });
+        Ok(json!({
+            "id": id,
+            "name": name,
+            "source": {"name": file_name, "path": path.to_string_lossy()},
+            "line": token.line,
+            "column": token.col,
+        }))
+    }
+
+    fn stack_trace(&self) -> Result<Value> {
+        let machine = self.machine()?;
+        let mut frames = vec![Self::frame(machine, 0, machine.pc())?];
+        // Every return address is the call that entered the frame above it
+        for address in machine.return_stack().elements().iter().rev() {
+            match address.as_int() {
+                Ok(address) if (address as usize) < machine.tokens().len() => {
+                    frames.push(Self::frame(machine, frames.len(), address as usize)?)
+                }
+                _ => {}
+            }
+        }
+        Ok(json!({"stackFrames": frames, "totalFrames": frames.len()}))
+    }
+
+    fn variables(&self, arguments: &Value) -> Result<Value> {
+        let machine = self.machine()?;
+        let variables = match arguments["variablesReference"].as_i64() {
+            Some(1) => stack_variables(machine.stack()),
+            Some(2) => stack_variables(machine.return_stack()),
+            Some(3) => named_variables(&machine.variables().locals()),
+            Some(4) => named_variables(&machine.variables().globals()),
+            Some(5) => machine
+                .marks()
+                .marks()
+                .iter()
+                .map(|mark| {
+                    json!({
+                        "name": mark.name,
+                        "value": mark.pc.to_string(),
+                        "variablesReference": 0,
+                    })
+                })
+                .collect(),
+            _ => return Err(anyhow::anyhow!("Unknown variables reference")),
+        };
+        Ok(json!({"variables": variables}))
+    }
+
+    /// Evaluates a variable name, `<name` for only locals and `<<name` for only globals.
+    fn evaluate(&self, arguments: &Value) -> Result<Value> {
+        let machine = self.machine()?;
+        let expression = arguments["expression"].as_str().unwrap_or("").trim();
+        let variables = machine.variables();
+        let value = if let Some(name) = expression.strip_prefix("<<") {
+            variables.get(name, false)
+        } else if let Some(name) = expression.strip_prefix('<') {
+            variables.get(name, true)
+        } else {
+            variables
+                .get(expression, true)
+                .or_else(|| variables.get(expression, false))
+        };
+        match value {
+            Some(value) => Ok(json!({"result": value.to_string(), "variablesReference": 0})),
+            None => Err(anyhow::anyhow!("Variable not found: {}", expression)),
+        }
+    }
+
+    /// Runs the program until it stops or ends, then tells the client which one happened.
+    fn run(&mut self, run: Run) -> Result<()> {
+        let stop_on_entry = self.stop_on_entry;
+        let machine = match self.machine.as_mut() {
+            Some(machine) => machine,
+            None => return Ok(()),
+        };
+        let result = match run {
+            Run::Start => {
+                machine.debugger().mode = if stop_on_entry {
+                    Mode::Step
+                } else {
+                    Mode::Continue
+                };
+                match machine.debug_check() {
+                    Some(reason) => Ok(Some(reason)),
+                    None => machine.resume(),
+                }
+            }
+            Run::Resume(mode) => {
+                machine.debugger().mode = mode;
+                machine.resume()
+            }
+        };
+
+        let output = self.output.take();
+        if !output.is_empty() {
+            self.event(
+                "output",
+                json!({"category": "stdout", "output": String::from_utf8_lossy(&output)}),
+            )?;
+        }
+        let code = match result {
+            Ok(Some(reason)) => {
+                let reason = match (reason, &run) {
+                    (StopReason::Breakpoint, _) => "breakpoint",
+                    (StopReason::Step, Run::Start) => "entry",
+                    (StopReason::Step, Run::Resume(_)) => "step",
+                };
+                return self.event(
+                    "stopped",
+                    json!({"reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true}),
+                );
+            }
+            Ok(None) => 0,
+            Err(e) => match e.downcast_ref::<Exit>() {
+                Some(Exit(code)) => *code,
+                None => {
+                    self.event(
+                        "output",
+                        json!({"category": "stderr", "output": format!("{}\n", e)}),
+                    )?;
+                    1
+                }
+            },
+        };
+        self.machine = None;
+        self.event("exited", json!({"exitCode": code}))?;
+        self.event("terminated", json!({}))
+    }
+}
+
+/// Serves the Debug Adapter Protocol on stdin and stdout until the client disconnects.
+pub fn run(args: &Args) -> Result<()> {
+    // Errors are sent to the client, which would show the escape codes
+    colored::control::set_override(false);
+    let mut reader = BufReader::new(std::io::stdin());
+    let mut session = Session::new(args, Box::new(std::io::stdout()));
+    while let Some(request) = read_message(&mut reader)? {
+        if !session.handle(&request)? {
+            break;
+        }
+    }
+    Ok(())
+}
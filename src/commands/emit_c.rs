@@ -0,0 +1,29 @@
+use anyhow::{Context, Result};
+use log::info;
+use std::path::{Path, PathBuf};
+
+use super::test::load;
+use crate::args::Args;
+
+/// Transpiles the `.seal` file at `file` to C, written to `output` or next to the file with a `.c`
+/// extension. With `-O` the program is optimized first.
+pub fn run(args: &Args, file: &str, output: Option<&str>) -> Result<()> {
+    let mut machine = load(args, Path::new(file))?;
+    if args.optimize {
+        machine.optimize()?;
+    }
+    let output = match output {
+        Some(output) => PathBuf::from(output),
+        None => Path::new(file).with_extension("c"),
+    };
+    let source = machine.transpile()?;
+    std::fs::write(&output, &source)
+        .context(format!("Error writing C file: {}", output.display()))?;
+    info!(
+        "Wrote {} tokens to {} ({} bytes)",
+        machine.tokens().len(),
+        output.display(),
+        source.len()
+    );
+    Ok(())
+}
@@ -0,0 +1,187 @@
+use anyhow::{Context, Result};
+use colored::Colorize;
+use log::info;
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+use super::test::collect_files;
+use crate::args::Args;
+use crate::engine::lexer::lexer::lex_with_comments;
+use crate::engine::lexer::token::{TokenKind, TokenType};
+use crate::engine::machine::Machine;
+use crate::tokens::tokens;
+
+const INDENT: &str = "  ";
+
+/// A word of the formatted output: a token or a comment.
+struct Item<'a> {
+    line: usize,
+    col: usize,
+    text: Cow<'a, str>,
+    kind: Option<TokenKind>,
+}
+
+/// Formats `source` canonically: words on a line are separated by one space, lines are indented
+/// by how deeply their first word is nested in `if`, `while`, `proc` and `try` blocks, and runs of
+/// blank lines become a single one. Line breaks and comments are kept where they are.
+pub fn format(args: &Args, types: &[TokenType], source: &str, file: &str) -> Result<String> {
+    let (tokens, comments) = lex_with_comments(source, types.to_vec(), file.to_string())?;
+
+    // Linking finds unmatched blocks, which could not be indented
+    let mut machine = Machine::new(args.clone());
+    machine.register_tokens(types.to_vec());
+    machine.link_tokens(tokens.clone())?;
+
+    let mut items = tokens
+        .iter()
+        .map(|token| Item {
+            line: token.line,
+            col: token.col,
+            text: match types[token.type_].type_ {
+                TokenKind::StackEffect => Cow::Owned(format!("( {} )", token.data)),
+                _ => Cow::Borrowed(&token.word),
+            },
+            kind: Some(types[token.type_].type_.clone()),
+        })
+        .chain(comments.iter().map(|comment| Item {
+            line: comment.line,
+            col: comment.col,
+            text: Cow::Borrowed(&comment.text),
+            kind: None,
+        }))
+        .collect::<Vec<Item>>();
+    items.sort_by_key(|item| (item.line, item.col));
+
+    let mut output = String::new();
+    let mut depth: usize = 0;
+    // Source line of the last item
+    let mut last_line = None;
+    for item in &items {
+        match last_line {
+            Some(last) if item.line == last => output.push(' '),
+            Some(last) => {
+                output.push('\n');
+                if item.line > last + 1 {
+                    output.push('\n');
+                }
+            }
+            None => {}
+        }
+        if last_line != Some(item.line) {
+            // Closers and `do`/`catch` line up with the word that opened their block
+            let dedent = item.kind.as_ref().is_some_and(|kind| {
+                kind.closes() || matches!(kind, TokenKind::Do | TokenKind::Catch)
+            });
+            let level = if dedent {
+                depth.saturating_sub(1)
+            } else {
+                depth
+            };
+            output.push_str(&INDENT.repeat(level));
+        }
+        output.push_str(&item.text);
+        match &item.kind {
+            Some(kind) if kind.opens() => depth += 1,
+            Some(kind) if kind.closes() => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        last_line = Some(item.line);
+    }
+    if !output.is_empty() {
+        output.push('\n');
+    }
+    Ok(output)
+}
+
+/// Formats the `.seal` files in `paths` in place. With `check` the files are left alone and
+/// the ones that are not formatted are listed instead. Returns whether all files were formatted.
+pub fn run(args: &Args, paths: &[String], check: bool) -> Result<bool> {
+    let mut files = Vec::new();
+    for path in paths {
+        collect_files(Path::new(path), &mut files)?;
+    }
+    info!("Found {} files to format", files.len());
+
+    let types = tokens();
+    let mut unformatted = Vec::<PathBuf>::new();
+    for file in &files {
+        let source = std::fs::read_to_string(file)
+            .context(format!("Error reading file: {}", file.display()))?;
+        let formatted = format(args, &types, &source, &file.to_string_lossy())
+            .context(format!("Error formatting file: {}", file.display()))?;
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{} {}", "Not formatted:".red().bold(), file.display());
+        } else {
+            std::fs::write(file, &formatted)
+                .context(format!("Error writing file: {}", file.display()))?;
+            println!("Formatted {}", file.display());
+        }
+        unformatted.push(file.clone());
+    }
+    Ok(!check || unformatted.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fmt(source: &str) -> String {
+        format(&Args::default(), &tokens(), source, "test.seal").unwrap()
+    }
+
+    #[test]
+    fn indents_blocks() {
+        assert_eq!(
+            fmt(
+                "(sq) proc\n: *\n      ret\n(0) while : (3) > do\n(1) +\n(1) if\ntry\n{sq}\n   \
+                 catch\nclr\nendtry\nendif\nend\n"
+            ),
+            "(sq) proc\n  : *\nret\n(0) while : (3) > do\n  (1) +\n  (1) if\n    try\n      \
+             {sq}\n    catch\n      clr\n    endtry\n  endif\nend\n"
+        );
+        assert_eq!(
+            fmt("(0)\nwhile : (3) >\ndo (1) + end"),
+            "(0)\nwhile : (3) >\ndo (1) + end\n"
+        );
+    }
+
+    #[test]
+    fn keeps_comments_and_blank_lines() {
+        let source = "\n\n# Counts up\n(1)   (2)    + # sum\n\n\n\n(\"a   b\") .   nl\n\n";
+        let formatted = fmt(source);
+        assert_eq!(
+            formatted,
+            "# Counts up\n(1) (2) + # sum\n\n(\"a   b\") . nl\n"
+        );
+        assert_eq!(fmt(&formatted), formatted);
+        assert_eq!(fmt(""), "");
+        assert_eq!(
+            fmt("(f) proc (  a b--c  -- d  )\nret\n"),
+            "(f) proc ( a b--c -- d )\nret\n"
+        );
+    }
+
+    #[test]
+    fn rejects_unmatched_blocks() {
+        let args = Args::default();
+        assert!(format(&args, &tokens(), "(1) if (2)\n", "test.seal").is_err());
+        assert!(format(&args, &tokens(), "(1) ~~~\n", "test.seal").is_err());
+    }
+
+    #[test]
+    fn examples_are_formatted() {
+        let mut files = Vec::new();
+        collect_files(
+            &Path::new(env!("CARGO_MANIFEST_DIR")).join("examples"),
+            &mut files,
+        )
+        .unwrap();
+        for file in files {
+            let source = std::fs::read_to_string(&file).unwrap();
+            assert_eq!(fmt(&source), source, "{} is not formatted", file.display());
+        }
+    }
+}
@@ -0,0 +1,684 @@
+use anyhow::Result;
+use log::info;
+use regex::Regex;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{BufReader, Write};
+
+use super::protocol::{read_message, write_message};
+use crate::args::Args;
+use crate::engine::lexer::error::SourceError;
+use crate::engine::lexer::token::{Token, TokenKind, TokenType};
+use crate::engine::machine::Machine;
+use crate::tokens::tokens;
+
+/// `DiagnosticSeverity` values
+const ERROR: i64 = 1;
+const WARNING: i64 = 2;
+
+/// `CompletionItemKind` values
+const FUNCTION: i64 = 3;
+const VARIABLE: i64 = 6;
+const KEYWORD: i64 = 14;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SymbolKind {
+    /// Procs share their names with the marks `proc` registers
+    Proc,
+    Local,
+    Global,
+}
+
+/// A proc or variable name used by a token.
+#[derive(Debug, Clone, PartialEq)]
+struct Occurrence {
+    kind: SymbolKind,
+    name: String,
+    /// Whether this defines the name: `(name) proc`, `>(name)` or `>>(name)`
+    definition: bool,
+    /// Index of the token holding the name
+    token: usize,
+}
+
+/// What the `character` of a position counts, agreed on in `initialize`. Columns in tokens count
+/// chars, which is UTF-32.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Encoding {
+    /// The protocol's default
+    Utf16,
+    Utf32,
+}
+
+impl Encoding {
+    fn units(self, c: char) -> usize {
+        match self {
+            Encoding::Utf16 => c.len_utf16(),
+            Encoding::Utf32 => 1,
+        }
+    }
+}
+
+/// A source file as the editor has it, lexed and indexed.
+struct Document {
+    text: String,
+    encoding: Encoding,
+    tokens: Vec<Token>,
+    occurrences: Vec<Occurrence>,
+    diagnostics: Vec<Value>,
+}
+
+/// Editor position of the one-based `line` and `col` in `text`.
+fn position(text: &str, encoding: Encoding, line: usize, col: usize) -> Value {
+    let chars = col.saturating_sub(1);
+    let prefix = text
+        .lines()
+        .nth(line.saturating_sub(1))
+        .unwrap_or("")
+        .chars()
+        .take(chars);
+    let (count, units) = prefix.fold((0, 0), |(count, units), c| {
+        (count + 1, units + encoding.units(c))
+    });
+    // Past the end of the line, as with words spanning lines, every column is one unit
+    json!({"line": line.saturating_sub(1), "character": units + chars - count})
+}
+
+/// Zero-based column in chars of the zero-based editor position `line` and `character`.
+fn column(text: &str, encoding: Encoding, line: usize, character: usize) -> usize {
+    let mut units = 0;
+    let mut col = 0;
+    for c in text.lines().nth(line).unwrap_or("").chars() {
+        if units >= character {
+            return col;
+        }
+        units += encoding.units(c);
+        col += 1;
+    }
+    col + character.saturating_sub(units)
+}
+
+fn token_range(text: &str, encoding: Encoding, token: &Token) -> Value {
+    let end = token.col + token.word.chars().count();
+    json!({
+        "start": position(text, encoding, token.line, token.col),
+        "end": position(text, encoding, token.line, end),
+    })
+}
+
+/// Range of the word starting at `line` and `col` in `text`.
+fn word_range(text: &str, encoding: Encoding, line: usize, col: usize) -> Value {
+    let len = text
+        .lines()
+        .nth(line.saturating_sub(1))
+        .map(|line| {
+            line.chars()
+                .skip(col.saturating_sub(1))
+                .take_while(|c| !c.is_whitespace())
+                .count()
+        })
+        .unwrap_or(0);
+    json!({
+        "start": position(text, encoding, line, col),
+        "end": position(text, encoding, line, col + len),
+    })
+}
+
+/// Replaces the word starting at `line` and `col` with spaces.
+fn blank_word(text: &str, line: usize, col: usize) -> String {
+    text.split('\n')
+        .enumerate()
+        .map(|(i, text)| {
+            if i + 1 != line {
+                return text.to_string();
+            }
+            let mut blanking = false;
+            text.chars()
+                .enumerate()
+                .map(|(j, c)| {
+                    if j + 1 == col {
+                        blanking = true;
+                    }
+                    if blanking && c.is_whitespace() {
+                        blanking = false;
+                    }
+                    if blanking {
+                        ' '
+                    } else {
+                        c
+                    }
+                })
+                .collect()
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn diagnostic(error: &anyhow::Error, text: &str, encoding: Encoding, severity: i64) -> Value {
+    let range = match error.downcast_ref::<SourceError>() {
+        Some(error) => word_range(text, encoding, error.line, error.col),
+        None => word_range(text, encoding, 1, 1),
+    };
+    json!({
+        "range": range,
+        "severity": severity,
+        "source": "pinniped",
+        "message": error.to_string(),
+    })
+}
+
+/// Finds every proc and variable name the tokens define or use.
+fn occurrences(types: &[TokenType], tokens: &[Token]) -> Vec<Occurrence> {
+    let mut result = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        // `proc` and `jmp` take their name from the literal before them
+        let literal = i
+            .checked_sub(1)
+            .map(|previous| &tokens[previous])
+            .filter(|previous| types[previous.type_].name == "push" && previous.data.is_string());
+        let (kind, definition, token) = match types[token.type_].name.as_str() {
+            "proc" | "jmp" => match literal {
+                Some(_) => (SymbolKind::Proc, types[token.type_].name == "proc", i - 1),
+                None => continue,
+            },
+            "call" => (SymbolKind::Proc, false, i),
+            "store" => (SymbolKind::Local, true, i),
+            "load" | "remove" => (SymbolKind::Local, false, i),
+            "store-global" => (SymbolKind::Global, true, i),
+            "load-global" | "remove-global" => (SymbolKind::Global, false, i),
+            _ => continue,
+        };
+        result.push(Occurrence {
+            kind,
+            name: tokens[token].data.to_string(),
+            definition,
+            token,
+        });
+    }
+    result
+}
+
+impl Document {
+    fn analyze(
+        args: &Args,
+        types: &[TokenType],
+        typed: bool,
+        encoding: Encoding,
+        uri: &str,
+        text: &str,
+    ) -> Self {
+        let mut machine = Machine::new(Args {
+            command: None,
+            ..args.clone()
+        });
+        machine.register_tokens(types.to_vec());
+        let mut diagnostics = Vec::new();
+        // Unknown words are blanked out and lexing retried, so one typo does not hide the rest
+        let mut source = text.to_string();
+        let tokens = loop {
+            match machine.lex_source(&source, uri) {
+                Ok(tokens) => break tokens,
+                Err(e) => {
+                    diagnostics.push(diagnostic(&e, text, encoding, ERROR));
+                    let blanked = e
+                        .downcast_ref::<SourceError>()
+                        .map(|error| blank_word(&source, error.line, error.col));
+                    match blanked {
+                        Some(blanked) if blanked != source => source = blanked,
+                        _ => break Vec::new(),
+                    }
+                }
+            }
+        };
+        match machine.link_tokens(tokens.clone()) {
+            Ok(_) => {
+                for problem in machine.check(typed) {
+                    diagnostics.push(json!({
+                        "range": word_range(text, encoding, problem.line, problem.col),
+                        "severity": WARNING,
+                        "source": "pinniped",
+                        "message": problem.message,
+                    }));
+                }
+            }
+            Err(e) => diagnostics.push(diagnostic(&e, text, encoding, ERROR)),
+        }
+
+        let occurrences = occurrences(types, &tokens);
+        let mut document = Self {
+            text: text.to_string(),
+            encoding,
+            tokens,
+            occurrences,
+            diagnostics,
+        };
+        for occurrence in &document.occurrences {
+            if occurrence.definition || document.definition(occurrence).is_some() {
+                continue;
+            }
+            let message = match occurrence.kind {
+                SymbolKind::Proc => format!("Proc is not defined: {}", occurrence.name),
+                SymbolKind::Local => format!("Variable is never stored: {}", occurrence.name),
+                SymbolKind::Global => {
+                    format!("Global variable is never stored: {}", occurrence.name)
+                }
+            };
+            document.diagnostics.push(json!({
+                "range": document.range(occurrence.token),
+                "severity": WARNING,
+                "source": "pinniped",
+                "message": message,
+            }));
+        }
+        document
+    }
+
+    fn range(&self, token: usize) -> Value {
+        token_range(&self.text, self.encoding, &self.tokens[token])
+    }
+
+    /// Index of the token under a zero-based editor position.
+    fn token_at(&self, line: usize, character: usize) -> Option<usize> {
+        let character = column(&self.text, self.encoding, line, character);
+        self.tokens.iter().position(|token| {
+            let start = token.col - 1;
+            token.line == line + 1
+                && start <= character
+                && character <= start + token.word.chars().count()
+        })
+    }
+
+    fn occurrence_at(&self, line: usize, character: usize) -> Option<&Occurrence> {
+        let token = self.token_at(line, character)?;
+        self.occurrences
+            .iter()
+            .find(|occurrence| occurrence.token == token)
+    }
+
+    fn definition(&self, of: &Occurrence) -> Option<&Occurrence> {
+        self.occurrences.iter().find(|occurrence| {
+            occurrence.definition && occurrence.kind == of.kind && occurrence.name == of.name
+        })
+    }
+
+    fn references(&self, of: &Occurrence, include_definition: bool) -> Vec<&Occurrence> {
+        self.occurrences
+            .iter()
+            .filter(|occurrence| occurrence.kind == of.kind && occurrence.name == of.name)
+            .filter(|occurrence| include_definition || !occurrence.definition)
+            .collect()
+    }
+}
+
+struct Server {
+    args: Args,
+    types: Vec<TokenType>,
+    /// Whether type problems are reported, see [`Machine::check`]
+    typed: bool,
+    encoding: Encoding,
+    documents: HashMap<String, Document>,
+    writer: Box<dyn Write>,
+}
+
+impl Server {
+    fn send(&mut self, message: Value) -> Result<()> {
+        write_message(&mut self.writer, &message)
+    }
+
+    fn open(&mut self, uri: &str, text: &str) -> Result<()> {
+        let document = Document::analyze(
+            &self.args,
+            &self.types,
+            self.typed,
+            self.encoding,
+            uri,
+            text,
+        );
+        let diagnostics = document.diagnostics.clone();
+        self.documents.insert(uri.to_string(), document);
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": {"uri": uri, "diagnostics": diagnostics},
+        }))
+    }
+
+    /// The document and zero-based position a request is about.
+    fn target(&self, params: &Value) -> Result<(&Document, usize, usize)> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+        let document = match self.documents.get(uri) {
+            Some(document) => document,
+            None => return Err(anyhow::anyhow!("Document is not open: {}", uri)),
+        };
+        let line = params["position"]["line"].as_u64().unwrap_or(0) as usize;
+        let character = params["position"]["character"].as_u64().unwrap_or(0) as usize;
+        Ok((document, line, character))
+    }
+
+    fn location(uri: &str, document: &Document, occurrence: &Occurrence) -> Value {
+        json!({"uri": uri, "range": document.range(occurrence.token)})
+    }
+
+    fn hover(&self, params: &Value) -> Result<Value> {
+        let (document, line, character) = self.target(params)?;
+        let token = match document.token_at(line, character) {
+            Some(token) => token,
+            None => return Ok(Value::Null),
+        };
+        let token_type = &self.types[document.tokens[token].type_];
+        let mut contents = format!("`{}`\n\n{}", token_type.name, token_type.doc);
+        let definition = document
+            .occurrences
+            .iter()
+            .find(|occurrence| occurrence.token == token)
+            .and_then(|occurrence| Some((occurrence, document.definition(occurrence)?)));
+        if let Some((occurrence, definition)) = definition {
+            let what = match occurrence.kind {
+                SymbolKind::Proc => "Proc",
+                SymbolKind::Local => "Variable",
+                SymbolKind::Global => "Global variable",
+            };
+            contents.push_str(&format!(
+                "\n\n{} `{}` defined on line {}",
+                what, occurrence.name, document.tokens[definition.token].line
+            ));
+            // `(name) proc ( a -- b )`
+            let effect = document
+                .tokens
+                .get(definition.token + 2)
+                .filter(|token| self.types[token.type_].type_ == TokenKind::StackEffect);
+            if let (SymbolKind::Proc, Some(effect)) = (occurrence.kind, effect) {
+                contents.push_str(&format!(" as `( {} )`", effect.data));
+            }
+        }
+        Ok(json!({
+            "contents": {"kind": "markdown", "value": contents},
+            "range": document.range(token),
+        }))
+    }
+
+    fn definition(&self, params: &Value) -> Result<Value> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+        let (document, line, character) = self.target(params)?;
+        Ok(document
+            .occurrence_at(line, character)
+            .and_then(|occurrence| document.definition(occurrence))
+            .map_or(Value::Null, |definition| {
+                Self::location(uri, document, definition)
+            }))
+    }
+
+    fn references(&self, params: &Value) -> Result<Value> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+        let (document, line, character) = self.target(params)?;
+        let include_definition = params["context"]["includeDeclaration"]
+            .as_bool()
+            .unwrap_or(true);
+        let references = match document.occurrence_at(line, character) {
+            Some(occurrence) => document
+                .references(occurrence, include_definition)
+                .into_iter()
+                .map(|reference| Self::location(uri, document, reference))
+                .collect(),
+            None => Vec::new(),
+        };
+        Ok(Value::Array(references))
+    }
+
+    fn completion(&self, params: &Value) -> Result<Value> {
+        // Only words that can be typed as their name, `push` is written as `(value)`
+        let mut items = self
+            .types
+            .iter()
+            .filter(|token_type| {
+                Regex::new(&format!("^(?:{})$", token_type.regex))
+                    .is_ok_and(|regex| regex.is_match(&token_type.name))
+            })
+            .map(|token_type| {
+                json!({
+                    "label": token_type.name,
+                    "kind": KEYWORD,
+                    "detail": token_type.doc,
+                })
+            })
+            .collect::<Vec<Value>>();
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+        if let Some(document) = self.documents.get(uri) {
+            for occurrence in document.occurrences.iter().filter(|o| o.definition) {
+                let (label, kind) = match occurrence.kind {
+                    SymbolKind::Proc => (format!("{{{}}}", occurrence.name), FUNCTION),
+                    SymbolKind::Local => (format!("<({})", occurrence.name), VARIABLE),
+                    SymbolKind::Global => (format!("<<({})", occurrence.name), VARIABLE),
+                };
+                if !items.iter().any(|item| item["label"] == label) {
+                    items.push(json!({"label": label, "kind": kind}));
+                }
+            }
+        }
+        Ok(Value::Array(items))
+    }
+
+    /// Answers one message. Returns false once the client asked the server to exit.
+    fn handle(&mut self, message: &Value) -> Result<bool> {
+        let method = message["method"].as_str().unwrap_or("");
+        let params = &message["params"];
+        info!("LSP message: {}", method);
+        let result = match method {
+            "initialize" => {
+                // Columns are counted in chars, which clients offering UTF-32 can take as they are
+                let offered = params["capabilities"]["general"]["positionEncodings"]
+                    .as_array()
+                    .is_some_and(|encodings| encodings.iter().any(|e| e == "utf-32"));
+                let (encoding, name) = if offered {
+                    (Encoding::Utf32, "utf-32")
+                } else {
+                    (Encoding::Utf16, "utf-16")
+                };
+                self.encoding = encoding;
+                Ok(json!({
+                    "capabilities": {
+                        "positionEncoding": name,
+                        "textDocumentSync": 1,
+                        "hoverProvider": true,
+                        "definitionProvider": true,
+                        "referencesProvider": true,
+                        "completionProvider": {},
+                    },
+                    "serverInfo": {"name": "pinniped", "version": env!("CARGO_PKG_VERSION")},
+                }))
+            }
+            "shutdown" => Ok(Value::Null),
+            "exit" => return Ok(false),
+            "textDocument/didOpen" => {
+                let document = &params["textDocument"];
+                self.open(
+                    document["uri"].as_str().unwrap_or(""),
+                    document["text"].as_str().unwrap_or(""),
+                )?;
+                return Ok(true);
+            }
+            "textDocument/didChange" => {
+                // Full sync, the last change has the whole text
+                let text = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str())
+                    .unwrap_or("");
+                self.open(params["textDocument"]["uri"].as_str().unwrap_or(""), text)?;
+                return Ok(true);
+            }
+            "textDocument/didClose" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+                self.documents.remove(uri);
+                self.send(json!({
+                    "jsonrpc": "2.0",
+                    "method": "textDocument/publishDiagnostics",
+                    "params": {"uri": uri, "diagnostics": []},
+                }))?;
+                return Ok(true);
+            }
+            "textDocument/hover" => self.hover(params),
+            "textDocument/definition" => self.definition(params),
+            "textDocument/references" => self.references(params),
+            "textDocument/completion" => self.completion(params),
+            _ => Err(anyhow::anyhow!("Unsupported method: {}", method)),
+        };
+
+        // Notifications get no response
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+            None => return Ok(true),
+        };
+        let response = match result {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            Err(e) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": {"code": -32601, "message": e.to_string()},
+            }),
+        };
+        self.send(response)?;
+        Ok(true)
+    }
+}
+
+/// Serves the Language Server Protocol on stdin and stdout until the client exits, reporting type
+/// problems with `typed`.
+pub fn run(args: &Args, typed: bool) -> Result<()> {
+    let mut reader = BufReader::new(std::io::stdin());
+    let mut server = Server {
+        args: args.clone(),
+        types: tokens(),
+        typed,
+        encoding: Encoding::Utf16,
+        documents: HashMap::new(),
+        writer: Box::new(std::io::stdout()),
+    };
+    while let Some(message) = read_message(&mut reader)? {
+        if !server.handle(&message)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "(greet) proc\n  (\"hi\") >(word)\n  <(word) . nl\nret\n{greet} {greet}\n";
+
+    fn analyze(text: &str) -> Document {
+        analyze_as(Encoding::Utf16, text)
+    }
+
+    fn analyze_as(encoding: Encoding, text: &str) -> Document {
+        Document::analyze(
+            &Args::default(),
+            &tokens(),
+            true,
+            encoding,
+            "test.seal",
+            text,
+        )
+    }
+
+    #[test]
+    fn definitions_and_references() {
+        let document = analyze(SOURCE);
+        assert!(document.diagnostics.is_empty());
+
+        // The second call on the last line
+        let call = document.occurrence_at(4, 9).unwrap();
+        assert_eq!((call.kind, call.name.as_str()), (SymbolKind::Proc, "greet"));
+        let definition = document.definition(call).unwrap();
+        assert_eq!(document.tokens[definition.token].line, 1);
+        assert_eq!(document.references(call, true).len(), 3);
+        assert_eq!(document.references(call, false).len(), 2);
+
+        let load = document.occurrence_at(2, 4).unwrap();
+        assert_eq!((load.kind, load.name.as_str()), (SymbolKind::Local, "word"));
+        let store = document.definition(load).unwrap();
+        assert_eq!((store.token, document.tokens[store.token].col), (3, 10));
+    }
+
+    #[test]
+    fn diagnostics() {
+        let document = analyze("(1) (2) +\n  nope . nah\n");
+        assert_eq!(document.diagnostics.len(), 2);
+        assert_eq!(
+            document.diagnostics[0]["range"],
+            json!({"start": {"line": 1, "character": 2}, "end": {"line": 1, "character": 6}})
+        );
+        assert_eq!(
+            document.diagnostics[1]["range"]["start"],
+            json!({"line": 1, "character": 9})
+        );
+        // The words around the unknown ones are still lexed
+        assert_eq!(document.tokens.len(), 4);
+
+        let document = analyze("(1) if\n  {missing} <(x)\n");
+        let messages = document
+            .diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic["message"].as_str().unwrap())
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            messages,
+            [
+                "No matching endif token found for token at 1:5",
+                "Proc is not defined: missing",
+                "Variable is never stored: x",
+            ]
+        );
+        assert_eq!(document.diagnostics[1]["severity"], WARNING);
+
+        // Problems found by the checker are warnings on the word they are about
+        let document = analyze("(\"a\") (1) + .\n");
+        assert_eq!(document.diagnostics.len(), 1);
+        assert_eq!(
+            document.diagnostics[0]["message"],
+            "`+` needs numbers but got string"
+        );
+        assert_eq!(
+            document.diagnostics[0]["range"]["start"],
+            json!({"line": 0, "character": 10})
+        );
+        assert_eq!(document.diagnostics[0]["severity"], WARNING);
+    }
+
+    #[test]
+    fn position_encodings() {
+        // The seal takes two UTF-16 units but is one char
+        let text = "(\"🦭 hi\") >(word)\n<(word) . <(nope)\n";
+        let document = analyze(text);
+        let store = document.occurrence_at(0, 10).unwrap();
+        assert_eq!(store.name, "word");
+        assert_eq!(
+            document.range(store.token),
+            json!({"start": {"line": 0, "character": 10}, "end": {"line": 0, "character": 17}})
+        );
+        assert_eq!(
+            document.range(0),
+            json!({"start": {"line": 0, "character": 0}, "end": {"line": 0, "character": 9}})
+        );
+        assert_eq!(
+            document.diagnostics[0]["range"]["start"],
+            json!({"line": 1, "character": 10})
+        );
+
+        let document = analyze_as(Encoding::Utf32, text);
+        let store = document.occurrence_at(0, 9).unwrap();
+        assert_eq!(store.name, "word");
+        assert_eq!(
+            document.range(store.token),
+            json!({"start": {"line": 0, "character": 9}, "end": {"line": 0, "character": 16}})
+        );
+    }
+
+    #[test]
+    fn every_word_has_docs() {
+        for token_type in tokens() {
+            assert!(!token_type.doc.is_empty(), "{} has no doc", token_type.name);
+        }
+    }
+}
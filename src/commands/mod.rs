@@ -0,0 +1,10 @@
+pub mod bench;
+pub mod build;
+pub mod check;
+pub mod dap;
+pub mod emit_c;
+pub mod fmt;
+pub mod lsp;
+pub mod protocol;
+pub mod repl;
+pub mod test;
@@ -0,0 +1,81 @@
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::io::{BufRead, Write};
+
+/// Reads one JSON message framed with a `Content-Length` header, the transport shared by the
+/// debug adapter and the language server. Returns `None` at the end of input.
+pub fn read_message(reader: &mut impl BufRead) -> Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader
+            .read_line(&mut header)
+            .context("Error reading message header")?
+            == 0
+        {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = Some(
+                    value
+                        .trim()
+                        .parse::<usize>()
+                        .context("Invalid Content-Length")?,
+                );
+            }
+        }
+    }
+    let length = match length {
+        Some(length) => length,
+        None => return Err(anyhow::anyhow!("Message without Content-Length")),
+    };
+    let mut body = vec![0; length];
+    reader
+        .read_exact(&mut body)
+        .context("Error reading message body")?;
+    Ok(Some(
+        serde_json::from_slice(&body).context("Invalid JSON message")?,
+    ))
+}
+
+/// Writes one JSON message with its `Content-Length` header.
+pub fn write_message(writer: &mut impl Write, message: &Value) -> Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::io::Cursor;
+
+    #[test]
+    fn round_trip() {
+        let mut buffer = Vec::new();
+        write_message(&mut buffer, &json!({"seq": 1, "text": "é"})).unwrap();
+        write_message(&mut buffer, &json!([])).unwrap();
+        let mut reader = Cursor::new(buffer);
+        assert_eq!(
+            read_message(&mut reader).unwrap(),
+            Some(json!({"seq": 1, "text": "é"}))
+        );
+        assert_eq!(read_message(&mut reader).unwrap(), Some(json!([])));
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn headers() {
+        let mut reader =
+            Cursor::new("Content-Type: application/json\r\ncontent-length: 2\r\n\r\n{}".as_bytes());
+        assert_eq!(read_message(&mut reader).unwrap(), Some(json!({})));
+        assert!(read_message(&mut Cursor::new("X: 1\r\n\r\n{}".as_bytes())).is_err());
+    }
+}
@@ -0,0 +1,230 @@
+use anyhow::Result;
+use colored::Colorize;
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::args::Args;
+use crate::engine::input::Input;
+use crate::engine::machine::Machine;
+use crate::engine::runtime::Exit;
+use crate::tokens::tokens;
+
+const HELP: &str = "\
+:stack       Show the stack and the return stack
+:vars        Show the local and global variables
+:load <file> Run a file in the current machine
+:reset       Start over with a fresh machine
+:history     Show the previous inputs, also from earlier sessions
+!!           Run the previous input again
+!<n>         Run input <n> from :history again
+:help        Show this message
+:quit        Leave the REPL";
+
+fn new_machine(args: &Args) -> Machine {
+    let mut machine = Machine::new(args.clone());
+    machine.register_tokens(tokens());
+    machine
+}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(|home| PathBuf::from(home).join(".pinniped_history"))
+}
+
+/// How many inputs of earlier sessions are loaded at startup.
+const HISTORY_LIMIT: usize = 1000;
+
+fn load_history() -> Vec<String> {
+    let Some(contents) = history_path().and_then(|path| std::fs::read_to_string(path).ok()) else {
+        return Vec::new();
+    };
+    let entries = contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.to_string())
+        .collect::<Vec<String>>();
+    entries[entries.len().saturating_sub(HISTORY_LIMIT)..].to_vec()
+}
+
+fn save_history(entry: &str) {
+    if let Some(path) = history_path() {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path);
+        if let Ok(mut file) = file {
+            writeln!(file, "{}", entry.replace('\n', " ")).ok();
+        }
+    }
+}
+
+fn print_stack(machine: &Machine) {
+    println!("{} [{}]", "Stack:".blue().bold(), machine.stack());
+}
+
+fn run_source(machine: &mut Machine, source: &str, file: &str) {
+    let result = machine
+        .lex_source(source, file)
+        .and_then(|tokens| machine.run_tokens(tokens));
+    if let Err(e) = result {
+        if let Some(Exit(code)) = e.downcast_ref::<Exit>() {
+            std::process::exit(*code);
+        }
+        println!("{} {}", "Error:".red().bold(), e);
+    }
+}
+
+/// The input `!!` or `!<n>` recalls from the history, `None` if it is not a recall.
+fn recall(history: &[String], entry: &str) -> Option<Result<String, String>> {
+    let index = match entry.strip_prefix('!')? {
+        "!" => history.len(),
+        n if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) => n.parse().unwrap_or(0),
+        _ => return None,
+    };
+    match index.checked_sub(1).and_then(|i| history.get(i)) {
+        Some(entry) => Some(Ok(entry.clone())),
+        None => Some(Err(format!("No input {} in the history", entry))),
+    }
+}
+
+/// Handles a `:command`. Returns false when the REPL should stop.
+fn meta_command(
+    args: &Args,
+    machine: &mut Machine,
+    history: &[String],
+    command: &str,
+) -> Result<bool> {
+    let (name, argument) = match command.split_once(' ') {
+        Some((name, argument)) => (name, argument.trim()),
+        None => (command, ""),
+    };
+    match name {
+        ":quit" | ":q" => return Ok(false),
+        ":help" => println!("{}", HELP),
+        ":reset" => {
+            // Input may still hold buffered lines, so it outlives the machine
+            let input = std::mem::replace(machine.input(), Input::from_reader(std::io::empty()));
+            *machine = new_machine(args);
+            *machine.input() = input;
+            println!("Machine reset");
+        }
+        ":stack" => {
+            print_stack(machine);
+            println!(
+                "{} [{}]",
+                "Ret stack:".blue().bold(),
+                machine.return_stack()
+            );
+        }
+        ":vars" => print!("{}", machine.variables()),
+        ":history" => {
+            for (i, entry) in history.iter().enumerate() {
+                println!("{:>4}  {}", i + 1, entry);
+            }
+        }
+        ":load" if !argument.is_empty() => match std::fs::read_to_string(argument) {
+            Ok(source) => {
+                run_source(machine, &source, argument);
+                println!();
+                print_stack(machine);
+            }
+            Err(e) => println!(
+                "{} Error reading {}: {}",
+                "Error:".red().bold(),
+                argument,
+                e
+            ),
+        },
+        ":load" => println!("Usage: :load <file>"),
+        _ => println!("Unknown command {}, try :help", name),
+    }
+    Ok(true)
+}
+
+/// Reads lines until the blocks they open are all closed. Returns `None` at the end of input.
+fn read_entry(machine: &mut Machine) -> Result<Option<String>> {
+    let mut entry = String::new();
+    loop {
+        print!("{} ", if entry.is_empty() { ">>" } else { ".." });
+        std::io::stdout().flush()?;
+        let line = match machine.input().read_line()? {
+            Some(line) => line,
+            None if entry.is_empty() => return Ok(None),
+            None => return Ok(Some(entry)),
+        };
+        if !entry.is_empty() {
+            entry.push('\n');
+        }
+        entry.push_str(&line);
+        if entry.trim_start().starts_with(':') {
+            return Ok(Some(entry));
+        }
+        // Lexing errors are reported once the entry is run
+        match machine
+            .lex_source(&entry, "repl")
+            .map(|tokens| machine.open_blocks(&tokens))
+        {
+            Ok(depth) if depth > 0 => continue,
+            _ => return Ok(Some(entry)),
+        }
+    }
+}
+
+pub fn run(args: &Args) -> Result<()> {
+    println!(
+        "pinniped {} REPL, type :help for commands",
+        env!("CARGO_PKG_VERSION")
+    );
+    let mut machine = new_machine(args);
+    let mut history = load_history();
+
+    while let Some(mut entry) = read_entry(&mut machine)? {
+        match recall(&history, entry.trim()) {
+            Some(Ok(recalled)) => {
+                println!("{}", recalled);
+                entry = recalled;
+            }
+            Some(Err(e)) => {
+                println!("{} {}", "Error:".red().bold(), e);
+                continue;
+            }
+            None => {}
+        }
+        let trimmed = entry.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        history.push(trimmed.to_string());
+        save_history(trimmed);
+        if trimmed.starts_with(':') {
+            if !meta_command(args, &mut machine, &history, trimmed)? {
+                break;
+            }
+            continue;
+        }
+        run_source(&mut machine, &entry, "repl");
+        // Output without a trailing newline would share a line with the stack
+        println!();
+        print_stack(&machine);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::recall;
+
+    #[test]
+    fn recalls_inputs() {
+        let history = vec!["(1) .".to_string(), ":stack".to_string()];
+        assert_eq!(recall(&history, "!!"), Some(Ok(":stack".to_string())));
+        assert_eq!(recall(&history, "!1"), Some(Ok("(1) .".to_string())));
+        assert!(matches!(recall(&history, "!3"), Some(Err(_))));
+        assert!(matches!(recall(&history, "!0"), Some(Err(_))));
+        assert!(matches!(recall(&[], "!!"), Some(Err(_))));
+        // `!` is also a word
+        assert_eq!(recall(&history, "! ."), None);
+        assert_eq!(recall(&history, "(1) ."), None);
+    }
+}
@@ -0,0 +1,107 @@
+use anyhow::{Context, Result};
+use colored::Colorize;
+use log::info;
+use std::path::{Path, PathBuf};
+
+use crate::args::Args;
+use crate::engine::machine::Machine;
+use crate::tokens::tokens;
+
+/// Collects the `.seal` files in `path`, walking directories recursively.
+pub fn collect_files(path: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    if !path.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+    let mut entries = std::fs::read_dir(path)
+        .context(format!("Error reading directory: {}", path.display()))?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<PathBuf>>>()?;
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() || entry.extension().is_some_and(|e| e == "seal") {
+            collect_files(&entry, files)?;
+        }
+    }
+    Ok(())
+}
+
+/// Creates a machine for `file` that is lexed, linked and has its procs registered.
+pub fn load(args: &Args, file: &Path) -> Result<Machine> {
+    let mut machine = Machine::new(Args {
+        command: None,
+        file: file.to_string_lossy().to_string(),
+        args: Vec::new(),
+        ..args.clone()
+    });
+    machine.register_tokens(tokens());
+    machine.preprocess()?;
+    machine.lex()?;
+    machine.after_lex()?;
+    machine.define_procs()?;
+    Ok(machine)
+}
+
+/// Runs one test proc in a fresh machine.
+fn run_test(args: &Args, file: &Path, name: &str) -> Result<()> {
+    let mut machine = load(args, file)?;
+    machine.call(name)
+}
+
+/// Runs every `test_*` proc in `paths`. Returns whether all of them passed.
+pub fn run(args: &Args, paths: &[String]) -> Result<bool> {
+    let mut files = Vec::new();
+    for path in paths {
+        collect_files(Path::new(path), &mut files)?;
+    }
+    info!("Found {} test files", files.len());
+
+    let mut passed = 0;
+    let mut failures = Vec::new();
+    for file in &files {
+        let tests = match load(args, file).and_then(|machine| machine.proc_names()) {
+            Ok(procs) => procs
+                .into_iter()
+                .map(|(name, _)| name)
+                .filter(|name| name.starts_with("test_"))
+                .collect::<Vec<String>>(),
+            Err(e) => {
+                println!("load {} ... {}", file.display(), "FAILED".red().bold());
+                failures.push((file.display().to_string(), e));
+                continue;
+            }
+        };
+        for name in tests {
+            let id = format!("{}::{}", file.display(), name);
+            match run_test(args, file, &name) {
+                Ok(_) => {
+                    println!("test {} ... {}", id, "ok".green());
+                    passed += 1;
+                }
+                Err(e) => {
+                    println!("test {} ... {}", id, "FAILED".red().bold());
+                    failures.push((id, e));
+                }
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        println!("\nfailures:");
+        for (id, e) in &failures {
+            println!("    {}: {}", id.bold(), e);
+        }
+    }
+    let result = if failures.is_empty() {
+        "ok".green()
+    } else {
+        "FAILED".red().bold()
+    };
+    println!(
+        "\ntest result: {}. {} passed; {} failed",
+        result,
+        passed,
+        failures.len()
+    );
+    Ok(failures.is_empty())
+}
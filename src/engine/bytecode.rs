@@ -0,0 +1,332 @@
+//! Compact on-disk form of a linked program, written by `pinniped build` and run like source.
+//!
+//! Numbers are little-endian and strings are a `u32` length followed by UTF-8 bytes. A file starts
+//! with the magic bytes `SEAL` and a `u16` format version, followed by four sections that each
+//! start with a `u32` count:
+//!
+//! - words: the names of the token types the code uses, so a file does not depend on the order
+//!   tokens are registered in
+//! - constants: the distinct data of the tokens, each a tag byte followed by its value
+//! - code: one instruction per token, a `u16` index into the words and a `u32` index into the
+//!   constants. Block tokens keep the offsets `Machine::after_lex` gave them, so nothing is
+//!   linked again when the file is loaded
+//! - debug info: a table of distinct strings, then the file, line, column, source line and word of
+//!   every instruction, strings as indices into the table
+use anyhow::Result;
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use super::data::Data;
+use super::lexer::token::{Token, TokenType};
+
+pub const MAGIC: &[u8; 4] = b"SEAL";
+/// Bumped whenever the layout changes, files of other versions are rejected
+pub const VERSION: u16 = 1;
+
+const TAG_STRING: u8 = 0;
+const TAG_INT: u8 = 1;
+const TAG_FLOAT: u8 = 2;
+const TAG_BOOL: u8 = 3;
+const TAG_NONE: u8 = 4;
+
+/// Whether `bytes` start like a compiled program.
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Assigns indices to distinct values in the order they are first seen.
+struct Pool<T> {
+    indices: HashMap<T, u32>,
+    values: Vec<T>,
+}
+
+impl<T: Clone + Eq + std::hash::Hash> Pool<T> {
+    fn new() -> Self {
+        Self {
+            indices: HashMap::new(),
+            values: Vec::new(),
+        }
+    }
+
+    fn index<Q>(&mut self, value: &Q) -> u32
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Eq + std::hash::Hash + ToOwned<Owned = T>,
+    {
+        if let Some(&index) = self.indices.get(value) {
+            return index;
+        }
+        let index = self.values.len() as u32;
+        self.indices.insert(value.to_owned(), index);
+        self.values.push(value.to_owned());
+        index
+    }
+}
+
+fn write_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend(value.to_le_bytes());
+}
+
+fn write_string(out: &mut Vec<u8>, value: &str) {
+    write_u32(out, value.len() as u32);
+    out.extend(value.as_bytes());
+}
+
+/// Encodes a constant, `Data` itself cannot be hashed because of its floats.
+fn encode_data(data: &Data) -> Vec<u8> {
+    let mut out = Vec::new();
+    match data {
+        Data::String(s) => {
+            out.push(TAG_STRING);
+            write_string(&mut out, s);
+        }
+        Data::Int(i) => {
+            out.push(TAG_INT);
+            out.extend(i.to_le_bytes());
+        }
+        Data::Float(f) => {
+            out.push(TAG_FLOAT);
+            out.extend(f.to_le_bytes());
+        }
+        Data::Bool(b) => out.extend([TAG_BOOL, *b as u8]),
+        Data::None => out.push(TAG_NONE),
+    }
+    out
+}
+
+/// Encodes linked tokens, see the module documentation for the layout.
+pub fn encode(types: &[TokenType], tokens: &[Token]) -> Vec<u8> {
+    let mut words = Pool::new();
+    let mut constants = Pool::new();
+    let mut strings = Pool::new();
+    let mut code = Vec::new();
+    let mut debug = Vec::new();
+    for token in tokens {
+        let word = words.index(&types[token.type_].name);
+        code.extend((word as u16).to_le_bytes());
+        write_u32(&mut code, constants.index(&encode_data(&token.data)));
+        write_u32(&mut debug, strings.index(&*token.file));
+        write_u32(&mut debug, token.line as u32);
+        write_u32(&mut debug, token.col as u32);
+        write_u32(&mut debug, strings.index(&*token.vis));
+        write_u32(&mut debug, strings.index(token.word.as_str()));
+    }
+
+    let mut out = MAGIC.to_vec();
+    out.extend(VERSION.to_le_bytes());
+    write_u32(&mut out, words.values.len() as u32);
+    for word in &words.values {
+        write_string(&mut out, word);
+    }
+    write_u32(&mut out, constants.values.len() as u32);
+    for constant in &constants.values {
+        out.extend(constant);
+    }
+    write_u32(&mut out, tokens.len() as u32);
+    out.extend(code);
+    write_u32(&mut out, strings.values.len() as u32);
+    for string in &strings.values {
+        write_string(&mut out, string);
+    }
+    out.extend(debug);
+    out
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8]> {
+        if self.bytes.len() - self.pos < len {
+            return Err(anyhow::anyhow!(
+                "Bytecode is truncated at byte {}",
+                self.pos
+            ));
+        }
+        self.pos += len;
+        Ok(&self.bytes[self.pos - len..self.pos])
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = self.u32()? as usize;
+        let pos = self.pos;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|_| anyhow::anyhow!("Bytecode has an invalid string at byte {}", pos))
+    }
+
+    fn data(&mut self) -> Result<Data> {
+        match self.u8()? {
+            TAG_STRING => Ok(Data::String(self.string()?)),
+            TAG_INT => Ok(Data::Int(i64::from_le_bytes(self.take(8)?.try_into()?))),
+            TAG_FLOAT => Ok(Data::Float(f32::from_le_bytes(self.take(4)?.try_into()?))),
+            TAG_BOOL => Ok(Data::Bool(self.u8()? != 0)),
+            TAG_NONE => Ok(Data::None),
+            tag => Err(anyhow::anyhow!(
+                "Bytecode has an unknown constant tag {} at byte {}",
+                tag,
+                self.pos - 1
+            )),
+        }
+    }
+
+    /// Reads a `u32` count followed by that many items.
+    fn list<T>(&mut self, mut item: impl FnMut(&mut Self) -> Result<T>) -> Result<Vec<T>> {
+        let count = self.u32()?;
+        (0..count).map(|_| item(self)).collect()
+    }
+}
+
+/// Looks up what an index read from the file points at.
+fn get<'v, T>(values: &'v [T], index: u32, what: &str) -> Result<&'v T> {
+    values
+        .get(index as usize)
+        .ok_or_else(|| anyhow::anyhow!("Bytecode refers to {} {} that does not exist", what, index))
+}
+
+/// Decodes tokens written by [`encode`], resolving their words against the registered `types`.
+pub fn decode(types: &[TokenType], bytes: &[u8]) -> Result<Vec<Token>> {
+    let mut reader = Reader { bytes, pos: 0 };
+    if reader.take(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+        return Err(anyhow::anyhow!("Not a compiled pinniped program"));
+    }
+    let version = reader.u16()?;
+    if version != VERSION {
+        return Err(anyhow::anyhow!(
+            "Bytecode version {} is not supported, this pinniped reads version {}. Build the \
+             program again",
+            version,
+            VERSION
+        ));
+    }
+
+    let words = reader
+        .list(|reader| reader.string())?
+        .into_iter()
+        .map(|word| {
+            types
+                .iter()
+                .position(|token_type| token_type.name == word)
+                .ok_or_else(|| anyhow::anyhow!("Bytecode uses an unknown word: {}", word))
+        })
+        .collect::<Result<Vec<usize>>>()?;
+    let constants = reader.list(|reader| reader.data())?;
+    let code = reader.list(|reader| Ok((reader.u16()?, reader.u32()?)))?;
+    // File names and source lines are shared by the tokens, like in the file
+    let strings = reader
+        .list(|reader| reader.string())?
+        .into_iter()
+        .map(Rc::from)
+        .collect::<Vec<Rc<str>>>();
+
+    let mut tokens = Vec::with_capacity(code.len());
+    for (word, constant) in code {
+        let file = get(&strings, reader.u32()?, "string")?.clone();
+        let line = reader.u32()? as usize;
+        let col = reader.u32()? as usize;
+        let vis = get(&strings, reader.u32()?, "string")?.clone();
+        let source = get(&strings, reader.u32()?, "string")?.to_string();
+        tokens.push(Token::new(
+            *get(&words, word as u32, "word")?,
+            get(&constants, constant, "constant")?.clone(),
+            file,
+            line,
+            col,
+            vis,
+            source,
+        ));
+    }
+    if reader.pos != bytes.len() {
+        return Err(anyhow::anyhow!(
+            "Bytecode has {} unexpected bytes at the end",
+            bytes.len() - reader.pos
+        ));
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::machine::Machine;
+    use crate::tokens::tokens;
+
+    const SOURCE: &str = "(sq) proc ( n:int -- n:int ) : * ret\n\
+                          (3) {sq} . nl\n\
+                          (0) while : (2) < do (1) + end\n\
+                          try (\"x\") (1.5) + catch clr endtry (true) if (None) . endif\n";
+
+    fn linked(source: &str) -> (Vec<TokenType>, Vec<Token>) {
+        (tokens(), Machine::linked(source).tokens().to_vec())
+    }
+
+    #[test]
+    fn round_trip() {
+        let (types, tokens) = linked(SOURCE);
+        let bytes = encode(&types, &tokens);
+        assert!(is_bytecode(&bytes));
+        let decoded = decode(&types, &bytes).unwrap();
+        assert_eq!(format!("{:?}", decoded), format!("{:?}", tokens));
+        // Repeated strings are stored once
+        let file = b"test.seal";
+        assert_eq!(bytes.windows(file.len()).filter(|w| w == file).count(), 1);
+        // and shared by the decoded tokens
+        assert!(Rc::ptr_eq(&decoded[0].file, &decoded[1].file));
+        assert!(Rc::ptr_eq(&decoded[0].vis, &decoded[1].vis));
+        assert!(!Rc::ptr_eq(
+            &decoded[0].vis,
+            &decoded[decoded.len() - 1].vis
+        ));
+    }
+
+    #[test]
+    fn words_are_resolved_by_name() {
+        let (types, tokens) = linked(SOURCE);
+        let bytes = encode(&types, &tokens);
+        let mut reversed = types.clone();
+        reversed.reverse();
+        let decoded = decode(&reversed, &bytes).unwrap();
+        for (decoded, token) in decoded.iter().zip(&tokens) {
+            assert_eq!(reversed[decoded.type_].name, types[token.type_].name);
+        }
+        assert!(decode(&types[..1], &bytes)
+            .unwrap_err()
+            .to_string()
+            .starts_with("Bytecode uses an unknown word"));
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        let (types, tokens) = linked(SOURCE);
+        let bytes = encode(&types, &tokens);
+        assert!(decode(&types, b"(1) . nl").is_err());
+
+        let mut newer = bytes.clone();
+        newer[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(decode(&types, &newer)
+            .unwrap_err()
+            .to_string()
+            .starts_with("Bytecode version 2 is not supported"));
+
+        for len in [5, 12, bytes.len() - 1] {
+            assert!(decode(&types, &bytes[..len]).is_err());
+        }
+        let mut longer = bytes.clone();
+        longer.push(0);
+        assert!(decode(&types, &longer).is_err());
+    }
+}
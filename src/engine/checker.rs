@@ -0,0 +1,626 @@
+use std::collections::HashMap;
+
+use super::data::{Data, Type};
+use super::lexer::error::SourceError;
+use super::lexer::token::{Effect, Signature, Token, TokenKind, TokenType};
+use super::mark::StackEffect;
+
+/// Stack at a point of the program, relative to where the walk started.
+#[derive(Debug, Clone, PartialEq)]
+enum Depth {
+    /// How many values are above the start, and the types of the top ones as far as known
+    Known(i64, Vec<Type>),
+    /// After a word whose effect the checker cannot know
+    Unknown,
+    /// After a word that does not continue with the next token
+    Unreachable,
+}
+
+/// What calling a proc does to the stack.
+#[derive(Debug, Clone)]
+struct ProcEffect {
+    effect: Effect,
+    inputs: Vec<Type>,
+    outputs: Vec<Type>,
+}
+
+fn values(count: i64) -> String {
+    match count {
+        1 => "1 value".to_string(),
+        count => format!("{} values", count),
+    }
+}
+
+/// Offset a linked block token points at, see `Machine::after_lex`.
+fn offset(token: &Token) -> usize {
+    match token.data {
+        Data::Int(offset) => offset as usize,
+        _ => 0,
+    }
+}
+
+/// Types of the values two paths both leave on top of the stack.
+fn merge_types(a: &[Type], b: &[Type]) -> Vec<Type> {
+    let len = a.len().min(b.len());
+    a[a.len() - len..]
+        .iter()
+        .zip(&b[b.len() - len..])
+        .map(|(a, b)| a.merge(*b))
+        .collect()
+}
+
+/// The `count` types on top of `types`, `Unknown` where they are not known.
+fn top(types: &[Type], count: usize) -> Vec<Type> {
+    let known = types.len().min(count);
+    let mut top = vec![Type::Unknown; count - known];
+    top.extend_from_slice(&types[types.len() - known..]);
+    top
+}
+
+struct Checker<'a> {
+    types: &'a [TokenType],
+    tokens: &'a [Token],
+    /// Whether type problems are reported, not only stack depth ones
+    typed: bool,
+    /// PC of the `proc` of every proc named by a literal
+    procs: HashMap<String, usize>,
+    /// Effect of the proc at every PC, `None` while it is being inferred
+    effects: HashMap<usize, Option<ProcEffect>>,
+    /// PC of the `do` of every `while`
+    loops: HashMap<usize, usize>,
+    /// Problems with the PC of the token they are about
+    problems: Vec<(usize, SourceError)>,
+}
+
+impl<'a> Checker<'a> {
+    fn new(types: &'a [TokenType], tokens: &'a [Token], typed: bool) -> Self {
+        let mut procs = HashMap::new();
+        let mut loops = HashMap::new();
+        for (pc, token) in tokens.iter().enumerate() {
+            match types[token.type_].type_ {
+                TokenKind::Proc if pc > 0 => {
+                    let name = &tokens[pc - 1];
+                    if types[name.type_].name == "push" && name.data.is_string() {
+                        procs.insert(name.data.to_string(), pc);
+                    }
+                }
+                TokenKind::Do => {
+                    let end = pc + 1 + offset(token);
+                    if let Some(end_token) = tokens.get(end) {
+                        loops.insert(end - 1 - offset(end_token), pc);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Self {
+            types,
+            tokens,
+            typed,
+            procs,
+            effects: HashMap::new(),
+            loops,
+            problems: Vec::new(),
+        }
+    }
+
+    fn report(&mut self, pc: usize, message: String) {
+        let token = &self.tokens[pc];
+        self.problems.push((
+            pc,
+            SourceError::new(&token.file, token.line, token.col, message),
+        ));
+    }
+
+    fn report_type(&mut self, pc: usize, message: String) {
+        if self.typed {
+            self.report(pc, message);
+        }
+    }
+
+    /// Types the token at `pc` pushes for the types it pops, reporting popped types that do not
+    /// fit.
+    fn push_types(&mut self, pc: usize, popped: &[Type], pushes: usize) -> Vec<Type> {
+        let token = &self.tokens[pc];
+        let token_type = &self.types[token.type_];
+        if token_type.name == "push" {
+            return vec![token.data.type_of()];
+        }
+        let call = match token_type.name.as_str() {
+            "call" => self
+                .procs
+                .get(&token.data.to_string())
+                .and_then(|proc| self.effects.get(proc).cloned().flatten()),
+            _ => None,
+        };
+        let (expected, mut pushed) = match (call, token_type.signature) {
+            (Some(call), _) => (call.inputs, call.outputs),
+            (None, Some(Signature::Fixed(inputs, outputs))) => (inputs.to_vec(), outputs.to_vec()),
+            (None, Some(Signature::Rule(rule))) => match rule(popped) {
+                Ok(pushed) => (Vec::new(), pushed),
+                Err(message) => {
+                    self.report_type(pc, format!("`{}` {}", token.word, message));
+                    (Vec::new(), Vec::new())
+                }
+            },
+            (None, None) => (Vec::new(), Vec::new()),
+        };
+        for (expected, popped) in expected.iter().zip(popped) {
+            if !popped.fits(*expected) {
+                self.report_type(
+                    pc,
+                    format!("`{}` needs {} but got {}", token.word, expected, popped),
+                );
+            }
+        }
+        pushed.resize(pushes, Type::Unknown);
+        pushed
+    }
+
+    /// Applies the effect of the token at `pc`. Underflows are reported when `min` is `None`,
+    /// otherwise `min` records the lowest depth reached.
+    fn apply(&mut self, pc: usize, effect: Effect, depth: Depth, min: &mut Option<i64>) -> Depth {
+        let (depth, mut types) = match depth {
+            Depth::Known(depth, types) => (depth, types),
+            Depth::Unreachable => return Depth::Unreachable,
+            Depth::Unknown => {
+                return match effect {
+                    Effect::Ends(_) => Depth::Unreachable,
+                    // Clearing makes the depth known again, except in proc bodies where it is
+                    // relative to the depth of the caller
+                    Effect::Clear if min.is_none() => Depth::Known(0, Vec::new()),
+                    _ => Depth::Unknown,
+                };
+            }
+        };
+        let pops = match effect {
+            Effect::Fixed(pops, _) | Effect::Ends(pops) => pops,
+            Effect::Clear if min.is_none() => return Depth::Known(0, Vec::new()),
+            Effect::Clear | Effect::Dynamic => return Depth::Unknown,
+        };
+        if depth < pops as i64 {
+            match min {
+                Some(min) => *min = (*min).min(depth - pops as i64),
+                None => {
+                    let word = self.tokens[pc].word.clone();
+                    self.report(
+                        pc,
+                        format!(
+                            "`{}` needs {} but the stack only has {}",
+                            word,
+                            values(pops as i64),
+                            depth
+                        ),
+                    );
+                    return Depth::Unknown;
+                }
+            }
+        }
+        let popped = top(&types, pops);
+        types.truncate(types.len().saturating_sub(pops));
+        match effect {
+            Effect::Fixed(_, pushes) => {
+                types.extend(self.push_types(pc, &popped, pushes));
+                Depth::Known(depth - pops as i64 + pushes as i64, types)
+            }
+            _ => {
+                self.push_types(pc, &popped, 0);
+                Depth::Unreachable
+            }
+        }
+    }
+
+    /// Pops the condition of `if` or `do`, reporting conditions that are always None.
+    fn condition(&mut self, pc: usize, depth: Depth, min: &mut Option<i64>) -> Depth {
+        if let Depth::Known(_, types) = &depth {
+            if types.last() == Some(&Type::None) {
+                self.report_type(
+                    pc,
+                    format!(
+                        "The condition of `{}` is always None, which is false",
+                        self.tokens[pc].word
+                    ),
+                );
+            }
+        }
+        let effect = self.types[self.tokens[pc].type_].effect;
+        self.apply(pc, effect, depth, min)
+    }
+
+    /// Joins the stacks of two paths that meet again, reporting them if their depths differ.
+    fn merge(
+        &mut self,
+        pc: usize,
+        a: Depth,
+        b: Depth,
+        message: impl Fn(i64, i64) -> String,
+    ) -> Depth {
+        match (a, b) {
+            (Depth::Unreachable, depth) | (depth, Depth::Unreachable) => depth,
+            (Depth::Known(a, a_types), Depth::Known(b, b_types)) if a == b => {
+                Depth::Known(a, merge_types(&a_types, &b_types))
+            }
+            (Depth::Known(a, _), Depth::Known(b, _)) => {
+                self.report(pc, message(a, b));
+                Depth::Unknown
+            }
+            _ => Depth::Unknown,
+        }
+    }
+
+    /// Stack effect declared after the `proc` at `pc`, if any.
+    fn declared_effect(&self, pc: usize) -> Option<StackEffect> {
+        let token = self.tokens.get(pc + 1)?;
+        if self.types[token.type_].type_ != TokenKind::StackEffect {
+            return None;
+        }
+        StackEffect::parse(&token.data.to_string()).ok()
+    }
+
+    /// Effect of calling the proc defined at `pc`, checking its body the first time.
+    fn proc_effect(&mut self, pc: usize) -> Effect {
+        match self.effects.get(&pc) {
+            Some(Some(proc)) => return proc.effect,
+            // Recursive calls cannot be inferred
+            Some(None) => return Effect::Dynamic,
+            None => {}
+        }
+        self.effects.insert(pc, None);
+        let ret = pc + 1 + offset(&self.tokens[pc]);
+        if let Some(effect) = self.declared_effect(pc) {
+            // The body is checked against the declaration instead of inferring it
+            let inputs = effect
+                .inputs
+                .iter()
+                .map(|slot| slot.type_)
+                .collect::<Vec<Type>>();
+            let outputs = effect
+                .outputs
+                .iter()
+                .map(|slot| slot.type_)
+                .collect::<Vec<Type>>();
+            let start = Depth::Known(inputs.len() as i64, inputs.clone());
+            let end = self.walk(pc + 2, ret, start, &mut None);
+            let name = match pc.checked_sub(1).map(|name| &self.tokens[name].data) {
+                Some(Data::String(name)) => name.clone(),
+                _ => "?".to_string(),
+            };
+            if let Depth::Known(depth, types) = end {
+                if depth != outputs.len() as i64 {
+                    self.report(
+                        pc,
+                        format!(
+                            "Proc {} {} leaves {} instead of {}",
+                            name,
+                            effect,
+                            values(depth),
+                            outputs.len()
+                        ),
+                    );
+                } else {
+                    let left = top(&types, outputs.len());
+                    for (slot, left) in effect.outputs.iter().zip(left) {
+                        if !left.fits(slot.type_) {
+                            self.report_type(
+                                pc,
+                                format!(
+                                    "Proc {} {} leaves {} as {}",
+                                    name, effect, slot.name, left
+                                ),
+                            );
+                        }
+                    }
+                }
+            }
+            let proc = ProcEffect {
+                effect: Effect::Fixed(inputs.len(), outputs.len()),
+                inputs,
+                outputs,
+            };
+            self.effects.insert(pc, Some(proc.clone()));
+            return proc.effect;
+        }
+        let mut min = Some(0);
+        let end = self.walk(pc + 1, ret, Depth::Known(0, Vec::new()), &mut min);
+        let inputs = -min.unwrap_or(0) as usize;
+        let proc = match end {
+            Depth::Known(depth, types) => {
+                let pushes = (depth + inputs as i64) as usize;
+                ProcEffect {
+                    effect: Effect::Fixed(inputs, pushes),
+                    inputs: Vec::new(),
+                    outputs: top(&types, pushes),
+                }
+            }
+            Depth::Unreachable => ProcEffect {
+                effect: Effect::Ends(inputs),
+                inputs: Vec::new(),
+                outputs: Vec::new(),
+            },
+            Depth::Unknown => ProcEffect {
+                effect: Effect::Dynamic,
+                inputs: Vec::new(),
+                outputs: Vec::new(),
+            },
+        };
+        self.effects.insert(pc, Some(proc.clone()));
+        proc.effect
+    }
+
+    /// Walks the tokens from `start` up to `end`, returning the stack at `end`.
+    fn walk(&mut self, start: usize, end: usize, mut depth: Depth, min: &mut Option<i64>) -> Depth {
+        let (types, tokens) = (self.types, self.tokens);
+        let mut pc = start;
+        while pc < end {
+            let token = &tokens[pc];
+            let token_type = &types[token.type_];
+            match token_type.type_ {
+                TokenKind::If => {
+                    let endif = pc + 1 + offset(token);
+                    let skipped = self.condition(pc, depth, min);
+                    let taken = self.walk(pc + 1, endif, skipped.clone(), min);
+                    depth = self.merge(pc, taken, skipped, |taken, skipped| {
+                        format!(
+                            "The if block changes the stack depth by {:+}, so the depth after it \
+                             depends on the condition",
+                            taken - skipped
+                        )
+                    });
+                    pc = endif + 1;
+                }
+                TokenKind::While if self.loops.contains_key(&pc) => {
+                    let do_ = self.loops[&pc];
+                    let end = do_ + 1 + offset(&tokens[do_]);
+                    // Types can change between iterations, so the loop is walked until they
+                    // settle
+                    let mut entry = depth;
+                    let reported = self.problems.len();
+                    depth = loop {
+                        let condition = self.walk(pc + 1, do_, entry.clone(), min);
+                        let body = self.condition(do_, condition, min);
+                        let after = self.walk(do_ + 1, end, body.clone(), min);
+                        match (&entry, after) {
+                            (Depth::Known(before, _), Depth::Known(after, _))
+                                if *before != after =>
+                            {
+                                self.report(
+                                    pc,
+                                    format!(
+                                        "Each iteration of the loop changes the stack depth \
+                                         by {:+}",
+                                        after - before
+                                    ),
+                                );
+                                break Depth::Unknown;
+                            }
+                            (Depth::Known(before, before_types), Depth::Known(_, after_types)) => {
+                                let widened =
+                                    Depth::Known(*before, merge_types(before_types, &after_types));
+                                if widened == entry {
+                                    break body;
+                                }
+                                // Only the last walk reports the loop, its first ones saw types
+                                // that are too narrow
+                                let mut index = 0;
+                                self.problems.retain(|(problem, _)| {
+                                    index += 1;
+                                    index <= reported || !(pc..=end).contains(problem)
+                                });
+                                entry = widened;
+                            }
+                            (_, Depth::Unknown) => break Depth::Unknown,
+                            _ => break body,
+                        }
+                    };
+                    pc = end + 1;
+                }
+                TokenKind::Proc => {
+                    depth = self.apply(pc, token_type.effect, depth, min);
+                    let ret = pc + 1 + offset(token);
+                    self.proc_effect(pc);
+                    pc = ret + 1;
+                }
+                TokenKind::Try => {
+                    let catch = pc + 1 + offset(token);
+                    let endtry = catch + 1 + offset(&tokens[catch]);
+                    let finished = self.walk(pc + 1, catch, depth.clone(), min);
+                    // The handler unwinds to the depth at `try` and pushes the location and error
+                    let (handler, before) = match &depth {
+                        Depth::Known(depth, types) => {
+                            let mut types = types.clone();
+                            types.extend([Type::String, Type::Unknown]);
+                            (Depth::Known(depth + 2, types), *depth)
+                        }
+                        depth => (depth.clone(), 0),
+                    };
+                    let caught = self.walk(catch + 1, endtry, handler, min);
+                    depth = self.merge(pc, finished, caught, |finished, caught| {
+                        format!(
+                            "The try block changes the stack depth by {:+} but its catch by {:+}",
+                            finished - before,
+                            caught - before
+                        )
+                    });
+                    pc = endtry + 1;
+                }
+                _ => {
+                    let effect = if token_type.name == "call" {
+                        match self.procs.get(&token.data.to_string()) {
+                            Some(&proc) => self.proc_effect(proc),
+                            None => Effect::Dynamic,
+                        }
+                    } else {
+                        token_type.effect
+                    };
+                    depth = self.apply(pc, effect, depth, min);
+                    pc += 1;
+                }
+            }
+        }
+        depth
+    }
+}
+
+/// Checks the stack effects of linked tokens, assuming the program starts with an empty stack.
+/// Reports words that pop more values than the stack holds, `if` blocks and `try`/`catch` pairs
+/// whose paths leave different depths, and loops whose iterations change the depth. With `typed`
+/// it also reports values of the wrong type, like `+` on a string or `if` on a value that is
+/// always None.
+pub fn check(types: &[TokenType], tokens: &[Token], typed: bool) -> Vec<SourceError> {
+    let mut checker = Checker::new(types, tokens, typed);
+    checker.walk(0, tokens.len(), Depth::Known(0, Vec::new()), &mut None);
+    let mut problems = checker
+        .problems
+        .into_iter()
+        .map(|(_, problem)| problem)
+        .collect::<Vec<SourceError>>();
+    problems.sort_by_key(|problem| (problem.line, problem.col));
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::lexer::token::{Effect, Signature};
+    use crate::engine::machine::Machine;
+    use crate::tokens::tokens;
+
+    fn problems(source: &str, typed: bool) -> Vec<String> {
+        Machine::linked(source)
+            .check(typed)
+            .iter()
+            .map(|problem| format!("{}:{}: {}", problem.line, problem.col, problem))
+            .collect()
+    }
+
+    fn check_source(source: &str) -> Vec<String> {
+        problems(source, false)
+    }
+
+    fn check_types(source: &str) -> Vec<String> {
+        problems(source, true)
+    }
+
+    #[test]
+    fn underflow() {
+        assert_eq!(
+            check_source("(1) (2) + .\n(1) +\n"),
+            ["2:5: `+` needs 2 values but the stack only has 1"]
+        );
+        assert_eq!(
+            check_source("(1) : . . .\n"),
+            ["1:11: `.` needs 1 value but the stack only has 0"]
+        );
+        assert!(check_source("(1) clr . read-line .\n").len() == 1);
+    }
+
+    #[test]
+    fn branches_and_loops() {
+        assert_eq!(
+            check_source("(1) if (2) endif\n(1) if (2) . endif\n"),
+            ["1:5: The if block changes the stack depth by +1, so the depth after it depends on \
+              the condition"]
+        );
+        assert_eq!(
+            check_source("(0) while : (3) > do (1) + end .\n(0) while : (3) > do : end\n"),
+            ["2:5: Each iteration of the loop changes the stack depth by +1"]
+        );
+        assert_eq!(
+            check_source("try (1) catch . . endtry\ntry (1) catch . endtry .\n"),
+            ["1:1: The try block changes the stack depth by +1 but its catch by +0"]
+        );
+        assert!(check_source("(1) if (\"no\") throw endif (2) if (0) exit endif\n").is_empty());
+    }
+
+    #[test]
+    fn procs() {
+        assert!(check_source("(sq) proc : * ret\n(2) {sq} .\n").is_empty());
+        assert_eq!(check_source("(sq) proc : * ret\n{sq}\n").len(), 1);
+        assert_eq!(
+            check_source("(two) proc (1) (2) ret\n{two} + + .\n"),
+            ["2:9: `+` needs 2 values but the stack only has 1"]
+        );
+        // Recursion and unknown procs make the depth unknown instead of failing
+        assert!(check_source("(f) proc {f} ret\n{f} {g} . .\n").is_empty());
+        // Declared effects are used for calls and checked against the body
+        assert_eq!(
+            check_source("(add3) proc ( a b c -- sum ) + + ret\n(1) (2) {add3} .\n"),
+            ["2:9: `{add3}` needs 3 values but the stack only has 2"]
+        );
+        assert_eq!(
+            check_source("(bad) proc ( a -- b c ) : : ret\n"),
+            ["1:7: Proc bad ( a -- b c ) leaves 3 values instead of 2"]
+        );
+        assert_eq!(check_source("(bad) proc ( a -- ) + ret\n").len(), 1);
+        // Proc bodies are checked even when they are never called
+        assert_eq!(check_source("(p) proc (1) if (1) endif ret\n").len(), 1);
+    }
+
+    #[test]
+    fn types() {
+        assert!(check_types("(1) (2) + (1.5) (2.5) * . .\n(\"a\") (\"b\") , .\n").is_empty());
+        assert_eq!(
+            check_types("(\"a\") (1) + .\n(1) (2.5) - .\n"),
+            [
+                "1:11: `+` needs numbers but got string",
+                "2:11: `-` mixes int and float",
+            ]
+        );
+        assert_eq!(
+            check_types("(None) if (1) . endif\n(None) while : do end .\n"),
+            [
+                "1:8: The condition of `if` is always None, which is false",
+                "2:16: The condition of `do` is always None, which is false",
+            ]
+        );
+        // Types are only reported when asked for
+        assert!(check_source("(\"a\") (1) + .\n").is_empty());
+        // Values from words without a known type fit anything
+        assert!(check_types("read-line (1) + .\n").is_empty());
+    }
+
+    #[test]
+    fn merged_types() {
+        // Both branches leave an int, so `+` is fine after the if
+        assert!(check_types("(1) read-line if . (2) endif (1) + .\n").is_empty());
+        // One branch leaves a string, so the type after the if is unknown
+        assert!(check_types("(1) read-line if . (\"a\") endif (1) + .\n").is_empty());
+        // A loop that replaces a None is walked again with the merged types
+        assert!(check_types("(None) while : do . (1) end .\n").is_empty());
+        assert_eq!(
+            check_types("(None) while : do . (None) end .\n"),
+            ["1:16: The condition of `do` is always None, which is false"]
+        );
+    }
+
+    #[test]
+    fn proc_types() {
+        assert!(check_types("(inc) proc ( n:int -- n:int ) (1) + ret\n(2) {inc} .\n").is_empty());
+        assert_eq!(
+            check_types("(inc) proc ( n:int -- n:int ) (1) + ret\n(\"a\") {inc} .\n"),
+            ["2:7: `{inc}` needs int but got string"]
+        );
+        assert_eq!(
+            check_types("(name) proc ( -- s:string ) (1) ret\n"),
+            ["1:8: Proc name ( -- s:string ) leaves s as int"]
+        );
+        // Inferred procs pass on the types they leave
+        assert_eq!(
+            check_types("(one) proc (1) ret\n{one} (\"a\") + .\n"),
+            ["2:13: `+` needs numbers but got string"]
+        );
+    }
+
+    #[test]
+    fn signatures_match_effects() {
+        for token_type in tokens() {
+            if let Some(Signature::Fixed(inputs, outputs)) = token_type.signature {
+                let matches = match token_type.effect {
+                    Effect::Fixed(pops, pushes) => (pops, pushes) == (inputs.len(), outputs.len()),
+                    Effect::Ends(pops) => (pops, 0) == (inputs.len(), outputs.len()),
+                    _ => false,
+                };
+                assert!(matches, "{}", token_type.name);
+            }
+        }
+    }
+}
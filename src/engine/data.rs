@@ -3,12 +3,66 @@ use anyhow::Result;
 #[derive(Debug, Clone, PartialEq)]
 pub enum Data {
     String(String),
-    Int(i32),
+    /// 64-bit so that Unix timestamps in milliseconds and nanoseconds fit, which overflow `i32`
+    Int(i64),
     Float(f32),
     Bool(bool),
     None,
 }
 
+/// Type of a value as the static checker sees it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Type {
+    Int,
+    Float,
+    String,
+    Bool,
+    None,
+    /// Could be a value of any type
+    Unknown,
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self {
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::String => write!(f, "string"),
+            Type::Bool => write!(f, "bool"),
+            Type::None => write!(f, "None"),
+            Type::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+impl Type {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "int" => Ok(Type::Int),
+            "float" => Ok(Type::Float),
+            "string" => Ok(Type::String),
+            "bool" => Ok(Type::Bool),
+            "None" => Ok(Type::None),
+            "any" => Ok(Type::Unknown),
+            _ => Err(anyhow::anyhow!("Unknown type: {}", name)),
+        }
+    }
+
+    /// The type a value has when it can come from either of two places.
+    pub fn merge(self, other: Type) -> Type {
+        if self == other {
+            self
+        } else {
+            Type::Unknown
+        }
+    }
+
+    /// Whether a value of this type can be used where `expected` is needed.
+    pub fn fits(self, expected: Type) -> bool {
+        self == expected || self == Type::Unknown || expected == Type::Unknown
+    }
+}
+
 impl std::fmt::Display for Data {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match &self {
@@ -23,7 +77,7 @@ impl std::fmt::Display for Data {
 
 impl Data {
     pub fn from_any(s: &str) -> Self {
-        if let Ok(i) = s.parse::<i32>() {
+        if let Ok(i) = s.parse::<i64>() {
             return Data::Int(i);
         }
 
@@ -42,7 +96,7 @@ impl Data {
         Data::String(s.to_string())
     }
 
-    pub fn from_int(i: i32) -> Self {
+    pub fn from_int(i: i64) -> Self {
         Data::Int(i)
     }
 
@@ -58,6 +112,7 @@ impl Data {
         Data::String(s)
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Self {
         Data::String(s.to_string())
     }
@@ -77,48 +132,30 @@ impl Data {
     }
 
     pub fn is_int(&self) -> bool {
-        match &self {
-            Data::Int(_) => true,
-            _ => false,
-        }
+        matches!(self, Data::Int(_))
     }
 
     pub fn is_float(&self) -> bool {
-        match &self {
-            Data::Float(_) => true,
-            _ => false,
-        }
+        matches!(self, Data::Float(_))
     }
 
     pub fn is_bool(&self) -> bool {
-        match &self {
-            Data::Bool(_) => true,
-            _ => false,
-        }
+        matches!(self, Data::Bool(_))
     }
 
     pub fn is_string(&self) -> bool {
-        match &self {
-            Data::String(_) => true,
-            _ => false,
-        }
+        matches!(self, Data::String(_))
     }
 
     pub fn is_number(&self) -> bool {
-        match &self {
-            Data::Int(_) | Data::Float(_) => true,
-            _ => false,
-        }
+        matches!(self, Data::Int(_) | Data::Float(_))
     }
 
     pub fn is_none(&self) -> bool {
-        match &self {
-            Data::None => true,
-            _ => false,
-        }
+        matches!(self, Data::None)
     }
 
-    pub fn as_int(&self) -> Result<i32> {
+    pub fn as_int(&self) -> Result<i64> {
         match &self {
             Data::Int(i) => Ok(*i),
             _ => Err(anyhow::anyhow!("Data is not an int")),
@@ -163,6 +200,16 @@ impl Data {
         }
     }
 
+    pub fn type_of(&self) -> Type {
+        match &self {
+            Data::Int(_) => Type::Int,
+            Data::Float(_) => Type::Float,
+            Data::Bool(_) => Type::Bool,
+            Data::String(_) => Type::String,
+            Data::None => Type::None,
+        }
+    }
+
     pub fn check_type(&self, other: &Data) -> bool {
         match &self {
             Data::Int(_) => other.is_int(),
@@ -176,7 +223,19 @@ impl Data {
 
 #[cfg(test)]
 mod tests {
-    use super::Data;
+    use super::{Data, Type};
+
+    #[test]
+    fn types() {
+        assert_eq!(Data::from_any("1.5").type_of(), Type::Float);
+        assert_eq!(Data::None.type_of().to_string(), Data::None.type_name());
+        assert_eq!(Type::parse("string").unwrap(), Type::String);
+        assert!(Type::parse("str").is_err());
+        assert_eq!(Type::Int.merge(Type::Int), Type::Int);
+        assert_eq!(Type::Int.merge(Type::Float), Type::Unknown);
+        assert!(Type::Unknown.fits(Type::String) && Type::Int.fits(Type::Unknown));
+        assert!(!Type::Int.fits(Type::Float));
+    }
 
     #[test]
     fn from_int() {
@@ -219,6 +278,12 @@ mod tests {
         assert_eq!(data, Data::Int(42));
         assert!(data.is_int());
 
+        let data = Data::from_any("9223372036854775807");
+        assert_eq!(data, Data::Int(i64::MAX));
+
+        let data = Data::from_any("1700000000000");
+        assert_eq!(data, Data::Int(1_700_000_000_000));
+
         let data = Data::from_any("42.0");
         assert_eq!(data, Data::Float(42.0));
         assert!(data.is_float());
@@ -288,7 +353,7 @@ mod tests {
     #[test]
     fn as_bool() {
         let data = Data::Bool(true);
-        assert_eq!(data.as_bool().unwrap(), true);
+        assert!(data.as_bool().unwrap());
 
         let data = Data::Int(42);
         assert!(data.as_bool().is_err());
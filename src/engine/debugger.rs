@@ -0,0 +1,217 @@
+use anyhow::Result;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Breakpoint {
+    /// Stops at the first token of a line
+    Line(usize),
+    /// Stops at the first token of a proc body, `pc` is the `proc` token
+    Proc { name: String, pc: usize },
+}
+
+impl std::fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Breakpoint::Line(line) => write!(f, "line {}", line),
+            Breakpoint::Proc { name, .. } => write!(f, "proc {}", name),
+        }
+    }
+}
+
+/// How the debugger decides where to stop next.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Step,
+    Continue,
+    /// Stops at the next token that is not deeper in the return stack than `depth`
+    StepOver(usize),
+    /// Stops at the next token that is shallower in the return stack than `depth`
+    Finish(usize),
+}
+
+/// Why the debugger stopped before a token.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    Step,
+    Breakpoint,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Step,
+    StepOver,
+    Finish,
+    Continue,
+    Break(String),
+    Delete(usize),
+    Breakpoints,
+    Print(String),
+    Stack,
+    ReturnStack,
+    Vars,
+    Help,
+    Quit,
+}
+
+pub const HELP: &str = "\
+enter, s, step      Execute the next token
+n, next, over       Step over a {call}
+f, finish           Run until the current proc returns
+c, continue         Run until a breakpoint
+b, break <line>     Break at the first token of a line
+b, break <proc>     Break at the start of a proc
+bl, breakpoints     List breakpoints
+d, delete <n>       Delete breakpoint number n
+p, print <var>      Print a local or global variable
+stack, rstack, vars Show the stack, the return stack or the variables
+q, quit             Stop the program
+h, help             Show this message";
+
+impl Command {
+    pub fn parse(input: &str) -> Result<Self> {
+        let mut words = input.split_whitespace();
+        let name = words.next().unwrap_or("");
+        let argument = words.collect::<Vec<&str>>().join(" ");
+        let need_argument = |what: &str| -> Result<String> {
+            if argument.is_empty() {
+                return Err(anyhow::anyhow!("{} requires {}", name, what));
+            }
+            Ok(argument.clone())
+        };
+        Ok(match name {
+            "" | "s" | "step" => Command::Step,
+            "n" | "next" | "over" => Command::StepOver,
+            "f" | "finish" => Command::Finish,
+            "c" | "continue" => Command::Continue,
+            "b" | "break" => Command::Break(need_argument("a line or a proc name")?),
+            "d" | "delete" => match need_argument("a breakpoint number")?.parse() {
+                Ok(n) => Command::Delete(n),
+                Err(_) => return Err(anyhow::anyhow!("Invalid breakpoint number")),
+            },
+            "bl" | "breakpoints" => Command::Breakpoints,
+            "p" | "print" => Command::Print(need_argument("a variable name")?),
+            "stack" => Command::Stack,
+            "rstack" => Command::ReturnStack,
+            "vars" => Command::Vars,
+            "h" | "help" => Command::Help,
+            "q" | "quit" => Command::Quit,
+            _ => return Err(anyhow::anyhow!("Unknown command: {}", name)),
+        })
+    }
+}
+
+pub struct Debugger {
+    pub breakpoints: Vec<Breakpoint>,
+    pub mode: Mode,
+    last_line: Option<usize>,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self {
+            breakpoints: Vec::new(),
+            mode: Mode::Step,
+            last_line: None,
+        }
+    }
+
+    /// Whether a breakpoint is set on the token at `pc`. `entered_line` tells if it is the first
+    /// token executed on its line since the previous one.
+    pub fn is_breakpoint(&self, pc: usize, line: usize, entered_line: bool) -> bool {
+        self.breakpoints.iter().any(|breakpoint| match breakpoint {
+            Breakpoint::Line(l) => entered_line && *l == line,
+            Breakpoint::Proc { pc: proc_pc, .. } => *proc_pc + 1 == pc,
+        })
+    }
+
+    /// Decides whether to stop before running the token at `pc` on `line`, with `depth` entries
+    /// in the return stack.
+    pub fn check(&mut self, pc: usize, line: usize, depth: usize) -> Option<StopReason> {
+        let entered_line = self.last_line != Some(line);
+        self.last_line = Some(line);
+        if self.is_breakpoint(pc, line, entered_line) {
+            return Some(StopReason::Breakpoint);
+        }
+        let stop = match self.mode {
+            Mode::Step => true,
+            Mode::Continue => false,
+            Mode::StepOver(over) => depth <= over,
+            Mode::Finish(finish) => depth < finish,
+        };
+        stop.then_some(StopReason::Step)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(Command::parse("").unwrap(), Command::Step);
+        assert_eq!(Command::parse("next").unwrap(), Command::StepOver);
+        assert_eq!(
+            Command::parse("b 12").unwrap(),
+            Command::Break("12".to_string())
+        );
+        assert_eq!(
+            Command::parse("print  x ").unwrap(),
+            Command::Print("x".to_string())
+        );
+        assert_eq!(Command::parse("d 2").unwrap(), Command::Delete(2));
+        assert!(Command::parse("d two").is_err());
+        assert!(Command::parse("break").is_err());
+        assert!(Command::parse("jump").is_err());
+    }
+
+    #[test]
+    fn step() {
+        let mut debugger = Debugger::new();
+        assert!(debugger.check(0, 1, 0).is_some());
+        assert!(debugger.check(1, 1, 0).is_some());
+    }
+
+    #[test]
+    fn continue_to_line() {
+        let mut debugger = Debugger::new();
+        debugger.mode = Mode::Continue;
+        debugger.breakpoints.push(Breakpoint::Line(2));
+        assert!(debugger.check(0, 1, 0).is_none());
+        assert!(debugger.check(1, 2, 0).is_some());
+        // Only the first token of the line stops
+        assert!(debugger.check(2, 2, 0).is_none());
+        assert!(debugger.check(3, 3, 0).is_none());
+        // Coming back to the line stops again
+        assert!(debugger.check(1, 2, 0).is_some());
+    }
+
+    #[test]
+    fn continue_to_proc() {
+        let mut debugger = Debugger::new();
+        debugger.mode = Mode::Continue;
+        debugger.breakpoints.push(Breakpoint::Proc {
+            name: "print".to_string(),
+            pc: 4,
+        });
+        assert!(debugger.check(4, 1, 0).is_none());
+        assert_eq!(debugger.check(5, 1, 1), Some(StopReason::Breakpoint));
+    }
+
+    #[test]
+    fn step_over_and_finish() {
+        let mut debugger = Debugger::new();
+        debugger.mode = Mode::StepOver(0);
+        assert!(debugger.check(10, 5, 1).is_none());
+        assert!(debugger.check(3, 1, 0).is_some());
+
+        debugger.mode = Mode::Finish(2);
+        assert!(debugger.check(10, 5, 2).is_none());
+        assert!(debugger.check(10, 5, 3).is_none());
+        assert!(debugger.check(7, 2, 1).is_some());
+    }
+}
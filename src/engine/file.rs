@@ -1,11 +1,15 @@
 use anyhow::{Context, Result};
 use log::info;
 
+use super::bytecode;
+
 pub struct File {
     pub name: String,
     pub path: String,
     pub absolute_path: String,
     pub contents: String,
+    /// The compiled program when the file is bytecode instead of source
+    pub bytecode: Option<Vec<u8>>,
 }
 
 impl File {
@@ -22,12 +26,13 @@ impl File {
             path,
             absolute_path,
             contents: String::new(),
+            bytecode: None,
         })
     }
 
     pub fn read(&mut self) -> Result<()> {
-        self.contents = match std::fs::read_to_string(&self.absolute_path) {
-            Ok(contents) => contents,
+        let bytes = match std::fs::read(&self.absolute_path) {
+            Ok(bytes) => bytes,
             Err(e) => {
                 return Err(e).context(format!("Error reading file: {}", self.absolute_path))?;
             }
@@ -35,8 +40,14 @@ impl File {
         info!(
             "Read file: {} ({} KB)",
             self.absolute_path,
-            self.contents.len() as f32 / 1024.0
+            bytes.len() as f32 / 1024.0
         );
+        if bytecode::is_bytecode(&bytes) {
+            self.bytecode = Some(bytes);
+            return Ok(());
+        }
+        self.contents = String::from_utf8(bytes)
+            .context(format!("Error reading file: {}", self.absolute_path))?;
         Ok(())
     }
 }
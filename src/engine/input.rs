@@ -0,0 +1,160 @@
+use anyhow::{Context, Result};
+use std::io::{BufRead, BufReader, IsTerminal, Read};
+
+/// Program input. Scripts read from it with `read-line` and friends, while the interactive
+/// debugger reads its commands from the terminal so it does not eat the piped input.
+pub struct Input {
+    reader: Box<dyn BufRead>,
+    interactive: bool,
+    terminal: Option<Box<dyn BufRead>>,
+}
+
+impl Default for Input {
+    fn default() -> Self {
+        Self::stdin()
+    }
+}
+
+impl Input {
+    pub fn stdin() -> Self {
+        // Buffered on our side instead of holding the stdin lock for the whole run
+        let stdin = std::io::stdin();
+        Self {
+            interactive: stdin.is_terminal(),
+            reader: Box::new(BufReader::new(stdin)),
+            terminal: None,
+        }
+    }
+
+    pub fn from_reader(reader: impl BufRead + 'static) -> Self {
+        Self {
+            reader: Box::new(reader),
+            interactive: false,
+            terminal: None,
+        }
+    }
+
+    /// Reads one line without the line ending. Returns `None` at the end of input.
+    pub fn read_line(&mut self) -> Result<Option<String>> {
+        let mut line = String::new();
+        if self
+            .reader
+            .read_line(&mut line)
+            .context("Error reading from input")?
+            == 0
+        {
+            return Ok(None);
+        }
+        if line.ends_with('\n') {
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+        }
+        Ok(Some(line))
+    }
+
+    /// Reads everything up to the end of input.
+    pub fn read_all(&mut self) -> Result<String> {
+        let mut contents = String::new();
+        self.reader
+            .read_to_string(&mut contents)
+            .context("Error reading from input")?;
+        Ok(contents)
+    }
+
+    /// Reads one UTF-8 character. Returns `None` at the end of input.
+    pub fn read_char(&mut self) -> Result<Option<char>> {
+        let first = match self.peek_byte()? {
+            Some(byte) => byte,
+            None => return Ok(None),
+        };
+        let len = match first {
+            0x00..=0x7f => 1,
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            _ => 4,
+        };
+        let mut bytes = vec![0; len];
+        self.reader
+            .read_exact(&mut bytes)
+            .context("Error reading from input")?;
+        match String::from_utf8(bytes) {
+            Ok(s) => Ok(s.chars().next()),
+            Err(_) => Err(anyhow::anyhow!("Input is not valid UTF-8")),
+        }
+    }
+
+    pub fn is_eof(&mut self) -> Result<bool> {
+        Ok(self.peek_byte()?.is_none())
+    }
+
+    /// Reads a debugger command. Shares the program input when it is the terminal, otherwise
+    /// opens the terminal directly and only falls back to the program input without one.
+    pub fn read_command(&mut self) -> Result<String> {
+        if !self.interactive && self.terminal.is_none() {
+            self.terminal = open_terminal();
+        }
+        let reader = match self.terminal.as_mut() {
+            Some(terminal) => terminal,
+            None => &mut self.reader,
+        };
+        let mut line = String::new();
+        reader
+            .read_line(&mut line)
+            .context("Error reading debugger command")?;
+        Ok(line.trim().to_string())
+    }
+
+    fn peek_byte(&mut self) -> Result<Option<u8>> {
+        let buffer = self.reader.fill_buf().context("Error reading from input")?;
+        Ok(buffer.first().copied())
+    }
+}
+
+fn open_terminal() -> Option<Box<dyn BufRead>> {
+    let path = if cfg!(windows) { "CONIN$" } else { "/dev/tty" };
+    std::fs::File::open(path)
+        .ok()
+        .map(|file| Box::new(BufReader::new(file)) as Box<dyn BufRead>)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Input;
+    use std::io::Cursor;
+
+    #[test]
+    fn read_line() {
+        let mut input = Input::from_reader(Cursor::new("one\r\ntwo\nthree"));
+        assert_eq!(input.read_line().unwrap(), Some("one".to_string()));
+        assert_eq!(input.read_line().unwrap(), Some("two".to_string()));
+        assert_eq!(input.read_line().unwrap(), Some("three".to_string()));
+        assert_eq!(input.read_line().unwrap(), None);
+    }
+
+    #[test]
+    fn read_all() {
+        let mut input = Input::from_reader(Cursor::new("one\ntwo\n"));
+        assert_eq!(input.read_line().unwrap(), Some("one".to_string()));
+        assert_eq!(input.read_all().unwrap(), "two\n");
+        assert_eq!(input.read_all().unwrap(), "");
+    }
+
+    #[test]
+    fn read_char() {
+        let mut input = Input::from_reader(Cursor::new("aé😀"));
+        assert_eq!(input.read_char().unwrap(), Some('a'));
+        assert_eq!(input.read_char().unwrap(), Some('é'));
+        assert_eq!(input.read_char().unwrap(), Some('😀'));
+        assert_eq!(input.read_char().unwrap(), None);
+    }
+
+    #[test]
+    fn is_eof() {
+        let mut input = Input::from_reader(Cursor::new("a"));
+        assert!(!input.is_eof().unwrap());
+        input.read_char().unwrap();
+        assert!(input.is_eof().unwrap());
+    }
+}
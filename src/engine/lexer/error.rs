@@ -0,0 +1,27 @@
+/// Error at a place in a source file, so tools like the language server can point at it.
+#[derive(Debug, Clone)]
+pub struct SourceError {
+    pub file: String,
+    pub line: usize,
+    pub col: usize,
+    pub message: String,
+}
+
+impl SourceError {
+    pub fn new(file: &str, line: usize, col: usize, message: String) -> Self {
+        Self {
+            file: file.to_string(),
+            line,
+            col,
+            message,
+        }
+    }
+}
+
+impl std::fmt::Display for SourceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for SourceError {}
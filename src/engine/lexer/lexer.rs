@@ -1,18 +1,34 @@
 use anyhow::Result;
 use log::{debug, info};
 use regex::Regex;
+use std::rc::Rc;
 
 use super::super::data::Data;
+use super::super::mark::StackEffect;
+use super::error::SourceError;
 use super::token::{Token, TokenKind, TokenType};
 
+/// A `#` comment, which runs to the end of its line. Comments are not tokens, but the formatter
+/// keeps them.
+#[derive(Debug, Clone)]
+pub struct Comment {
+    pub line: usize,
+    pub col: usize,
+    /// The comment as written, `#` included
+    pub text: String,
+}
+
 struct Lexer {
     contents: String,
-    raw_contents: String,
-    file: String,
+    /// The lines of the source, shared by the tokens on them
+    lines: Vec<Rc<str>>,
+    file: Rc<str>,
     line: usize,
     col: usize,
     tokens: Vec<Token>,
+    comments: Vec<Comment>,
     token_types: Vec<TokenType>,
+    regexes: Vec<Regex>,
 }
 
 impl Lexer {
@@ -23,28 +39,55 @@ impl Lexer {
         }
         contents.push(' ');
 
+        // A word has to match a token regex as a whole, so `rand-int` is not lexed as `-`, `ifx` as
+        // `if` or `==` as `=`
+        let regexes = token_types
+            .iter()
+            .map(|token| Regex::new(&format!("^(?:{})$", token.regex)).unwrap())
+            .collect();
+
         Self {
             contents: contents.clone(),
-            raw_contents: contents.clone(),
-            file,
+            lines: contents.lines().map(Rc::from).collect(),
+            file: Rc::from(file),
             line: 1,
             col: 1,
             tokens: Vec::new(),
+            comments: Vec::new(),
             token_types,
+            regexes,
         }
     }
 
     fn lex(&mut self) -> Result<()> {
+        if self.contents.starts_with('#') {
+            let end = self.contents.find('\n').unwrap_or(self.contents.len());
+            let text = self.contents[..end].trim_end().to_string();
+            self.comments.push(Comment {
+                line: self.line,
+                col: self.col,
+                text,
+            });
+            self.col += self.contents[..end].chars().count();
+            self.contents = self.contents[end..].to_string();
+            return Ok(());
+        }
+
         // Find the next word (separated by whitespace, \t, \n, or \r) and try to regex it. Word
         // should not be empty or contain only whitespace.
         let mut remove_symbols = 0;
         let mut word = String::new();
-        let mut word_start_col = self.col;
+        let word_start_col = self.col;
         let word_start_line = self.line;
         let mut inside_quotes = false;
         for c in self.contents[0..].chars() {
-            remove_symbols += 1;
-            self.col += 1;
+            remove_symbols += c.len_utf8();
+            if c == '\n' {
+                self.line += 1;
+                self.col = 1;
+            } else {
+                self.col += 1;
+            }
             if c == '"' {
                 inside_quotes = !inside_quotes;
                 continue;
@@ -55,12 +98,6 @@ impl Lexer {
                 continue;
             } else {
                 if c == ' ' || c == '\t' || c == '\r' || c == '\n' {
-                    word_start_col += 1;
-                    if c == '\n' {
-                        self.line += 1;
-                        self.col = 1;
-                        word_start_col = 1;
-                    }
                     break;
                 }
                 word.push(c);
@@ -70,31 +107,31 @@ impl Lexer {
             self.contents = self.contents[remove_symbols..].to_string();
             return Ok(());
         }
+        let raw_word = self.contents[..remove_symbols].trim_end().to_string();
         self.contents = self.contents[remove_symbols..].to_string();
         debug!("Word: `{}`", word);
         let mut found = false;
 
         for (i, token) in self.token_types.iter().enumerate() {
-            let re = Regex::new(&token.regex).unwrap();
+            let re = &self.regexes[i];
             if re.is_match(&word) {
                 debug!("Found token: {:?}", token);
+                if token.type_ == TokenKind::StackEffect {
+                    return self.lex_stack_effect(i, word_start_line, word_start_col);
+                }
                 let mut data = Data::None;
                 if token.type_ == TokenKind::Push || token.name == "call" {
                     let caps = re.captures(&word).unwrap();
                     data = Data::from_any(&caps[1]);
                 }
-                let line = self
-                    .raw_contents
-                    .lines()
-                    .nth(word_start_line - 1)
-                    .unwrap_or("");
                 self.tokens.push(Token::new(
                     i,
                     data,
                     self.file.clone(),
                     word_start_line,
                     word_start_col,
-                    line.to_string(),
+                    self.line(word_start_line),
+                    raw_word.clone(),
                 ));
                 found = true;
                 break;
@@ -102,19 +139,79 @@ impl Lexer {
         }
 
         if !found {
-            return Err(anyhow::anyhow!(
-                "No token found at line {}, col {}: \"{}\"",
-                self.line,
-                self.col,
-                word
-            ));
+            return Err(SourceError::new(
+                &self.file,
+                word_start_line,
+                word_start_col,
+                format!(
+                    "No token found at line {}, col {}: \"{}\"",
+                    word_start_line, word_start_col, word
+                ),
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// Lexes a stack effect, `( a b -- c )`, after its opening parenthesis.
+    fn lex_stack_effect(&mut self, type_: usize, line: usize, col: usize) -> Result<()> {
+        let end = match self.contents.find(')') {
+            Some(end) if self.line == line && !self.contents[..end].contains('\n') => end,
+            _ => {
+                return Err(SourceError::new(
+                    &self.file,
+                    line,
+                    col,
+                    format!("Unclosed stack effect at line {}, col {}", line, col),
+                )
+                .into())
+            }
+        };
+        let text = self.contents[..end]
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .join(" ");
+        if let Err(e) = StackEffect::parse(&text) {
+            return Err(SourceError::new(&self.file, line, col, e.to_string()).into());
         }
+        let len = self.contents[..=end].chars().count();
+        self.col += len;
+        self.contents = self.contents[end + 1..].to_string();
 
+        // The opening parenthesis and the whitespace after it were already consumed
+        let vis = self.line(line);
+        let word = vis.chars().skip(col - 1).take(len + 2).collect();
+        self.tokens.push(Token::new(
+            type_,
+            Data::from_string(text),
+            self.file.clone(),
+            line,
+            col,
+            vis,
+            word,
+        ));
         Ok(())
     }
+
+    fn line(&self, line: usize) -> Rc<str> {
+        match self.lines.get(line - 1) {
+            Some(vis) => vis.clone(),
+            None => Rc::from(""),
+        }
+    }
 }
 
 pub fn lex(contents: &str, token_types: Vec<TokenType>, file: String) -> Result<Vec<Token>> {
+    Ok(lex_with_comments(contents, token_types, file)?.0)
+}
+
+/// Lexes like [`lex`], also returning the comments in the order they appear.
+pub fn lex_with_comments(
+    contents: &str,
+    token_types: Vec<TokenType>,
+    file: String,
+) -> Result<(Vec<Token>, Vec<Comment>)> {
     let mut lexer = Lexer::new(contents, token_types, file);
     info!("Lexer created");
 
@@ -127,5 +224,62 @@ pub fn lex(contents: &str, token_types: Vec<TokenType>, file: String) -> Result<
         debug!("{:?}", token);
     }
 
-    Ok(lexer.tokens)
+    Ok((lexer.tokens, lexer.comments))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lex, lex_with_comments};
+    use crate::tokens::tokens;
+
+    fn words(source: &str) -> Vec<String> {
+        let types = tokens();
+        lex(source, types.clone(), "test.seal".to_string())
+            .unwrap()
+            .iter()
+            .map(|token| types[token.type_].name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn matches_whole_words() {
+        assert_eq!(words("(1) (2) rand-int"), ["push", "push", "rand-int"]);
+        assert_eq!(
+            words("(1) (1) = if endif"),
+            ["push", "push", "=", "if", "endif"]
+        );
+        assert!(lex("ifx", tokens(), "test.seal".to_string()).is_err());
+        assert!(lex("(1) (1) ==", tokens(), "test.seal".to_string()).is_err());
+    }
+
+    #[test]
+    fn columns() {
+        let source = "(\"héllo wörld\") . nl\n  (1)\t.\n# ünïcode\n(2)";
+        let locations = lex(source, tokens(), "test.seal".to_string())
+            .unwrap()
+            .iter()
+            .map(|token| (token.line, token.col))
+            .collect::<Vec<(usize, usize)>>();
+        assert_eq!(
+            locations,
+            [(1, 1), (1, 17), (1, 19), (2, 3), (2, 7), (4, 1)]
+        );
+    }
+
+    #[test]
+    fn comments() {
+        let source = "(\"a # b\") . # shown\n(#) .\n#(1)\n";
+        let (tokens, comments) =
+            lex_with_comments(source, tokens(), "test.seal".to_string()).unwrap();
+        let words = tokens
+            .iter()
+            .map(|token| token.word.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(words, ["(\"a # b\")", ".", "(#)", "."]);
+        let comments = comments
+            .iter()
+            .map(|comment| (comment.line, comment.col, comment.text.as_str()))
+            .collect::<Vec<(usize, usize, &str)>>();
+        assert_eq!(comments, [(1, 13, "# shown"), (3, 1, "#(1)")]);
+    }
 }
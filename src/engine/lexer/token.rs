@@ -1,9 +1,23 @@
-use super::super::{data::Data, mark::MarkList, stack::Stack, variables::Variables};
+use super::super::{
+    data::{Data, Type},
+    mark::MarkList,
+    runtime::Runtime,
+    stack::Stack,
+    variables::Variables,
+};
 
 use anyhow::Result;
+use std::rc::Rc;
 
-type TokenFunc =
-    fn(&mut Stack, &mut Stack, &mut Variables, &mut MarkList, &mut usize, Data) -> Result<()>;
+pub type TokenFunc = fn(
+    &mut Stack,
+    &mut Stack,
+    &mut Variables,
+    &mut MarkList,
+    &mut usize,
+    &mut Runtime,
+    &Data,
+) -> Result<()>;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum TokenKind {
@@ -16,6 +30,60 @@ pub enum TokenKind {
     While,
     Do,
     End,
+    Try,
+    Catch,
+    EndTry,
+    StackEffect,
+}
+
+impl TokenKind {
+    /// Whether the word opens a block: `if`, `while`, `proc` or `try`.
+    pub fn opens(&self) -> bool {
+        matches!(
+            self,
+            TokenKind::If | TokenKind::While | TokenKind::Proc | TokenKind::Try
+        )
+    }
+
+    /// Whether the word ends a block: `endif`, `end`, `ret` or `endtry`.
+    pub fn closes(&self) -> bool {
+        matches!(
+            self,
+            TokenKind::EndIf | TokenKind::End | TokenKind::ProcRet | TokenKind::EndTry
+        )
+    }
+}
+
+/// How a word changes the stack, used by the static checker.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Effect {
+    /// Pops the first number of values, then pushes the second
+    Fixed(usize, usize),
+    /// Pops a number of values and does not continue with the next token
+    Ends(usize),
+    /// Leaves the stack empty
+    Clear,
+    /// Depends on the values on the stack or on the program
+    Dynamic,
+}
+
+/// Computes the types a word pushes from the types it pops, or says why they do not fit.
+pub type TypeRule = fn(&[Type]) -> std::result::Result<Vec<Type>, String>;
+
+/// Types a word takes and leaves, used by the type checker. Popped types are in stack order.
+#[derive(Debug, Clone, Copy)]
+pub enum Signature {
+    /// Takes values of the first types, `Unknown` taking any, and pushes values of the second
+    Fixed(&'static [Type], &'static [Type]),
+    Rule(TypeRule),
+}
+
+/// Which variables a word's name refers to. The name is resolved to a slot when the program is
+/// linked, and the word runs with the slot as an int.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VariableScope {
+    Local,
+    Global,
 }
 
 #[derive(Debug, Clone)]
@@ -24,6 +92,12 @@ pub struct TokenType {
     pub name: String,
     pub regex: String,
     pub func: TokenFunc,
+    /// What the word does, shown by the language server on hover
+    pub doc: String,
+    pub effect: Effect,
+    /// Unknown types are pushed when there is none
+    pub signature: Option<Signature>,
+    pub variable: Option<VariableScope>,
 }
 
 impl TokenType {
@@ -33,28 +107,57 @@ impl TokenType {
             name: name.to_string(),
             regex: regex.to_string(),
             func,
+            doc: String::new(),
+            effect: Effect::Dynamic,
+            signature: None,
+            variable: None,
         }
     }
+
+    pub fn doc(mut self, doc: &str) -> Self {
+        self.doc = doc.to_string();
+        self
+    }
+
+    pub fn effect(mut self, effect: Effect) -> Self {
+        self.effect = effect;
+        self
+    }
+
+    pub fn signature(mut self, signature: Signature) -> Self {
+        self.signature = Some(signature);
+        self
+    }
+
+    pub fn variable(mut self, scope: VariableScope) -> Self {
+        self.variable = Some(scope);
+        self
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Token {
     pub type_: usize,
     pub data: Data,
-    pub file: String,
+    /// Shared by every token of the file
+    pub file: Rc<str>,
     pub line: usize,
     pub col: usize,
-    pub vis: String,
+    /// The source line of the token, shared by every token on it
+    pub vis: Rc<str>,
+    /// The word as written in the source, quotes included
+    pub word: String,
 }
 
 impl Token {
     pub fn new(
         type_: usize,
         data: Data,
-        file: String,
+        file: Rc<str>,
         line: usize,
         col: usize,
-        vis: String,
+        vis: Rc<str>,
+        word: String,
     ) -> Self {
         Self {
             type_,
@@ -63,30 +166,7 @@ impl Token {
             line,
             col,
             vis,
+            word,
         }
     }
-
-    pub fn get_type(&self, token_types: Vec<TokenType>) -> Result<TokenType> {
-        if self.type_ >= token_types.len() {
-            return Err(anyhow::anyhow!("Token type out of bounds: {}", self.type_));
-        }
-
-        Ok(token_types[self.type_].clone())
-    }
-
-    pub fn exec(
-        &self,
-        types: &Vec<TokenType>,
-        stack: &mut Stack,
-        return_stack: &mut Stack,
-        variables: &mut Variables,
-        marks: &mut MarkList,
-        pc: &mut usize,
-    ) -> Result<()> {
-        if self.type_ >= types.len() {
-            return Err(anyhow::anyhow!("Token type out of bounds: {}", self.type_));
-        }
-
-        (types[self.type_].func)(stack, return_stack, variables, marks, pc, self.data.clone())
-    }
 }
@@ -0,0 +1,222 @@
+use anyhow::Result;
+
+use super::data::Data;
+use super::lexer::error::SourceError;
+use super::lexer::token::{Token, TokenKind, TokenType};
+
+/// The kind of block token a closer ends, with the word it is written as.
+fn opener(closer: &TokenKind) -> Option<(TokenKind, &'static str)> {
+    match closer {
+        TokenKind::EndIf => Some((TokenKind::If, "if")),
+        TokenKind::Do => Some((TokenKind::While, "while")),
+        TokenKind::End => Some((TokenKind::Do, "do")),
+        TokenKind::ProcRet => Some((TokenKind::Proc, "proc")),
+        TokenKind::Catch => Some((TokenKind::Try, "try")),
+        TokenKind::EndTry => Some((TokenKind::Catch, "catch")),
+        _ => None,
+    }
+}
+
+/// The closer an unclosed block token is missing.
+fn closer(opener: &TokenKind) -> &'static str {
+    match opener {
+        TokenKind::If => "endif",
+        TokenKind::While => "do",
+        TokenKind::Do => "end",
+        TokenKind::Proc => "ret",
+        TokenKind::Try => "catch",
+        TokenKind::Catch => "endtry",
+        _ => unreachable!("{:?} does not open a block", opener),
+    }
+}
+
+fn error(token: &Token, message: String) -> anyhow::Error {
+    SourceError::new(&token.file, token.line, token.col, message).into()
+}
+
+/// Stores in every block token the offset of the token it jumps to, in one pass over the tokens:
+///
+/// - `if` the `endif` at `i + 1 + offset`
+/// - `do` the `end` at `i + 1 + offset`, and `end` the `while` at `i - 1 - offset`
+/// - `proc` the `ret` at `i + 1 + offset`
+/// - `try` the `catch` at `i + 1 + offset`, and `catch` the `endtry` at `i + 1 + offset`
+///
+/// Blocks are matched with a stack of the ones still open, so a closer that ends a different
+/// block than the innermost one is reported with the locations of both.
+pub fn link(types: &[TokenType], tokens: &mut [Token]) -> Result<()> {
+    // Kind and index of the block tokens that are not closed yet, innermost last
+    let mut open: Vec<(TokenKind, usize)> = Vec::new();
+    for i in 0..tokens.len() {
+        let kind = types[tokens[i].type_].type_.clone();
+        match kind {
+            _ if kind.opens() => {
+                open.push((kind, i));
+                continue;
+            }
+            TokenKind::StackEffect => {
+                let after_proc = i > 0 && types[tokens[i - 1].type_].type_ == TokenKind::Proc;
+                if !after_proc {
+                    let token = &tokens[i];
+                    return Err(error(
+                        token,
+                        format!(
+                            "Stack effect at {}:{} does not follow a proc",
+                            token.line, token.col
+                        ),
+                    ));
+                }
+                continue;
+            }
+            _ => {}
+        }
+        let Some((expected, expected_word)) = opener(&kind) else {
+            continue;
+        };
+        let start = match open.last() {
+            Some((opened, start)) if *opened == expected => *start,
+            Some((_, start)) => {
+                let (token, other) = (&tokens[i], &tokens[*start]);
+                return Err(error(
+                    token,
+                    format!(
+                        "`{}` at {}:{} does not match `{}` at {}:{}",
+                        token.word, token.line, token.col, other.word, other.line, other.col
+                    ),
+                ));
+            }
+            None => {
+                let token = &tokens[i];
+                return Err(error(
+                    token,
+                    format!(
+                        "`{}` at {}:{} has no matching `{}`",
+                        token.word, token.line, token.col, expected_word
+                    ),
+                ));
+            }
+        };
+        match kind {
+            // The `while` stays open under its `do`, `end` needs both
+            TokenKind::Do => open.push((TokenKind::Do, i)),
+            TokenKind::End => {
+                open.pop();
+                let (_, while_) = open.pop().expect("every do has a while under it");
+                tokens[start].data = Data::from_int((i - start - 1) as i64);
+                tokens[i].data = Data::from_int((i - while_ - 1) as i64);
+            }
+            TokenKind::Catch => {
+                open.pop();
+                tokens[start].data = Data::from_int((i - start - 1) as i64);
+                open.push((TokenKind::Catch, i));
+            }
+            _ => {
+                open.pop();
+                tokens[start].data = Data::from_int((i - start - 1) as i64);
+            }
+        }
+    }
+
+    if let Some((kind, start)) = open.pop() {
+        let token = &tokens[start];
+        return Err(error(
+            token,
+            format!(
+                "No matching {} token found for token at {}:{}",
+                closer(&kind),
+                token.line,
+                token.col
+            ),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::lexer::lexer::lex;
+    use crate::tokens::tokens;
+
+    fn linked(source: &str) -> Result<Vec<Token>> {
+        let mut lexed = lex(source, tokens(), "test.seal".to_string())?;
+        link(&tokens(), &mut lexed)?;
+        Ok(lexed)
+    }
+
+    fn offsets(source: &str) -> Vec<i64> {
+        linked(source)
+            .unwrap()
+            .iter()
+            .filter_map(|token| match (token.word.as_str(), &token.data) {
+                ("if" | "do" | "end" | "proc" | "try" | "catch", Data::Int(offset)) => {
+                    Some(*offset)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn link_error(source: &str) -> String {
+        linked(source).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn offsets_of_nested_blocks() {
+        assert_eq!(offsets("(1) if (2) . endif"), [2]);
+        assert_eq!(offsets("while (1) do (1) if (2) . endif end"), [5, 2, 7]);
+        assert_eq!(offsets("(f) proc ( -- ) (1) ret"), [2]);
+        assert_eq!(
+            offsets("try try catch endtry catch (1) endtry"),
+            [3, 0, 0, 1]
+        );
+    }
+
+    #[test]
+    fn linking_twice_is_stable() {
+        let source = "(0) while : (3) < do (1) + end (f) proc try catch endtry ret";
+        let mut tokens = linked(source).unwrap();
+        let first = format!("{:?}", tokens);
+        link(&crate::tokens::tokens(), &mut tokens).unwrap();
+        assert_eq!(format!("{:?}", tokens), first);
+    }
+
+    #[test]
+    fn stray_and_mismatched_closers() {
+        assert_eq!(
+            link_error("(1) . endif"),
+            "`endif` at 1:7 has no matching `if`"
+        );
+        assert_eq!(
+            link_error("(1) do end"),
+            "`do` at 1:5 has no matching `while`"
+        );
+        assert_eq!(
+            link_error("(1) if\nwhile (1) do endif end"),
+            "`endif` at 2:14 does not match `do` at 2:11"
+        );
+        assert_eq!(
+            link_error("(f) proc (1) if ret endif"),
+            "`ret` at 1:17 does not match `if` at 1:14"
+        );
+    }
+
+    #[test]
+    fn unclosed_blocks() {
+        assert_eq!(
+            link_error("(1) if (2)"),
+            "No matching endif token found for token at 1:5"
+        );
+        assert_eq!(
+            link_error("while (1)"),
+            "No matching do token found for token at 1:1"
+        );
+        assert_eq!(
+            link_error("try (1) catch"),
+            "No matching endtry token found for token at 1:9"
+        );
+        assert_eq!(
+            link_error("(1) ( a -- b )"),
+            "Stack effect at 1:5 does not follow a proc"
+        );
+    }
+}
@@ -1,18 +1,38 @@
 use anyhow::Result;
 use colored::Colorize;
 use log::{debug, info};
+use std::io::Write;
 
+use super::bytecode;
+use super::checker;
 use super::data::Data;
+use super::debugger::{self, Breakpoint, Command as DebugCommand, Debugger, Mode, StopReason};
 use super::file::File;
+use super::input::Input;
 use super::lexer::{
+    error::SourceError,
     lexer::lex,
-    token::{Token, TokenKind, TokenType},
+    token::{Token, TokenFunc, TokenKind, TokenType, VariableScope},
 };
-use super::mark::MarkList;
+use super::linker;
+use super::mark::{MarkList, StackEffect};
+use super::optimizer;
+use super::runtime::{Exit, Runtime, Thrown};
 use super::stack::Stack;
+use super::transpiler;
 use super::variables::Variables;
 use crate::args::Args;
 
+/// A token resolved for execution when the program is linked, so running it needs no lookups.
+struct Instruction {
+    func: TokenFunc,
+    /// Replaces the data of the token, the slot of a variable name. Otherwise the data is
+    /// borrowed from the token
+    operand: Option<Data>,
+    /// Running it defines a proc, whose declared effect is stored afterwards
+    defines_proc: bool,
+}
+
 pub struct Machine {
     args: Args,
     stack: Stack,
@@ -20,13 +40,18 @@ pub struct Machine {
     token_types: Vec<TokenType>,
     main_file: Option<File>,
     tokens: Vec<Token>,
+    /// One instruction per token, rebuilt whenever the tokens are linked
+    code: Vec<Instruction>,
     marks: MarkList,
     variables: Variables,
+    runtime: Runtime,
+    debugger: Debugger,
     pc: usize,
 }
 
 impl Machine {
     pub fn new(args: Args) -> Self {
+        let runtime = Runtime::from_args(&args);
         Self {
             args,
             stack: Stack::new(),
@@ -34,8 +59,11 @@ impl Machine {
             token_types: Vec::new(),
             main_file: None,
             tokens: Vec::new(),
+            code: Vec::new(),
             marks: MarkList::new(),
             variables: Variables::new(),
+            runtime,
+            debugger: Debugger::new(),
             pc: 0,
         }
     }
@@ -61,9 +89,46 @@ impl Machine {
         Ok(())
     }
 
+    /// Whether the main file is a program compiled by `pinniped build`, which is loaded with
+    /// `load_bytecode` instead of being lexed and linked.
+    pub fn is_compiled(&self) -> bool {
+        self.main_file
+            .as_ref()
+            .is_some_and(|main_file| main_file.bytecode.is_some())
+    }
+
+    /// Loads the already linked tokens of a compiled main file.
+    pub fn load_bytecode(&mut self) -> Result<()> {
+        info!("Loading compiled main file");
+        let main_file = self.main_file.as_ref().unwrap();
+        let bytes = main_file
+            .bytecode
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("{} is not compiled", main_file.path))?;
+        let mut tokens = bytecode::decode(&self.token_types, bytes)?;
+        self.tokens.append(&mut tokens);
+        self.resolve();
+
+        info!("Main file loaded");
+        Ok(())
+    }
+
+    /// Encodes the linked program, see [`bytecode::encode`].
+    pub fn bytecode(&self) -> Vec<u8> {
+        bytecode::encode(&self.token_types, &self.tokens)
+    }
+
+    /// Transpiles the linked program to C, see [`transpiler::transpile`].
+    pub fn transpile(&self) -> Result<String> {
+        transpiler::transpile(&self.token_types, &self.tokens, self.runtime.check_effects)
+    }
+
     pub fn lex(&mut self) -> Result<()> {
         info!("Lexing main file");
         let main_file = self.main_file.as_ref().unwrap();
+        if main_file.bytecode.is_some() {
+            return Err(anyhow::anyhow!("{} is already compiled", main_file.path));
+        }
         let mut tokens = lex(
             main_file.contents.as_str(),
             self.token_types.clone(),
@@ -75,281 +140,536 @@ impl Machine {
         Ok(())
     }
 
+    /// Lexes source that is not the main file, without adding it to the program.
+    pub fn lex_source(&self, source: &str, file: &str) -> Result<Vec<Token>> {
+        lex(source, self.token_types.clone(), file.to_string())
+    }
+
+    /// Returns how many blocks (`if`, `while`, `proc`, `try`) the tokens open but do not close.
+    pub fn open_blocks(&self, tokens: &[Token]) -> i64 {
+        let mut depth = 0;
+        for token in tokens {
+            let kind = &self.token_types[token.type_].type_;
+            if kind.opens() {
+                depth += 1;
+            } else if kind.closes() {
+                depth -= 1;
+            }
+        }
+        depth
+    }
+
+    /// Appends tokens to the program and links them, returning where they start. The program is
+    /// left unchanged when linking fails.
+    pub fn link_tokens(&mut self, mut tokens: Vec<Token>) -> Result<usize> {
+        let start = self.tokens.len();
+        self.tokens.append(&mut tokens);
+        if let Err(e) = self.after_lex() {
+            self.tokens.truncate(start);
+            return Err(e);
+        }
+        Ok(start)
+    }
+
+    /// Appends tokens to the program, links them and runs them. The program is left unchanged
+    /// when linking fails, and execution resumes after the new tokens when running fails.
+    pub fn run_tokens(&mut self, tokens: Vec<Token>) -> Result<()> {
+        let start = self.link_tokens(tokens)?;
+        self.pc = start;
+        let result = self.interpret();
+        self.pc = self.tokens.len();
+        if result.is_err() {
+            // Procs and try blocks the error left do not continue with the next tokens
+            self.return_stack.clear();
+            self.runtime.handlers.clear();
+            self.runtime.frames.clear();
+        }
+        result
+    }
+
+    pub fn stack(&self) -> &Stack {
+        &self.stack
+    }
+
+    pub fn return_stack(&self) -> &Stack {
+        &self.return_stack
+    }
+
+    pub fn variables(&self) -> &Variables {
+        &self.variables
+    }
+
+    pub fn input(&mut self) -> &mut Input {
+        &mut self.runtime.input
+    }
+
+    pub fn runtime(&mut self) -> &mut Runtime {
+        &mut self.runtime
+    }
+
+    pub fn debugger(&mut self) -> &mut Debugger {
+        &mut self.debugger
+    }
+
+    pub fn marks(&self) -> &MarkList {
+        &self.marks
+    }
+
+    pub fn tokens(&self) -> &[Token] {
+        &self.tokens
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// Links the block tokens of the program, see [`linker::link`].
     pub fn after_lex(&mut self) -> Result<()> {
         info!("Starting after-lexing");
+        linker::link(&self.token_types, &mut self.tokens)?;
+        self.resolve();
+        info!("After-lexing complete");
+        Ok(())
+    }
+
+    /// Rewrites the linked program to do the same with less work, see [`optimizer::optimize`].
+    pub fn optimize(&mut self) -> Result<()> {
+        info!("Optimizing tokens");
+        let tokens = std::mem::take(&mut self.tokens);
+        let count = tokens.len();
+        self.tokens = optimizer::optimize(&self.token_types, tokens)?;
+        self.resolve();
+        info!("Optimized {} tokens to {}", count, self.tokens.len());
+        Ok(())
+    }
 
-        let tokens = self.tokens.clone();
-        for (i, token) in &mut self.tokens.iter_mut().enumerate() {
-            let token_type = token.get_type(self.token_types.clone())?.type_;
-            debug!("Token: {:?}", token);
-            if token_type == TokenKind::If {
-                // Go through all the tokens and find the matching end token
-                let mut end_token = None;
-                let mut depth = 0;
-                let tokens = tokens.clone()[i + 1..].to_vec();
-                for (i, t) in tokens.iter().enumerate() {
-                    let token_type = t.get_type(self.token_types.clone())?;
-                    debug!(
-                        "Subtoken: {:?}, type: {:?}",
-                        t,
-                        t.get_type(self.token_types.clone())
-                    );
-                    if token_type.type_ == TokenKind::EndIf {
-                        if depth == 0 {
-                            end_token = Some(i);
-                            break;
-                        } else {
-                            depth -= 1;
-                        }
-                    } else if token_type.type_ == TokenKind::If {
-                        depth += 1;
+    /// Resolves every token to the function it runs, and variable names to their slots.
+    fn resolve(&mut self) {
+        self.code = self
+            .tokens
+            .iter()
+            .map(|token| {
+                let token_type = &self.token_types[token.type_];
+                let operand = token_type.variable.map(|scope| match &token.data {
+                    Data::String(name) => {
+                        let local = scope == VariableScope::Local;
+                        Data::from_int(self.variables.slot(name, local) as i64)
                     }
+                    // Not a name, which the word reports when it runs
+                    _ => Data::None,
+                });
+                Instruction {
+                    func: token_type.func,
+                    operand,
+                    defines_proc: token_type.type_ == TokenKind::Proc,
+                }
+            })
+            .collect();
+    }
+
+    /// Checks the stack effects of the linked program, and its types with `typed`, see
+    /// [`checker::check`].
+    pub fn check(&self, typed: bool) -> Vec<SourceError> {
+        checker::check(&self.token_types, &self.tokens, typed)
+    }
+
+    /// Registers every `(name) proc` up front, so procs can be called without running the file.
+    pub fn define_procs(&mut self) -> Result<()> {
+        for (name, pc) in self.proc_names()? {
+            self.marks.push(name, pc);
+            self.declare_effect(pc)?;
+        }
+        Ok(())
+    }
+
+    /// Stores the stack effect declared after the `proc` at `pc` with the mark of the proc.
+    fn declare_effect(&mut self, pc: usize) -> Result<()> {
+        if let Some(token) = self.tokens.get(pc + 1) {
+            if self.token_types[token.type_].type_ == TokenKind::StackEffect {
+                let effect = StackEffect::parse(&token.data.as_string()?)?;
+                self.marks.set_effect(pc, effect);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the name and PC of every proc whose name is a literal right before `proc`.
+    pub fn proc_names(&self) -> Result<Vec<(String, usize)>> {
+        let mut procs = Vec::new();
+        for (i, token) in self.tokens.iter().enumerate().skip(1) {
+            if self.token_types[token.type_].type_ != TokenKind::Proc {
+                continue;
+            }
+            let name = &self.tokens[i - 1];
+            if self.token_types[name.type_].name == "push" && name.data.is_string() {
+                procs.push((name.data.as_string()?, i));
+            }
+        }
+        Ok(procs)
+    }
+
+    /// Calls a proc registered with `define_procs` and runs until it returns.
+    pub fn call(&mut self, name: &str) -> Result<()> {
+        let pc = match self.marks.get_pc(name) {
+            Some(pc) => pc,
+            None => return Err(anyhow::anyhow!("Proc not found: {}", name)),
+        };
+        // Returning to the end of the program stops the interpreter
+        self.return_stack
+            .push(Data::from_int(self.tokens.len() as i64));
+        self.pc = pc + 1;
+        self.interpret()
+    }
+
+    pub fn interpret(&mut self) -> Result<()> {
+        info!("Interpreting tokens");
+
+        if self.args.debug_inter {
+            println!("Type help for the debugger commands, enter steps to the next token");
+        }
+
+        while self.pc < self.tokens.len() {
+            if self.args.debug_inter && self.debug_check().is_some() {
+                self.print_state()?;
+                self.debug_prompt()?;
+            }
+            self.interpret_step()?;
+            self.pc += 1;
+        }
+
+        info!("Interpretation complete");
+        Ok(())
+    }
+
+    /// Prints the current token with the stacks and variables for the debugger.
+    fn print_state(&self) -> Result<()> {
+        let token = &self.tokens[self.pc];
+        let token_type = &self.token_types[token.type_];
+        let data = token.data.to_string();
+        let quote = "\"".bright_black();
+        let colon = ":".bright_black();
+        let coma = ",".bright_black();
+        println!(
+            "\n{}{} {}{}{}",
+            "Line".blue().bold(),
+            colon,
+            quote,
+            token.vis,
+            quote
+        );
+        if !self.stack.is_empty() {
+            print!("{}{} ", "Stack".blue().bold(), colon);
+            for (i, element) in self.stack.elements().iter().enumerate() {
+                if i % 5 == 0 && i != 0 {
+                    print!("       ");
                 }
-                if end_token.is_none() {
-                    return Err(anyhow::anyhow!(
-                        "No matching end token found for token at {}:{}",
-                        token.line,
-                        token.col
-                    ));
+                print!("{}{}{}", quote, element, quote);
+                if i % 5 == 4 || i == self.stack.len() - 1 {
+                    println!();
+                } else {
+                    print!(", ");
                 }
-                token.data = Data::from_int(end_token.unwrap() as i32);
-            } else if token_type == TokenKind::Do {
-                // Go through all the tokens and find the matching end token
-                let mut end_token = None;
-                let mut depth = 0;
-                let tokens = tokens.clone()[i + 1..].to_vec();
-                for (i, t) in tokens.iter().enumerate() {
-                    let token_type = t.get_type(self.token_types.clone())?;
-                    debug!(
-                        "Subtoken: {:?}, type: {:?}",
-                        t,
-                        t.get_type(self.token_types.clone())
-                    );
-                    if token_type.type_ == TokenKind::End {
-                        if depth == 0 {
-                            end_token = Some(i);
-                            break;
-                        } else {
-                            depth -= 1;
-                        }
-                    } else if token_type.type_ == TokenKind::While {
-                        depth += 1;
-                    }
+            }
+        }
+        if !self.return_stack.is_empty() {
+            print!("{}{} ", "Ret stack".blue().bold(), colon);
+            for (i, element) in self.return_stack.elements().iter().enumerate() {
+                if i % 5 == 0 && i != 0 {
+                    print!("         ");
                 }
-                if end_token.is_none() {
-                    return Err(anyhow::anyhow!(
-                        "No matching end token found for token at {}:{}",
-                        token.line,
-                        token.col
-                    ));
+                print!("{}{}{}", quote, element, quote);
+                if i % 5 == 4 || i == self.return_stack.len() - 1 {
+                    println!();
+                } else {
+                    print!(", ");
                 }
-                token.data = Data::from_int(end_token.unwrap() as i32);
-            } else if token_type == TokenKind::End {
-                // Go back through ass the tokens and find a while token
-                let mut while_token = None;
-                let mut depth = 0;
-                let mut tokens = tokens.clone()[..i].to_vec();
-                tokens.reverse();
-                for (i, t) in tokens.iter().enumerate() {
-                    let token_type = t.get_type(self.token_types.clone())?;
-                    let token_kind = token_type.type_.clone();
-                    debug!("Subtoken: {:?}, type: {:?}", "", token_type.type_);
-                    if token_type.type_ == TokenKind::While {
-                        if depth == 0 {
-                            while_token = Some(i);
-                            break;
-                        } else {
-                            depth -= 1;
-                        }
-                    } else if token_kind == TokenKind::End {
-                        depth += 1;
-                    }
+            }
+        }
+        let local_variables = self.variables.locals();
+        if !local_variables.is_empty() {
+            print!("{}{} ", "Loc vars".blue().bold(), colon);
+            for (i, variable) in local_variables.iter().enumerate() {
+                if i % 5 == 0 && i != 0 {
+                    print!("        ");
                 }
-                if while_token.is_none() {
-                    return Err(anyhow::anyhow!(
-                        "No matching end token found for token at {}:{}",
-                        token.line,
-                        token.col
-                    ));
+                print!("{}{}{}", quote, variable, quote);
+                if i % 5 == 4 || i == local_variables.len() - 1 {
+                    println!();
+                } else {
+                    print!(", ");
                 }
-                token.data = Data::from_int(while_token.unwrap() as i32)
-            } else if token_type == TokenKind::Proc {
-                // Go through all the tokens and find the matching ret token
-                let mut ret_token = None;
-                let mut depth = 0;
-                let tokens = tokens.clone()[i + 1..].to_vec();
-                for (i, t) in tokens.iter().enumerate() {
-                    let token_type = t.get_type(self.token_types.clone())?;
-                    debug!(
-                        "Subtoken: {:?}, type: {:?}",
-                        t,
-                        t.get_type(self.token_types.clone())
-                    );
-                    if token_type.type_ == TokenKind::ProcRet {
-                        if depth == 0 {
-                            ret_token = Some(i);
-                            break;
-                        } else {
-                            depth -= 1;
-                        }
-                    } else if token_type.type_ == TokenKind::Proc {
-                        depth += 1;
-                    }
+            }
+        }
+        let global_variables = self.variables.globals();
+        if !global_variables.is_empty() {
+            print!("{}{} ", "Glo vars".blue().bold(), colon);
+            for (i, variable) in global_variables.iter().enumerate() {
+                if i % 5 == 0 && i != 0 {
+                    print!("        ");
                 }
-                if ret_token.is_none() {
-                    return Err(anyhow::anyhow!(
-                        "No matching ret token found for token at {}:{}",
-                        token.line,
-                        token.col
-                    ));
+                print!("{}{}{}", quote, variable, quote);
+                if i % 5 == 4 || i == global_variables.len() - 1 {
+                    println!();
+                } else {
+                    print!(", ");
                 }
-                token.data = Data::from_int(ret_token.unwrap() as i32);
             }
         }
-
-        info!("After-lexing complete");
+        // println!("PC: {:<5}; Token: \"{}\"; Data: \"{}\"", self.pc, token_type.name, data);
+        print!("{}{} {}{} ", "PC".blue().bold(), colon, self.pc, coma);
+        print!(
+            "{}{} {}{}{}{} ",
+            "Token".blue().bold(),
+            colon,
+            quote,
+            token_type.name,
+            quote,
+            coma
+        );
+        println!(
+            "{}{} {}{}{}",
+            "Data".blue().bold(),
+            colon,
+            quote,
+            data,
+            quote
+        );
         Ok(())
     }
 
-    pub fn interpret(&mut self) -> Result<()> {
-        info!("Interpreting tokens");
+    fn print_stack(name: &str, stack: &Stack) {
+        print!("{}{} ", name.blue().bold(), ":".bright_black());
+        if stack.is_empty() {
+            println!("empty");
+            return;
+        }
+        let elements = stack
+            .elements()
+            .iter()
+            .map(|element| format!("\"{}\"", element))
+            .collect::<Vec<String>>();
+        println!("{}", elements.join(", "));
+    }
 
-        if self.args.debug_inter {
-            println!("Press enter to go to the next token");
+    fn print_variables(&self) {
+        for (name, variables) in [
+            ("Loc vars", self.variables.locals()),
+            ("Glo vars", self.variables.globals()),
+        ] {
+            let variables = variables
+                .iter()
+                .map(|variable| format!("\"{}\"", variable))
+                .collect::<Vec<String>>();
+            println!(
+                "{}{} {}",
+                name.blue().bold(),
+                ":".bright_black(),
+                variables.join(", ")
+            );
         }
+    }
+
+    /// Resolves a breakpoint on a line number or at the start of a proc.
+    pub fn breakpoint(&self, target: &str) -> Result<Breakpoint> {
+        if let Ok(line) = target.parse::<usize>() {
+            return Ok(Breakpoint::Line(line));
+        }
+        match self
+            .proc_names()?
+            .into_iter()
+            .find(|(name, _)| name == target)
+        {
+            Some((name, pc)) => Ok(Breakpoint::Proc { name, pc }),
+            None => Err(anyhow::anyhow!("Proc not found: {}", target)),
+        }
+    }
 
+    /// Name of the proc whose body contains `pc`, if any.
+    pub fn proc_at(&self, pc: usize) -> Result<Option<String>> {
+        for (name, start) in self.proc_names()?.into_iter().rev() {
+            // The linker stored where the ret of the proc is
+            let ret = start + 1 + self.tokens[start].data.as_int()? as usize;
+            if start < pc && pc <= ret {
+                return Ok(Some(name));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Asks the debugger whether to stop before the token at the current PC.
+    pub fn debug_check(&mut self) -> Option<StopReason> {
+        let token = self.tokens.get(self.pc)?;
+        self.debugger
+            .check(self.pc, token.line, self.return_stack.len())
+    }
+
+    /// Runs the current token and continues until the debugger stops before another one.
+    /// Returns `None` once the program ran to the end.
+    pub fn resume(&mut self) -> Result<Option<StopReason>> {
         while self.pc < self.tokens.len() {
-            if self.args.debug_inter {
-                let token = &self.tokens[self.pc];
-                let token_type = token.get_type(self.token_types.clone())?;
-                let data = token.data.to_string();
-                let quote = "\"".bright_black();
-                let colon = ":".bright_black();
-                let coma = ",".bright_black();
-                println!(
-                    "\n{}{} {}{}{}",
-                    "Line".blue().bold(),
-                    colon,
-                    quote,
-                    token.vis,
-                    quote
-                );
-                if self.stack.len() != 0 {
-                    print!("{}{} ", "Stack".blue().bold(), colon);
-                    for (i, element) in self.stack.elements().iter().enumerate() {
-                        if i % 5 == 0 && i != 0 {
-                            print!("       ");
-                        }
-                        print!("{}{}{}", quote, element.to_string(), quote);
-                        if i % 5 == 4 || i == self.stack.len() - 1 {
-                            println!();
-                        } else {
-                            print!(", ");
-                        }
+            self.interpret_step()?;
+            self.pc += 1;
+            if let Some(reason) = self.debug_check() {
+                return Ok(Some(reason));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Adds a breakpoint on a line number or at the start of a proc.
+    fn add_breakpoint(&mut self, target: &str) -> Result<()> {
+        let breakpoint = self.breakpoint(target)?;
+        println!(
+            "Breakpoint {}: {}",
+            self.debugger.breakpoints.len(),
+            breakpoint
+        );
+        self.debugger.breakpoints.push(breakpoint);
+        Ok(())
+    }
+
+    /// Reads debugger commands until one of them resumes the program.
+    fn debug_prompt(&mut self) -> Result<()> {
+        loop {
+            print!("{} ", "(debug)".bright_black());
+            std::io::stdout().flush()?;
+            let command = match DebugCommand::parse(&self.runtime.input.read_command()?) {
+                Ok(command) => command,
+                Err(e) => {
+                    println!("{}", e);
+                    continue;
+                }
+            };
+            let depth = self.return_stack.len();
+            match command {
+                DebugCommand::Step => self.debugger.mode = Mode::Step,
+                DebugCommand::StepOver => self.debugger.mode = Mode::StepOver(depth),
+                DebugCommand::Finish => self.debugger.mode = Mode::Finish(depth),
+                DebugCommand::Continue => self.debugger.mode = Mode::Continue,
+                DebugCommand::Break(target) => {
+                    if let Err(e) = self.add_breakpoint(&target) {
+                        println!("{}", e);
                     }
+                    continue;
                 }
-                if self.return_stack.len() != 0 {
-                    print!("{}{} ", "Ret stack".blue().bold(), colon);
-                    for (i, element) in self.return_stack.elements().iter().enumerate() {
-                        if i % 5 == 0 && i != 0 {
-                            print!("         ");
-                        }
-                        print!("{}{}{}", quote, element.to_string(), quote);
-                        if i % 5 == 4 || i == self.return_stack.len() - 1 {
-                            println!();
-                        } else {
-                            print!(", ");
-                        }
+                DebugCommand::Delete(n) => {
+                    if n < self.debugger.breakpoints.len() {
+                        println!("Deleted breakpoint {}", self.debugger.breakpoints.remove(n));
+                    } else {
+                        println!("No breakpoint {}", n);
                     }
+                    continue;
                 }
-                let local_variables = self.variables.locals();
-                if local_variables.len() != 0 {
-                    print!("{}{} ", "Loc vars".blue().bold(), colon);
-                    for (i, variable) in local_variables.iter().enumerate() {
-                        if i % 5 == 0 && i != 0 {
-                            print!("        ");
-                        }
-                        print!("{}{}{}", quote, variable.to_string(), quote);
-                        if i % 5 == 4 || i == local_variables.len() - 1 {
-                            println!();
-                        } else {
-                            print!(", ");
-                        }
+                DebugCommand::Breakpoints => {
+                    for (i, breakpoint) in self.debugger.breakpoints.iter().enumerate() {
+                        println!("{}: {}", i, breakpoint);
                     }
+                    continue;
                 }
-                let global_variables = self.variables.globals();
-                if global_variables.len() != 0 {
-                    print!("{}{} ", "Glo vars".blue().bold(), colon);
-                    for (i, variable) in global_variables.iter().enumerate() {
-                        if i % 5 == 0 && i != 0 {
-                            print!("        ");
-                        }
-                        print!("{}{}{}", quote, variable.to_string(), quote);
-                        if i % 5 == 4 || i == global_variables.len() - 1 {
-                            println!();
-                        } else {
-                            print!(", ");
-                        }
+                DebugCommand::Print(name) => {
+                    match self
+                        .variables
+                        .get(&name, true)
+                        .or_else(|| self.variables.get(&name, false))
+                    {
+                        Some(value) => println!("{} = \"{}\"", name, value),
+                        None => println!("Variable not found: {}", name),
                     }
+                    continue;
+                }
+                DebugCommand::Stack => {
+                    Self::print_stack("Stack", &self.stack);
+                    continue;
+                }
+                DebugCommand::ReturnStack => {
+                    Self::print_stack("Ret stack", &self.return_stack);
+                    continue;
+                }
+                DebugCommand::Vars => {
+                    self.print_variables();
+                    continue;
                 }
-                // println!("PC: {:<5}; Token: \"{}\"; Data: \"{}\"", self.pc, token_type.name, data);
-                print!("{}{} {}{} ", "PC".blue().bold(), colon, self.pc, coma);
-                print!(
-                    "{}{} {}{}{}{} ",
-                    "Token".blue().bold(),
-                    colon,
-                    quote,
-                    token_type.name,
-                    quote,
-                    coma
-                );
-                println!(
-                    "{}{} {}{}{}",
-                    "Data".blue().bold(),
-                    colon,
-                    quote,
-                    data,
-                    quote
-                );
-                let mut input = String::new();
-                std::io::stdin().read_line(&mut input)?;
+                DebugCommand::Help => {
+                    println!("{}", debugger::HELP);
+                    continue;
+                }
+                DebugCommand::Quit => std::process::exit(0),
             }
-            self.interpret_step()?;
-            self.pc += 1;
+            return Ok(());
         }
-
-        info!("Interpretation complete");
-        Ok(())
     }
 
     fn interpret_step(&mut self) -> Result<()> {
-        if self.pc >= self.tokens.len() {
+        let pc = self.pc;
+        let (Some(instruction), Some(token)) = (self.code.get(pc), self.tokens.get(pc)) else {
             return Err(anyhow::anyhow!("Program counter out of bounds"));
-        }
-        let token = &self.tokens[self.pc];
+        };
+        let defines_proc = instruction.defines_proc;
 
         debug!("Interpreting token: {:?}", token);
-        match token.exec(
-            &self.token_types,
+        match (instruction.func)(
             &mut self.stack,
             &mut self.return_stack,
             &mut self.variables,
             &mut self.marks,
             &mut self.pc,
+            &mut self.runtime,
+            instruction.operand.as_ref().unwrap_or(&token.data),
         ) {
             Ok(_) => {}
+            Err(e) if e.is::<Exit>() => return Err(e),
             Err(e) => {
+                // Hand the error to the innermost try block if there is one
+                if let Some(handler) = self.runtime.handlers.pop() {
+                    debug!("Caught error: {}", e);
+                    self.stack.truncate(handler.depth);
+                    self.return_stack.truncate(handler.return_depth);
+                    self.runtime
+                        .frames
+                        .retain(|frame| frame.return_depth <= handler.return_depth);
+                    self.stack.push(Data::from_string(format!(
+                        "{}:{}:{}",
+                        token.file, token.line, token.col
+                    )));
+                    match e.downcast::<Thrown>() {
+                        Ok(thrown) => self.stack.push(thrown.0),
+                        Err(e) => self.stack.push(Data::from_string(e.to_string())),
+                    }
+                    self.pc = handler.catch_pc;
+                    return Ok(());
+                }
                 return Err(anyhow::anyhow!(
                     "Error interpreting token at {}:{}:{}: {}: \"{}\"",
                     token.file.to_string().blue().bold(),
                     token.line.to_string().bright_black().bold(),
                     token.col.to_string().bright_black().bold(),
                     e.to_string().red().bold(),
-                    token.get_type(self.token_types.clone())?.name
+                    self.token_types[token.type_].name
                 ));
             }
         };
 
+        if defines_proc {
+            self.declare_effect(pc)?;
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+impl Machine {
+    /// A machine with every word registered and the default arguments.
+    pub fn for_tests() -> Self {
+        let mut machine = Machine::new(Args::default());
+        machine.register_tokens(crate::tokens::tokens());
+        machine
+    }
+
+    /// A machine for tests with `source` lexed and linked as `test.seal`.
+    pub fn linked(source: &str) -> Self {
+        let mut machine = Machine::for_tests();
+        let lexed = machine.lex_source(source, "test.seal").unwrap();
+        machine.link_tokens(lexed).unwrap();
+        machine
+    }
+}
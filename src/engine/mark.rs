@@ -1,18 +1,109 @@
+use anyhow::Result;
+
+use super::data::Type;
+
+/// A value in a stack effect, `name` or `name:type`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Slot {
+    pub name: String,
+    /// `Unknown` when no type is declared
+    pub type_: Type,
+}
+
+impl Slot {
+    pub fn parse(text: &str) -> Result<Self> {
+        match text.split_once(':') {
+            Some((name, type_)) => Ok(Self {
+                name: name.to_string(),
+                type_: Type::parse(type_)?,
+            }),
+            None => Ok(Self {
+                name: text.to_string(),
+                type_: Type::Unknown,
+            }),
+        }
+    }
+}
+
+impl std::fmt::Display for Slot {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.type_ {
+            Type::Unknown => write!(f, "{}", self.name),
+            type_ => write!(f, "{}:{}", self.name, type_),
+        }
+    }
+}
+
+/// Stack effect declared after a `proc`, as in `(add3) proc ( a b c -- sum )`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackEffect {
+    pub inputs: Vec<Slot>,
+    pub outputs: Vec<Slot>,
+}
+
+impl StackEffect {
+    /// Parses the values between the parentheses, `a:int b c -- sum`.
+    pub fn parse(text: &str) -> Result<Self> {
+        let words = text.split_whitespace().collect::<Vec<&str>>();
+        let separator = match words.iter().position(|word| *word == "--") {
+            Some(separator) if words.iter().filter(|word| **word == "--").count() == 1 => separator,
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Stack effect must separate inputs and outputs with one --: ( {} )",
+                    text
+                ))
+            }
+        };
+        let slots = |words: &[&str]| {
+            words
+                .iter()
+                .map(|word| Slot::parse(word))
+                .collect::<Result<Vec<Slot>>>()
+        };
+        Ok(Self {
+            inputs: slots(&words[..separator])?,
+            outputs: slots(&words[separator + 1..])?,
+        })
+    }
+}
+
+impl std::fmt::Display for StackEffect {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "(")?;
+        for input in &self.inputs {
+            write!(f, " {}", input)?;
+        }
+        write!(f, " --")?;
+        for output in &self.outputs {
+            write!(f, " {}", output)?;
+        }
+        write!(f, " )")
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Mark {
     pub name: String,
     pub pc: usize,
+    pub effect: Option<StackEffect>,
 }
 
 impl std::fmt::Display for Mark {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}: {}", self.name, self.pc)
+        match &self.effect {
+            Some(effect) => write!(f, "{} {}: {}", self.name, effect, self.pc),
+            None => write!(f, "{}: {}", self.name, self.pc),
+        }
     }
 }
 
 impl Mark {
     pub fn new(name: String, pc: usize) -> Self {
-        Self { name, pc }
+        Self {
+            name,
+            pc,
+            effect: None,
+        }
     }
 }
 
@@ -21,6 +112,12 @@ pub struct MarkList {
     marks: Vec<Mark>,
 }
 
+impl Default for MarkList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl MarkList {
     pub fn new() -> Self {
         Self { marks: Vec::new() }
@@ -49,6 +146,22 @@ impl MarkList {
     pub fn get_pc(&self, name: &str) -> Option<usize> {
         self.get(name).map(|m| m.pc)
     }
+
+    pub fn marks(&self) -> &Vec<Mark> {
+        &self.marks
+    }
+
+    /// Returns the latest mark made at `pc`.
+    pub fn at(&self, pc: usize) -> Option<&Mark> {
+        self.marks.iter().rev().find(|m| m.pc == pc)
+    }
+
+    /// Declares the stack effect of the latest mark made at `pc`.
+    pub fn set_effect(&mut self, pc: usize, effect: StackEffect) {
+        if let Some(mark) = self.marks.iter_mut().rev().find(|m| m.pc == pc) {
+            mark.effect = Some(effect);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -65,9 +178,30 @@ mod tests {
     fn test_mark_list() {
         let mut mark_list = MarkList::new();
         mark_list.push("test".to_string(), 0);
-        assert_eq!(mark_list.exists("test"), true);
+        assert!(mark_list.exists("test"));
         assert_eq!(mark_list.get_pc("test"), Some(0));
         assert_eq!(mark_list.get_pc("test2"), None);
         assert_eq!(mark_list.pop(), Some(Mark::new("test".to_string(), 0)));
     }
+
+    #[test]
+    fn test_stack_effect() {
+        let effect = StackEffect::parse(" a b:int  c -- sum").unwrap();
+        assert_eq!(effect.inputs.len(), 3);
+        assert_eq!(effect.inputs[1].type_, Type::Int);
+        assert_eq!(effect.outputs[0].name, "sum");
+        assert_eq!(effect.to_string(), "( a b:int c -- sum )");
+        assert!(StackEffect::parse("a:text --").is_err());
+        assert_eq!(StackEffect::parse("--").unwrap().to_string(), "( -- )");
+        assert!(StackEffect::parse("a b").is_err());
+        assert!(StackEffect::parse("a -- b -- c").is_err());
+
+        let mut mark_list = MarkList::new();
+        mark_list.push("add3".to_string(), 4);
+        mark_list.set_effect(4, effect);
+        assert_eq!(
+            mark_list.at(4).unwrap().to_string(),
+            "add3 ( a b:int c -- sum ): 4"
+        );
+    }
 }
@@ -1,7 +1,16 @@
+pub mod bytecode;
+pub mod checker;
 pub mod data;
+pub mod debugger;
 pub mod file;
+pub mod input;
 pub mod lexer;
+pub mod linker;
 pub mod machine;
 pub mod mark;
+pub mod optimizer;
+pub mod random;
+pub mod runtime;
 pub mod stack;
+pub mod transpiler;
 pub mod variables;
@@ -0,0 +1,305 @@
+//! Peephole optimizer run with `-O` on linked programs.
+//!
+//! The rewrites only ever look at words that run one after the other: every jump lands right
+//! after a block token or a call, so no jump can land inside a rewritten sequence. Once the words
+//! are rewritten the program is linked again, which gives the block tokens their new offsets.
+use anyhow::Result;
+
+use super::data::Data;
+use super::lexer::token::{Effect, Token, TokenKind, TokenType};
+use super::linker;
+use super::mark::MarkList;
+use super::runtime::Runtime;
+use super::stack::Stack;
+use super::variables::Variables;
+
+/// Words that only compute their result from the values they pop, so they can run ahead of time.
+const BINARY: &[&str] = &["+", "-", "*", "/", "%", "^", "=", "<", ">"];
+const UNARY: &[&str] = &["~", "!"];
+
+struct Optimizer<'a> {
+    types: &'a [TokenType],
+    code: Vec<Token>,
+    /// How many values the stack holds at least after every token of `code`, if the token
+    /// before it ran
+    depths: Vec<usize>,
+}
+
+impl<'a> Optimizer<'a> {
+    fn word(&self, token: &Token) -> &'a str {
+        &self.types[token.type_].name
+    }
+
+    fn type_index(&self, name: &str) -> usize {
+        self.types
+            .iter()
+            .position(|token_type| token_type.name == name)
+            .expect("the optimizer only emits registered words")
+    }
+
+    /// The value a token pushes if it always pushes the same one. Strings can refer to variables.
+    fn constant<'t>(&self, token: &'t Token) -> Option<&'t Data> {
+        match &token.data {
+            Data::String(s) if s.contains("{<") => None,
+            data if self.word(token) == "push" => Some(data),
+            _ => None,
+        }
+    }
+
+    /// Least stack depth before the last `n` tokens.
+    fn depth_before(&self, n: usize) -> usize {
+        let len = self.depths.len();
+        if len > n {
+            self.depths[len - n - 1]
+        } else {
+            0
+        }
+    }
+
+    fn emit(&mut self, token: Token) {
+        let token_type = &self.types[token.type_];
+        let before = self.depths.last().copied().unwrap_or(0);
+        let depth = match (&token_type.type_, token_type.effect) {
+            (TokenKind::Function | TokenKind::Push, Effect::Fixed(pops, pushes)) => {
+                before.max(pops) - pops + pushes
+            }
+            // Block tokens are where jumps land, with any depth
+            _ => 0,
+        };
+        self.code.push(token);
+        self.depths.push(depth);
+        while self.rewrite() {}
+    }
+
+    /// Replaces the last `n` tokens with `tokens`.
+    fn replace(&mut self, n: usize, tokens: Vec<Token>) {
+        let len = self.code.len() - n;
+        self.code.truncate(len);
+        self.depths.truncate(len);
+        for token in tokens {
+            self.emit(token);
+        }
+    }
+
+    /// A token for `word` at the location of `at`.
+    fn token(&self, word: &str, data: Data, at: &Token) -> Token {
+        let source = match word {
+            "push" => format!("({})", data),
+            _ => word.to_string(),
+        };
+        Token::new(
+            self.type_index(word),
+            data,
+            at.file.clone(),
+            at.line,
+            at.col,
+            at.vis.clone(),
+            source,
+        )
+    }
+
+    /// Runs a word on constants, `None` if it fails, which has to happen when the program runs.
+    fn fold(&self, op: &Token, values: &[&Data]) -> Option<Data> {
+        let mut stack = Stack::new();
+        for value in values {
+            stack.push((*value).clone());
+        }
+        let mut pc = 0;
+        (self.types[op.type_].func)(
+            &mut stack,
+            &mut Stack::new(),
+            &mut Variables::new(),
+            &mut MarkList::new(),
+            &mut pc,
+            &mut Runtime::new(),
+            &op.data,
+        )
+        .ok()?;
+        match stack.len() {
+            1 => stack.pop().ok(),
+            _ => None,
+        }
+    }
+
+    /// Rewrites the end of the code once, returning whether anything changed.
+    fn rewrite(&mut self) -> bool {
+        let len = self.code.len();
+        let last = |n: usize| &self.code[len - n];
+
+        // (2) (3) +  ->  (5)
+        if len >= 3 && BINARY.contains(&self.word(last(1))) {
+            if let (Some(b), Some(a)) = (self.constant(last(3)), self.constant(last(2))) {
+                if let Some(result) = self.fold(last(1), &[b, a]) {
+                    let token = self.token("push", result, last(3));
+                    self.replace(3, vec![token]);
+                    return true;
+                }
+            }
+        }
+        // (3) ~  ->  (-3)
+        if len >= 2 && UNARY.contains(&self.word(last(1))) {
+            if let Some(a) = self.constant(last(2)) {
+                if let Some(result) = self.fold(last(1), &[a]) {
+                    let token = self.token("push", result, last(2));
+                    self.replace(2, vec![token]);
+                    return true;
+                }
+            }
+        }
+        if len < 2 {
+            return false;
+        }
+        match (self.word(last(2)), self.word(last(1))) {
+            // (1) +  ->  inc, failing where `+` did
+            ("push", "+") if matches!(last(2).data, Data::Int(1)) => {
+                let token = self.token("inc", Data::None, last(1));
+                self.replace(2, vec![token]);
+                true
+            }
+            // Only when the values are certainly there, so neither word could have failed
+            ("swp", "swp") if self.depth_before(2) >= 2 => {
+                self.replace(2, Vec::new());
+                true
+            }
+            (":", ".") if self.depth_before(2) >= 1 => {
+                let token = self.token(",", Data::None, last(2));
+                // `,` leaves the value that `:` duplicated
+                self.replace(2, vec![token]);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Removes the words after every `quit`, up to the next block token. Nothing jumps to them, so
+/// they never run. The name of a proc right before it is kept so the proc is still found.
+fn drop_after_quit(types: &[TokenType], tokens: Vec<Token>) -> Vec<Token> {
+    let mut kept: Vec<Token> = Vec::with_capacity(tokens.len());
+    let mut dead = false;
+    let mut tokens = tokens.into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        let token_type = &types[token.type_];
+        let straight = matches!(token_type.type_, TokenKind::Function | TokenKind::Push);
+        if !straight {
+            dead = false;
+        }
+        let names_proc = tokens
+            .peek()
+            .is_some_and(|next| types[next.type_].type_ == TokenKind::Proc);
+        if dead && !names_proc {
+            continue;
+        }
+        dead = token_type.name == "quit";
+        kept.push(token);
+    }
+    kept
+}
+
+/// Optimizes linked tokens and links them again:
+///
+/// - constant words are computed ahead of time, `(2) (3) +` becomes `(5)`
+/// - `(1) +` becomes `inc`
+/// - `swp swp` is removed and `: .` becomes `,` where the stack certainly holds enough values
+/// - words after `quit` that can never run are removed
+///
+/// Words fail where they did with the same messages, only an uncaught error from `inc` names it
+/// instead of `+`. Programs that use `here` see their own PCs, so they are left as they are.
+pub fn optimize(types: &[TokenType], tokens: Vec<Token>) -> Result<Vec<Token>> {
+    if tokens.iter().any(|token| types[token.type_].name == "here") {
+        return Ok(tokens);
+    }
+    let mut optimizer = Optimizer {
+        types,
+        code: Vec::with_capacity(tokens.len()),
+        depths: Vec::with_capacity(tokens.len()),
+    };
+    for token in drop_after_quit(types, tokens) {
+        optimizer.emit(token);
+    }
+    let mut code = optimizer.code;
+    linker::link(types, &mut code)?;
+    Ok(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::machine::Machine;
+    use crate::engine::runtime::Captured;
+
+    /// Words of the optimized program.
+    fn words(source: &str) -> Vec<String> {
+        let mut machine = Machine::linked(source);
+        machine.optimize().unwrap();
+        machine
+            .tokens()
+            .iter()
+            .map(|token| token.word.clone())
+            .collect()
+    }
+
+    /// Output, final stack and error of running the program.
+    fn run(source: &str, optimize: bool) -> String {
+        let mut machine = Machine::linked(source);
+        let output = Captured::default();
+        machine.runtime().output = Box::new(output.clone());
+        if optimize {
+            machine.optimize().unwrap();
+        }
+        let result = machine.interpret();
+        format!(
+            "{}\nstack: {}\nresult: {:?}",
+            String::from_utf8_lossy(&output.take()),
+            machine.stack(),
+            result.map_err(|e| e.to_string())
+        )
+    }
+
+    #[test]
+    fn folds_constants() {
+        assert_eq!(words("(2) (3) + (4) * ."), ["(20)", "."]);
+        assert_eq!(words("(3) ~ (1.5) (2.0) / ."), ["(-3)", "(0.75)", "."]);
+        assert_eq!(words("(1) (2) < ! ."), ["(true)", "."]);
+        // Failing words are left to fail when the program runs
+        assert_eq!(words("(1) (0) / ."), ["(1)", "(0)", "/", "."]);
+        assert_eq!(
+            words("(4611686018427387904) (2) * ."),
+            ["(4611686018427387904)", "(2)", "*", "."]
+        );
+        assert_eq!(words("(a) (2) * ."), ["(a)", "(2)", "*", "."]);
+        assert_eq!(
+            words("(\"{<x}\") (\"{<x}\") = ."),
+            ["(\"{<x}\")", "(\"{<x}\")", "=", "."]
+        );
+    }
+
+    #[test]
+    fn rewrites_words() {
+        assert_eq!(words("<(n) (1) + ."), ["<(n)", "inc", "."]);
+        assert_eq!(words("(1) (2) swp swp . ."), ["(1)", "(2)", ".", "."]);
+        assert_eq!(words("(1) : . nl"), ["(1)", ",", "nl"]);
+        // The stack might be too short for the first pair, so it has to fail as written
+        assert_eq!(words("swp swp : ."), ["swp", "swp", ","]);
+        assert_eq!(
+            words("(1) . quit (2) . (f) proc ret"),
+            ["(1)", ".", "quit", "(f)", "proc", "ret"]
+        );
+        assert_eq!(words("here (2) (3) +"), ["here", "(2)", "(3)", "+"]);
+    }
+
+    #[test]
+    fn same_behaviour_as_unoptimized() {
+        for source in [
+            "(0) while : (2) (3) * > do (1) + : . nl end .",
+            "(sq) proc ( n:int -- n:int ) : * ret (2) (1) + {sq} . (1) (0) > if (7) . endif",
+            "(1) (2) swp swp - . (3) : . (4) (5) < if quit (6) . endif (7) .",
+            "try (a) (1) + catch . . endtry (2) (1) + .",
+            "try (2.5) (1) + catch . endtry",
+            "(f) proc (1) (1) + ret {f} {f} + . quit (2) .",
+            "(0) >(i) while <(i) (5) > do <(i) (1) + >(i) end <(i) .",
+            "(1) (0) (2) ^ (1) (3) - ^ .",
+        ] {
+            assert_eq!(run(source, true), run(source, false), "{}", source);
+        }
+    }
+}
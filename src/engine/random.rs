@@ -0,0 +1,132 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+/// SplitMix64 pseudo-random number generator. Not cryptographically secure, but fast and fully
+/// deterministic for a given seed.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Self::from_os()
+    }
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// Seeds the generator from the per-process random keys the OS gives to `RandomState`.
+    pub fn from_os() -> Self {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_nanos())
+                .unwrap_or(0),
+        );
+        Self::new(hasher.finish())
+    }
+
+    pub fn seed(&mut self, seed: u64) {
+        self.state = seed;
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a float in `[0, 1)`.
+    pub fn next_float(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Returns an integer in `[0, bound)`. `bound` must not be zero.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        // Reject the values that would make the modulo biased
+        let zone = u64::MAX - u64::MAX % bound;
+        loop {
+            let value = self.next_u64();
+            if value < zone {
+                return value % bound;
+            }
+        }
+    }
+
+    /// Returns an integer in `[low, high]`.
+    pub fn range_int(&mut self, low: i64, high: i64) -> i64 {
+        let span = (high as i128 - low as i128 + 1) as u128;
+        if span > u64::MAX as u128 {
+            // The full i64 range, every value is fair
+            return self.next_u64() as i64;
+        }
+        (low as i128 + self.below(span as u64) as i128) as i64
+    }
+
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = self.below(i as u64 + 1) as usize;
+            items.swap(i, j);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rng;
+
+    #[test]
+    fn same_seed_same_sequence() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+    }
+
+    #[test]
+    fn reseed() {
+        let mut rng = Rng::new(7);
+        let first = rng.next_u64();
+        rng.next_u64();
+        rng.seed(7);
+        assert_eq!(rng.next_u64(), first);
+    }
+
+    #[test]
+    fn next_float() {
+        let mut rng = Rng::new(1);
+        for _ in 0..1000 {
+            let f = rng.next_float();
+            assert!((0.0..1.0).contains(&f));
+        }
+    }
+
+    #[test]
+    fn range_int() {
+        let mut rng = Rng::new(3);
+        for _ in 0..1000 {
+            let i = rng.range_int(-2, 2);
+            assert!((-2..=2).contains(&i));
+        }
+        assert_eq!(rng.range_int(5, 5), 5);
+        rng.range_int(i64::MIN, i64::MAX);
+    }
+
+    #[test]
+    fn shuffle() {
+        let mut rng = Rng::new(9);
+        let mut items = (0..20).collect::<Vec<i32>>();
+        rng.shuffle(&mut items);
+        let mut sorted = items.clone();
+        sorted.sort();
+        assert_eq!(sorted, (0..20).collect::<Vec<i32>>());
+    }
+}
@@ -0,0 +1,126 @@
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+use std::time::Instant;
+
+use super::data::Data;
+use super::input::Input;
+use super::mark::StackEffect;
+use super::random::Rng;
+use crate::args::Args;
+
+/// An active `try` block, registered when `try` runs and dropped at its `catch`.
+#[derive(Debug, Clone)]
+pub struct Handler {
+    /// PC of the matching `catch` token
+    pub catch_pc: usize,
+    /// Stack depths to unwind to when an error is caught
+    pub depth: usize,
+    pub return_depth: usize,
+}
+
+/// A call to a proc with a declared stack effect, checked when the proc returns.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub name: String,
+    pub effect: StackEffect,
+    /// Stack depth under the inputs of the proc
+    pub base: usize,
+    /// Return stack depth inside the call
+    pub return_depth: usize,
+}
+
+/// Error carrying a value raised with `throw`.
+#[derive(Debug, Clone)]
+pub struct Thrown(pub Data);
+
+impl std::fmt::Display for Thrown {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Uncaught throw: {}", self.0)
+    }
+}
+
+impl std::error::Error for Thrown {}
+
+/// Error raised by `exit` and `quit` to stop the program, so whoever runs the machine decides
+/// what stopping means.
+#[derive(Debug, Clone, Copy)]
+pub struct Exit(pub i32);
+
+impl std::fmt::Display for Exit {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Exited with code {}", self.0)
+    }
+}
+
+impl std::error::Error for Exit {}
+
+/// Output collected in memory, for reading back what a program printed. Clones share the buffer,
+/// so one can be given to [`Runtime::output`] and another kept to read from.
+#[derive(Clone, Default)]
+pub struct Captured(Rc<RefCell<Vec<u8>>>);
+
+impl Captured {
+    /// Takes everything written so far, leaving the buffer empty.
+    pub fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.borrow_mut())
+    }
+}
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// State owned by the machine that built-in words need besides the stacks, variables and marks.
+pub struct Runtime {
+    pub rng: Rng,
+    pub input: Input,
+    /// Where `.`, `nl` and the other printing words write to
+    pub output: Box<dyn Write>,
+    /// Whether scripts may spawn processes with `run`
+    pub allow_exec: bool,
+    /// Origin of the `monotonic` clock
+    pub started: Instant,
+    pub handlers: Vec<Handler>,
+    /// Whether procs are checked against their declared stack effects
+    pub check_effects: bool,
+    pub frames: Vec<Frame>,
+}
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Runtime {
+    pub fn new() -> Self {
+        Self {
+            rng: Rng::from_os(),
+            input: Input::stdin(),
+            output: Box::new(std::io::stdout()),
+            allow_exec: true,
+            started: Instant::now(),
+            handlers: Vec::new(),
+            check_effects: true,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn from_args(args: &Args) -> Self {
+        let mut runtime = Self::new();
+        if let Some(seed) = args.seed {
+            runtime.rng.seed(seed);
+        }
+        runtime.allow_exec = !args.no_exec;
+        runtime.check_effects = !args.no_effect_checks;
+        runtime
+    }
+}
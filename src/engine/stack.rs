@@ -19,6 +19,12 @@ impl std::fmt::Display for Stack {
     }
 }
 
+impl Default for Stack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Stack {
     pub fn new() -> Stack {
         Stack { stack: Vec::new() }
@@ -32,7 +38,7 @@ impl Stack {
         self.stack.push(element);
     }
 
-    pub fn push_int(&mut self, value: i32) {
+    pub fn push_int(&mut self, value: i64) {
         self.stack.push(Data::Int(value));
     }
 
@@ -60,6 +66,15 @@ impl Stack {
         self.stack.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.stack.is_empty()
+    }
+
+    /// Drops elements from the top until at most `len` are left.
+    pub fn truncate(&mut self, len: usize) {
+        self.stack.truncate(len);
+    }
+
     pub fn clear(&mut self) {
         self.stack.clear();
     }
@@ -132,17 +147,29 @@ mod tests {
         assert!(stack.pop().is_err());
     }
 
+    #[test]
+    fn test_truncate() {
+        let mut stack = super::Stack::new();
+        stack.push_int(1);
+        stack.push_int(2);
+        stack.push_int(3);
+        stack.truncate(1);
+        assert_eq!(stack.stack, vec![super::Data::Int(1)]);
+        stack.truncate(5);
+        assert_eq!(stack.len(), 1);
+    }
+
     #[test]
     fn test_display() {
         let mut stack = super::Stack::new();
         stack.push(super::Data::Int(42));
 
-        assert_eq!(format!("{}", stack).contains("42"), true);
+        assert!(format!("{}", stack).contains("42"));
     }
 
     #[test]
     fn test_display_empty() {
         let stack = super::Stack::new();
-        assert_eq!(format!("{}", stack).is_empty(), true);
+        assert!(format!("{}", stack).is_empty());
     }
 }
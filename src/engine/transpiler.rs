@@ -0,0 +1,408 @@
+//! Transpiles a linked program to a self-contained C file, written by `pinniped emit-c`.
+//!
+//! The file is the runtime in `transpiler/runtime.c` followed by the program: tables with the
+//! location of every token, the declared stack effects and the variable names, and a `main` that
+//! is one `switch` over the PCs of the tokens. Every token is a `case` that falls through to the
+//! next one, so jumps set the PC the interpreter would continue at and go round the loop again.
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use super::data::{Data, Type};
+use super::lexer::error::SourceError;
+use super::lexer::token::{Token, TokenKind, TokenType, VariableScope};
+use super::mark::{Slot, StackEffect};
+
+const RUNTIME: &str = include_str!("transpiler/runtime.c");
+
+/// Words that run a runtime function taking no arguments, by name.
+const CALLS: &[(&str, &str)] = &[
+    (".", "w_print()"),
+    (",", "w_print_top()"),
+    ("nl", "w_nl()"),
+    ("+", "pn_arithmetic(PN_ADD)"),
+    ("-", "pn_arithmetic(PN_SUB)"),
+    ("*", "pn_arithmetic(PN_MUL)"),
+    ("/", "pn_arithmetic(PN_DIV)"),
+    ("%", "pn_arithmetic(PN_MOD)"),
+    ("^", "pn_arithmetic(PN_POW)"),
+    ("inc", "w_inc()"),
+    ("~", "w_negate()"),
+    (":", "w_dup()"),
+    ("swp", "w_swp()"),
+    ("ror", "pn_rotate(0)"),
+    ("rol", "pn_rotate(1)"),
+    ("clr", "w_clr()"),
+    ("`", "w_debug()"),
+    ("exit", "w_exit()"),
+    ("=", "w_equal()"),
+    ("!", "w_not()"),
+    ("<", "pn_compare(0)"),
+    (">", "pn_compare(1)"),
+    ("len", "w_len()"),
+    ("read-line", "w_read_line()"),
+    ("read-all", "w_read_all()"),
+    ("eof", "w_eof()"),
+    ("assert", "w_assert()"),
+    ("assert-eq", "w_assert_eq()"),
+    ("format", "w_format()"),
+    ("printf", "w_printf()"),
+];
+
+/// A C string literal with the bytes of `text`.
+fn literal(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('"');
+    for byte in text.bytes() {
+        match byte {
+            b'"' | b'\\' | b'?' => write!(out, "\\{:03o}", byte).unwrap(),
+            0x20..=0x7e => out.push(byte as char),
+            _ => write!(out, "\\{:03o}", byte).unwrap(),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn type_constant(type_: Type) -> &'static str {
+    match type_ {
+        Type::Int => "PN_INT",
+        Type::Float => "PN_FLOAT",
+        Type::String => "PN_STRING",
+        Type::Bool => "PN_BOOL",
+        Type::None => "PN_NONE",
+        Type::Unknown => "PN_ANY",
+    }
+}
+
+/// An expression for a value that is not a string.
+fn value(data: &Data) -> String {
+    match data {
+        Data::Int(i64::MIN) => "pn_int(INT64_MIN)".to_string(),
+        Data::Int(i) => format!("pn_int({}LL)", i),
+        Data::Float(f) if f.is_nan() => "pn_float(NAN)".to_string(),
+        Data::Float(f) if f.is_infinite() => {
+            format!("pn_float({}INFINITY)", if *f < 0.0 { "-" } else { "" })
+        }
+        Data::Float(f) => format!("pn_float({:e}f)", f),
+        Data::Bool(b) => format!("pn_bool({})", *b as u8),
+        Data::None | Data::String(_) => "pn_none()".to_string(),
+    }
+}
+
+struct Transpiler<'a> {
+    types: &'a [TokenType],
+    tokens: &'a [Token],
+    /// Slots of the local and the global variable names, in the order they are first used
+    names: [Vec<String>; 2],
+    slots: [HashMap<String, usize>; 2],
+    /// String constants, created once when the program starts
+    strings: Vec<String>,
+    effects: Vec<StackEffect>,
+}
+
+impl Transpiler<'_> {
+    fn slot(&mut self, name: &str, scope: usize) -> usize {
+        if let Some(&slot) = self.slots[scope].get(name) {
+            return slot;
+        }
+        let slot = self.names[scope].len();
+        self.names[scope].push(name.to_string());
+        self.slots[scope].insert(name.to_string(), slot);
+        slot
+    }
+
+    fn error(&self, pc: usize, message: String) -> anyhow::Error {
+        let token = &self.tokens[pc];
+        SourceError::new(&token.file, token.line, token.col, message).into()
+    }
+
+    /// PC the interpreter continues at after the token at `pc` moved the PC by `delta`.
+    fn next(&self, pc: usize, delta: i64) -> usize {
+        (pc as i64 + delta + 1) as usize
+    }
+
+    fn offset(&self, pc: usize) -> Result<i64> {
+        match self.tokens[pc].data {
+            Data::Int(offset) => Ok(offset),
+            _ => Err(self.error(pc, "Block token is not linked".to_string())),
+        }
+    }
+
+    /// Index of the stack effect declared after the `proc` at `pc`, or -1.
+    fn effect(&mut self, pc: usize) -> Result<i64> {
+        let Some(token) = self.tokens.get(pc + 1) else {
+            return Ok(-1);
+        };
+        if self.types[token.type_].type_ != TokenKind::StackEffect {
+            return Ok(-1);
+        }
+        let effect = StackEffect::parse(&token.data.to_string())
+            .map_err(|e| self.error(pc + 1, e.to_string()))?;
+        self.effects.push(effect);
+        Ok(self.effects.len() as i64 - 1)
+    }
+
+    /// The C statements that run the token at `pc`.
+    fn statement(&mut self, pc: usize) -> Result<String> {
+        let token = &self.tokens[pc];
+        let token_type = &self.types[token.type_];
+        let name = token_type.name.as_str();
+        let code = match token_type.type_ {
+            TokenKind::If | TokenKind::Do => {
+                let delta = match token_type.type_ {
+                    TokenKind::If => self.offset(pc)?,
+                    _ => self.offset(pc)? + 1,
+                };
+                format!(
+                    "{{ pn_data c; if (pn_pop(&c)) goto fail; int f = pn_is_false(c); \
+                     pn_release(c); if (f) {{ pc = {}; continue; }} }}",
+                    self.next(pc, delta)
+                )
+            }
+            TokenKind::End => format!("pc = {}; continue;", self.next(pc, -(self.offset(pc)? + 1))),
+            TokenKind::EndIf | TokenKind::While | TokenKind::EndTry => String::new(),
+            TokenKind::Proc => format!(
+                "if (w_proc({}, {})) goto fail; pc = {}; continue;",
+                pc,
+                self.effect(pc)?,
+                self.next(pc, self.offset(pc)? + 1)
+            ),
+            TokenKind::StackEffect => format!("if (w_effect({})) goto fail;", pc - 1),
+            TokenKind::ProcRet => "if (w_ret()) goto fail; pc = pn.jump; continue;".to_string(),
+            // The PC of the catch, where errors in the block continue from
+            TokenKind::Try => format!("w_try({});", pc as i64 + self.offset(pc)? + 1),
+            TokenKind::Catch => format!(
+                "w_catch(); pc = {}; continue;",
+                self.next(pc, self.offset(pc)?)
+            ),
+            TokenKind::Push | TokenKind::Function => {
+                if let Some(scope) = token_type.variable {
+                    let scope = (scope == VariableScope::Global) as usize;
+                    let Data::String(variable) = &token.data else {
+                        return Ok(
+                            "pn_fail(\"Variable name must be a string\"); goto fail;".to_string()
+                        );
+                    };
+                    let slot = self.slot(variable, scope);
+                    let word = match name.trim_end_matches("-global") {
+                        "store" => "w_store",
+                        "load" => "w_load",
+                        _ => "w_remove",
+                    };
+                    return Ok(format!("if ({}({}, {})) goto fail;", word, scope, slot));
+                }
+                match (name, &token.data) {
+                    ("push", Data::String(s)) if s.contains("{<") => format!(
+                        "if (pn_push_literal({}, {})) goto fail;",
+                        literal(s),
+                        s.len()
+                    ),
+                    ("push", Data::String(s)) => {
+                        self.strings.push(s.clone());
+                        let index = self.strings.len() - 1;
+                        format!(
+                            "pn_retain(pn_strings[{0}]); pn_push(pn_strings[{0}]);",
+                            index
+                        )
+                    }
+                    ("push", data) => format!("pn_push({});", value(data)),
+                    ("call", Data::String(proc)) => format!(
+                        "if (w_call({}, {}, {})) goto fail; pc = pn.jump; continue;",
+                        pc,
+                        literal(proc),
+                        proc.len()
+                    ),
+                    ("call", _) => format!("w_call_invalid({}); goto fail;", pc),
+                    ("quit", _) => "pn_exit(0);".to_string(),
+                    ("here", _) => format!("pn_push(pn_int({}));", pc),
+                    ("jmp", _) => "if (w_jmp()) goto fail; pc = pn.jump; continue;".to_string(),
+                    ("throw", _) => "w_throw(); goto fail;".to_string(),
+                    _ => match CALLS.iter().find(|(word, _)| *word == name) {
+                        Some((_, call)) => format!("if ({}) goto fail;", call),
+                        None => {
+                            let message = format!(
+                                "`{}` at {}:{} is not supported by emit-c",
+                                token.word, token.line, token.col
+                            );
+                            return Err(self.error(pc, message));
+                        }
+                    },
+                }
+            }
+        };
+        Ok(code)
+    }
+
+    fn slots(slots: &[Slot]) -> String {
+        let slots = slots
+            .iter()
+            .map(|slot| format!("{{{}, {}}}", literal(&slot.name), type_constant(slot.type_)))
+            .collect::<Vec<String>>();
+        format!("(const pn_slot[]){{{}}}", slots.join(", "))
+    }
+
+    fn transpile(mut self, check_effects: bool) -> Result<String> {
+        let mut body = String::new();
+        for pc in 0..self.tokens.len() {
+            let statement = self.statement(pc)?;
+            writeln!(
+                body,
+                "        case {}: pn.cur = {}; {} /* fallthrough */",
+                pc, pc, statement
+            )?;
+        }
+        writeln!(body, "        case {}: pn_exit(0);", self.tokens.len())?;
+
+        let names = self.names[0].len().max(self.names[1].len()).max(1);
+        let mut out = String::new();
+        writeln!(out, "/* Generated by pinniped emit-c */")?;
+        writeln!(out, "#define PN_TOKENS {}", self.tokens.len().max(1))?;
+        writeln!(out, "#define PN_EFFECTS {}", self.effects.len().max(1))?;
+        writeln!(out, "#define PN_NAMES {}", names)?;
+        writeln!(out, "#define PN_CHECK_EFFECTS {}", check_effects as u8)?;
+        out.push_str(RUNTIME);
+
+        writeln!(out, "\nstatic const pn_loc pn_locs[PN_TOKENS] = {{")?;
+        for token in self.tokens {
+            writeln!(
+                out,
+                "    {{{}, {}, {}, {}}},",
+                literal(&token.file),
+                token.line,
+                token.col,
+                literal(&self.types[token.type_].name)
+            )?;
+        }
+        writeln!(
+            out,
+            "}};\n\nstatic const pn_effect pn_effects[PN_EFFECTS] = {{"
+        )?;
+        for effect in &self.effects {
+            writeln!(
+                out,
+                "    {{{}, {}, {}, {}, {}}},",
+                literal(&effect.to_string()),
+                effect.inputs.len(),
+                Self::slots(&effect.inputs),
+                effect.outputs.len(),
+                Self::slots(&effect.outputs)
+            )?;
+        }
+        writeln!(
+            out,
+            "}};\n\nstatic const char *const pn_names[2][PN_NAMES] = {{"
+        )?;
+        for names in &self.names {
+            let names = names
+                .iter()
+                .map(|name| literal(name))
+                .collect::<Vec<String>>();
+            writeln!(out, "    {{{}}},", names.join(", "))?;
+        }
+        writeln!(
+            out,
+            "}};\nstatic const int pn_name_count[2] = {{{}, {}}};",
+            self.names[0].len(),
+            self.names[1].len()
+        )?;
+        if !self.strings.is_empty() {
+            writeln!(out, "static pn_data pn_strings[{}];", self.strings.len())?;
+        }
+        writeln!(out)?;
+
+        writeln!(out, "int main(int argc, char **argv) {{")?;
+        for (index, string) in self.strings.iter().enumerate() {
+            writeln!(
+                out,
+                "    pn_strings[{}] = pn_string({}, {});",
+                index,
+                literal(string),
+                string.len()
+            )?;
+        }
+        writeln!(out, "    pn.error = pn_none();")?;
+        writeln!(out, "    pn_push_args(argc, argv);")?;
+        writeln!(out, "    long pc = 0;")?;
+        writeln!(out, "    for (;;) {{")?;
+        writeln!(out, "        switch (pc) {{")?;
+        out.push_str(&body);
+        writeln!(out, "        }}")?;
+        writeln!(out, "    fail:")?;
+        writeln!(out, "        pc = pn_catch();")?;
+        writeln!(out, "    }}")?;
+        writeln!(out, "}}")?;
+        Ok(out)
+    }
+}
+
+/// Transpiles linked tokens to C, see the module documentation. Words the runtime does not
+/// implement, like the file system, process and time words, are reported with their location.
+pub fn transpile(types: &[TokenType], tokens: &[Token], check_effects: bool) -> Result<String> {
+    Transpiler {
+        types,
+        tokens,
+        names: [Vec::new(), Vec::new()],
+        slots: [HashMap::new(), HashMap::new()],
+        strings: Vec::new(),
+        effects: Vec::new(),
+    }
+    .transpile(check_effects)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::literal;
+    use crate::engine::machine::Machine;
+    use std::process::Command;
+
+    fn transpile(source: &str) -> anyhow::Result<String> {
+        Machine::linked(source).transpile()
+    }
+
+    #[test]
+    fn escapes_literals() {
+        assert_eq!(literal("a b"), "\"a b\"");
+        assert_eq!(literal("\"?\\\n"), "\"\\042\\077\\134\\012\"");
+        assert_eq!(literal("é"), "\"\\303\\251\"");
+    }
+
+    #[test]
+    fn rejects_unsupported_words() {
+        let error = transpile("(1) .\n(2) monotonic\n").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "`monotonic` at 2:5 is not supported by emit-c"
+        );
+    }
+
+    #[test]
+    fn compiles_and_runs() {
+        let source = transpile(
+            "(fact) proc\n  : (1) < if : (1) - {fact} * endif\nret\n\
+             (5) {fact} . nl\ntry (1) (a) + catch (caught) . nl endtry\n",
+        )
+        .unwrap();
+        let dir = std::env::temp_dir().join(format!("pinniped-emit-c-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("test.c");
+        std::fs::write(&file, source).unwrap();
+        let executable = dir.join("test");
+        let compiled = Command::new("cc")
+            .arg("-o")
+            .arg(&executable)
+            .arg(&file)
+            .arg("-lm")
+            .status();
+        // Without a C compiler there is nothing to run
+        if compiled.is_err() {
+            let _ = std::fs::remove_dir_all(&dir);
+            return;
+        }
+        assert!(compiled.unwrap().success());
+        let output = Command::new(&executable).output().unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "120\ncaught\n");
+    }
+}
@@ -0,0 +1,1246 @@
+/* Runtime of programs transpiled by `pinniped emit-c`. It mirrors the interpreter: values are
+ * tagged like `Data`, words fail with the interpreter's messages, and a failing word is handed
+ * to the innermost `try` block or ends the program. The program itself follows this runtime as
+ * one `switch` over the PCs of its tokens. */
+#include <math.h>
+#include <stdarg.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/* A program only calls the words it uses. */
+#ifdef __GNUC__
+#pragma GCC diagnostic ignored "-Wunused-function"
+#endif
+
+typedef enum { PN_STRING, PN_INT, PN_FLOAT, PN_BOOL, PN_NONE } pn_tag;
+
+/* Reference counted string, it is shared between copies of a value */
+typedef struct {
+    long rc;
+    size_t len;
+    char bytes[];
+} pn_str;
+
+typedef struct {
+    pn_tag tag;
+    union {
+        pn_str *s;
+        int64_t i;
+        float f;
+        int b;
+    } as;
+} pn_data;
+
+/* Types of stack effect slots, -1 takes any value */
+#define PN_ANY (-1)
+
+typedef struct {
+    const char *name;
+    int type;
+} pn_slot;
+
+typedef struct {
+    const char *text;
+    int ninputs;
+    const pn_slot *inputs;
+    int noutputs;
+    const pn_slot *outputs;
+} pn_effect;
+
+typedef struct {
+    const char *file;
+    long line;
+    long col;
+    /* Name of the word, reported by errors that are not caught */
+    const char *word;
+} pn_loc;
+
+typedef struct {
+    pn_str *name;
+    long pc;
+    int effect;
+} pn_mark;
+
+typedef struct {
+    long catch_pc;
+    size_t depth;
+    size_t return_depth;
+} pn_handler;
+
+typedef struct {
+    pn_str *name;
+    int effect;
+    size_t base;
+    size_t return_depth;
+} pn_frame;
+
+typedef struct {
+    char *bytes;
+    size_t len;
+    size_t cap;
+} pn_buf;
+
+/* Tables of the program, defined after the runtime. The program defines PN_TOKENS, PN_EFFECTS,
+ * PN_NAMES and PN_CHECK_EFFECTS before it */
+static const pn_loc pn_locs[PN_TOKENS];
+static const pn_effect pn_effects[PN_EFFECTS];
+static const char *const pn_names[2][PN_NAMES];
+static const int pn_name_count[2];
+
+static struct {
+    pn_data *stack;
+    size_t len, cap;
+    long *returns;
+    size_t returns_len, returns_cap;
+    pn_mark *marks;
+    size_t marks_len, marks_cap;
+    pn_handler *handlers;
+    size_t handlers_len, handlers_cap;
+    pn_frame *frames;
+    size_t frames_len, frames_cap;
+    /* Local and global variables by slot, `set` is 0 for unset ones */
+    pn_data vars[2][PN_NAMES];
+    int set[2][PN_NAMES];
+    /* PC of the running token */
+    long cur;
+    /* Where a jumping word continues */
+    long jump;
+    /* The message of the last error, or the value it threw */
+    pn_data error;
+    int thrown;
+} pn;
+
+#define PN_GROW(items, len, cap)                                           \
+    do {                                                                   \
+        if ((len) == (cap)) {                                              \
+            (cap) = (cap) ? (cap) * 2 : 64;                                \
+            (items) = realloc((items), (cap) * sizeof(*(items)));          \
+            if (!(items)) {                                                \
+                fputs("out of memory\n", stderr);                          \
+                exit(101);                                                 \
+            }                                                              \
+        }                                                                  \
+    } while (0)
+
+static void pn_panic(const char *message) {
+    fflush(stdout);
+    fprintf(stderr, "thread 'main' panicked: %s\n", message);
+    exit(101);
+}
+
+static void pn_exit(int code) {
+    fflush(stdout);
+    exit(code);
+}
+
+/* Strings */
+
+static pn_str *pn_str_new(const char *bytes, size_t len) {
+    pn_str *s = malloc(sizeof(pn_str) + len + 1);
+    if (!s) pn_panic("out of memory");
+    s->rc = 1;
+    s->len = len;
+    memcpy(s->bytes, bytes, len);
+    s->bytes[len] = 0;
+    return s;
+}
+
+static void pn_retain(pn_data d) {
+    if (d.tag == PN_STRING) d.as.s->rc++;
+}
+
+static void pn_release(pn_data d) {
+    if (d.tag == PN_STRING && --d.as.s->rc == 0) free(d.as.s);
+}
+
+static pn_data pn_int(int64_t i) {
+    pn_data d;
+    d.tag = PN_INT;
+    d.as.i = i;
+    return d;
+}
+
+static pn_data pn_float(float f) {
+    pn_data d;
+    d.tag = PN_FLOAT;
+    d.as.f = f;
+    return d;
+}
+
+static pn_data pn_bool(int b) {
+    pn_data d;
+    d.tag = PN_BOOL;
+    d.as.b = b != 0;
+    return d;
+}
+
+static pn_data pn_none(void) {
+    pn_data d;
+    d.tag = PN_NONE;
+    d.as.i = 0;
+    return d;
+}
+
+static pn_data pn_string(const char *bytes, size_t len) {
+    pn_data d;
+    d.tag = PN_STRING;
+    d.as.s = pn_str_new(bytes, len);
+    return d;
+}
+
+static pn_data pn_cstring(const char *s) {
+    return pn_string(s, strlen(s));
+}
+
+/* Buffers */
+
+static void pn_buf_add(pn_buf *b, const char *bytes, size_t len) {
+    if (b->len + len + 1 > b->cap) {
+        b->cap = (b->len + len + 1) * 2;
+        b->bytes = realloc(b->bytes, b->cap);
+        if (!b->bytes) pn_panic("out of memory");
+    }
+    memcpy(b->bytes + b->len, bytes, len);
+    b->len += len;
+    b->bytes[b->len] = 0;
+}
+
+static void pn_buf_str(pn_buf *b, const char *s) {
+    pn_buf_add(b, s, strlen(s));
+}
+
+static void pn_buf_printf(pn_buf *b, const char *format, ...) {
+    char small[128];
+    va_list args;
+    va_start(args, format);
+    int len = vsnprintf(small, sizeof(small), format, args);
+    va_end(args);
+    if (len < (int)sizeof(small)) {
+        pn_buf_add(b, small, (size_t)len);
+        return;
+    }
+    char *large = malloc((size_t)len + 1);
+    if (!large) pn_panic("out of memory");
+    va_start(args, format);
+    vsnprintf(large, (size_t)len + 1, format, args);
+    va_end(args);
+    pn_buf_add(b, large, (size_t)len);
+    free(large);
+}
+
+/* Takes the contents of the buffer as a string value */
+static pn_data pn_buf_take(pn_buf *b) {
+    pn_data d = pn_string(b->bytes ? b->bytes : "", b->len);
+    free(b->bytes);
+    b->bytes = NULL;
+    b->len = b->cap = 0;
+    return d;
+}
+
+/* Writes a float like Rust's `Display`: the fewest digits that read back as the same float,
+ * never in scientific notation */
+static void pn_buf_float(pn_buf *b, float f) {
+    if (isnan(f)) {
+        pn_buf_str(b, "NaN");
+        return;
+    }
+    if (isinf(f)) {
+        pn_buf_str(b, f < 0 ? "-inf" : "inf");
+        return;
+    }
+    char text[64];
+    for (int precision = 0; precision < 9; precision++) {
+        snprintf(text, sizeof(text), "%.*e", precision, (double)f);
+        if (strtof(text, NULL) == f) break;
+    }
+    char digits[32];
+    int count = 0;
+    const char *c = text;
+    if (*c == '-') {
+        pn_buf_str(b, "-");
+        c++;
+    }
+    for (; *c && *c != 'e'; c++) {
+        if (*c != '.') digits[count++] = *c;
+    }
+    int exponent = atoi(c + 1);
+    while (count > 1 && digits[count - 1] == '0') count--;
+    int point = exponent + 1;
+    if (point <= 0) {
+        pn_buf_str(b, "0.");
+        for (int i = 0; i < -point; i++) pn_buf_str(b, "0");
+        pn_buf_add(b, digits, (size_t)count);
+    } else if (point >= count) {
+        pn_buf_add(b, digits, (size_t)count);
+        for (int i = count; i < point; i++) pn_buf_str(b, "0");
+    } else {
+        pn_buf_add(b, digits, (size_t)point);
+        pn_buf_str(b, ".");
+        pn_buf_add(b, digits + point, (size_t)(count - point));
+    }
+}
+
+/* Writes a value like `Data`'s `Display` */
+static void pn_buf_data(pn_buf *b, pn_data d) {
+    switch (d.tag) {
+    case PN_STRING:
+        pn_buf_add(b, d.as.s->bytes, d.as.s->len);
+        break;
+    case PN_INT:
+        pn_buf_printf(b, "%lld", (long long)d.as.i);
+        break;
+    case PN_FLOAT:
+        pn_buf_float(b, d.as.f);
+        break;
+    case PN_BOOL:
+        pn_buf_str(b, d.as.b ? "true" : "false");
+        break;
+    case PN_NONE:
+        pn_buf_str(b, "None");
+        break;
+    }
+}
+
+static void pn_print(pn_data d) {
+    if (d.tag == PN_STRING) {
+        fwrite(d.as.s->bytes, 1, d.as.s->len, stdout);
+        return;
+    }
+    pn_buf b = {0};
+    pn_buf_data(&b, d);
+    fwrite(b.bytes, 1, b.len, stdout);
+    free(b.bytes);
+}
+
+static const char *pn_type_name(int type) {
+    switch (type) {
+    case PN_STRING:
+        return "string";
+    case PN_INT:
+        return "int";
+    case PN_FLOAT:
+        return "float";
+    case PN_BOOL:
+        return "bool";
+    case PN_NONE:
+        return "None";
+    default:
+        return "unknown";
+    }
+}
+
+static int pn_is_number(pn_data d) {
+    return d.tag == PN_INT || d.tag == PN_FLOAT;
+}
+
+static int pn_is_false(pn_data d) {
+    switch (d.tag) {
+    case PN_BOOL:
+        return !d.as.b;
+    case PN_INT:
+        return d.as.i == 0;
+    case PN_FLOAT:
+        return d.as.f == 0.0f;
+    case PN_STRING:
+        return d.as.s->len == 0;
+    default:
+        return 1;
+    }
+}
+
+static int pn_equal(pn_data a, pn_data b) {
+    if (a.tag != b.tag) return 0;
+    switch (a.tag) {
+    case PN_STRING:
+        return a.as.s->len == b.as.s->len && memcmp(a.as.s->bytes, b.as.s->bytes, a.as.s->len) == 0;
+    case PN_INT:
+        return a.as.i == b.as.i;
+    case PN_FLOAT:
+        return a.as.f == b.as.f;
+    case PN_BOOL:
+        return a.as.b == b.as.b;
+    default:
+        return 1;
+    }
+}
+
+/* Errors, every failing word returns 1 */
+
+static int pn_fail(const char *format, ...) {
+    char message[512];
+    va_list args;
+    va_start(args, format);
+    vsnprintf(message, sizeof(message), format, args);
+    va_end(args);
+    pn_release(pn.error);
+    pn.error = pn_cstring(message);
+    pn.thrown = 0;
+    return 1;
+}
+
+static int pn_fail_with(pn_buf *message) {
+    pn_release(pn.error);
+    pn.error = pn_buf_take(message);
+    pn.thrown = 0;
+    return 1;
+}
+
+/* Hands the last error to the innermost try block and returns the PC to continue at, or ends
+ * the program */
+static long pn_catch(void) {
+    const pn_loc *loc = &pn_locs[pn.cur];
+    if (pn.handlers_len == 0) {
+        pn_buf message = {0};
+        if (pn.thrown) pn_buf_str(&message, "Uncaught throw: ");
+        pn_buf_data(&message, pn.error);
+        fflush(stdout);
+        fprintf(stderr, "Error interpreting token at %s:%ld:%ld: %s: \"%s\"\n", loc->file, loc->line,
+                loc->col, message.bytes, loc->word);
+        exit(1);
+    }
+    pn_handler handler = pn.handlers[--pn.handlers_len];
+    while (pn.len > handler.depth) pn_release(pn.stack[--pn.len]);
+    if (pn.returns_len > handler.return_depth) pn.returns_len = handler.return_depth;
+    size_t kept = 0;
+    for (size_t i = 0; i < pn.frames_len; i++) {
+        if (pn.frames[i].return_depth <= handler.return_depth) pn.frames[kept++] = pn.frames[i];
+    }
+    pn.frames_len = kept;
+    pn_buf where = {0};
+    pn_buf_printf(&where, "%s:%ld:%ld", loc->file, loc->line, loc->col);
+    PN_GROW(pn.stack, pn.len, pn.cap);
+    pn.stack[pn.len++] = pn_buf_take(&where);
+    PN_GROW(pn.stack, pn.len, pn.cap);
+    pn.stack[pn.len++] = pn.error;
+    pn.error = pn_none();
+    return handler.catch_pc + 1;
+}
+
+/* The stack */
+
+static void pn_push(pn_data d) {
+    PN_GROW(pn.stack, pn.len, pn.cap);
+    pn.stack[pn.len++] = d;
+}
+
+static int pn_pop(pn_data *d) {
+    if (pn.len == 0) {
+        *d = pn_none();
+        return pn_fail("Cannot pop from an empty stack");
+    }
+    *d = pn.stack[--pn.len];
+    return 0;
+}
+
+static void pn_push_args(int argc, char **argv) {
+    for (int i = 1; i < argc; i++) pn_push(pn_cstring(argv[i]));
+}
+
+/* Words */
+
+static int w_print(void) {
+    pn_data a;
+    if (pn_pop(&a)) return 1;
+    pn_print(a);
+    pn_release(a);
+    return 0;
+}
+
+static int w_print_top(void) {
+    if (pn.len == 0) return pn_fail("Cannot print empty stack");
+    pn_print(pn.stack[pn.len - 1]);
+    return 0;
+}
+
+static int w_nl(void) {
+    putchar('\n');
+    return 0;
+}
+
+/* Checked integer operations, returning 0 when the result does not fit like Rust's `checked_*` */
+static int pn_checked_add(int64_t x, int64_t y, int64_t *r) {
+    if ((y > 0 && x > INT64_MAX - y) || (y < 0 && x < INT64_MIN - y)) return 0;
+    *r = x + y;
+    return 1;
+}
+
+static int pn_checked_sub(int64_t x, int64_t y, int64_t *r) {
+    if ((y < 0 && x > INT64_MAX + y) || (y > 0 && x < INT64_MIN + y)) return 0;
+    *r = x - y;
+    return 1;
+}
+
+static int pn_checked_mul(int64_t x, int64_t y, int64_t *r) {
+    if (x > 0) {
+        if (y > 0 ? x > INT64_MAX / y : y < INT64_MIN / x) return 0;
+    } else if (x < 0) {
+        if (y > 0 ? x < INT64_MIN / y : y < INT64_MAX / x) return 0;
+    }
+    *r = x * y;
+    return 1;
+}
+
+static int pn_checked_pow(int64_t base, int64_t exponent, int64_t *r) {
+    if (exponent < 0 || exponent > UINT32_MAX) return 0;
+    int64_t acc = 1;
+    if (exponent == 0) {
+        *r = 1;
+        return 1;
+    }
+    while (exponent > 1) {
+        if ((exponent & 1) && !pn_checked_mul(acc, base, &acc)) return 0;
+        exponent /= 2;
+        if (!pn_checked_mul(base, base, &base)) return 0;
+    }
+    return pn_checked_mul(acc, base, r);
+}
+
+enum { PN_ADD, PN_SUB, PN_MUL, PN_DIV, PN_MOD, PN_POW };
+
+static const char *const pn_arithmetic_errors[] = {
+    "Cannot add non-number values",      "Cannot subtract non-number values",
+    "Cannot multiply non-number values", "Cannot divide non-number values",
+    "Cannot modulo non-number values",   "Cannot exponentiate non-number values",
+};
+
+static int pn_arithmetic(int op) {
+    pn_data a, b;
+    if (pn_pop(&a)) return 1;
+    if (pn_pop(&b)) {
+        pn_release(a);
+        return 1;
+    }
+    if (!pn_is_number(a) || !pn_is_number(b)) {
+        pn_push(b);
+        pn_push(a);
+        return pn_fail("%s", pn_arithmetic_errors[op]);
+    }
+    if (a.tag == PN_INT && b.tag == PN_INT) {
+        int64_t x = b.as.i, y = a.as.i, r = 0;
+        int ok;
+        switch (op) {
+        case PN_ADD:
+            ok = pn_checked_add(x, y, &r);
+            break;
+        case PN_SUB:
+            ok = pn_checked_sub(x, y, &r);
+            break;
+        case PN_MUL:
+            ok = pn_checked_mul(x, y, &r);
+            break;
+        case PN_DIV:
+        case PN_MOD:
+            ok = y != 0 && !(x == INT64_MIN && y == -1);
+            if (ok) r = op == PN_DIV ? x / y : x % y;
+            break;
+        default:
+            ok = pn_checked_pow(x, y, &r);
+            break;
+        }
+        if (!ok) {
+            pn_push(b);
+            pn_push(a);
+            if ((op == PN_DIV || op == PN_MOD) && y == 0) return pn_fail("Division by zero");
+            if (op == PN_POW && y < 0) return pn_fail("Negative exponent");
+            return pn_fail("Integer overflow");
+        }
+        pn_push(pn_int(r));
+        return 0;
+    }
+    /* Ints and floats are never mixed */
+    if (a.tag != PN_FLOAT || b.tag != PN_FLOAT) return pn_fail("Data is not a float");
+    float x = b.as.f, y = a.as.f;
+    switch (op) {
+    case PN_ADD:
+        pn_push(pn_float(x + y));
+        break;
+    case PN_SUB:
+        pn_push(pn_float(x - y));
+        break;
+    case PN_MUL:
+        pn_push(pn_float(x * y));
+        break;
+    case PN_DIV:
+        pn_push(pn_float(x / y));
+        break;
+    case PN_MOD:
+        pn_push(pn_float(fmodf(x, y)));
+        break;
+    default:
+        pn_push(pn_float(powf(x, y)));
+        break;
+    }
+    return 0;
+}
+
+static int w_inc(void) {
+    pn_data a;
+    if (pn_pop(&a)) return 1;
+    if (a.tag == PN_INT) {
+        if (a.as.i == INT64_MAX) {
+            pn_push(a);
+            pn_push(pn_int(1));
+            return pn_fail("Integer overflow");
+        }
+        pn_push(pn_int(a.as.i + 1));
+        return 0;
+    }
+    if (a.tag == PN_FLOAT) return pn_fail("Data is not a float");
+    pn_push(a);
+    pn_push(pn_int(1));
+    return pn_fail("Cannot add non-number values");
+}
+
+static int w_negate(void) {
+    pn_data a;
+    if (pn_pop(&a)) return 1;
+    if (a.tag == PN_INT) {
+        if (a.as.i == INT64_MIN) {
+            pn_push(a);
+            return pn_fail("Integer overflow");
+        }
+        pn_push(pn_int(-a.as.i));
+    } else if (a.tag == PN_FLOAT) {
+        pn_push(pn_float(-a.as.f));
+    } else {
+        pn_push(a);
+        return pn_fail("Cannot negate non-number value");
+    }
+    return 0;
+}
+
+static int w_dup(void) {
+    pn_data a;
+    if (pn_pop(&a)) return 1;
+    pn_retain(a);
+    pn_push(a);
+    pn_push(a);
+    return 0;
+}
+
+static int w_swp(void) {
+    pn_data a, b;
+    if (pn_pop(&a)) return 1;
+    if (pn_pop(&b)) {
+        pn_release(a);
+        return 1;
+    }
+    pn_push(a);
+    pn_push(b);
+    return 0;
+}
+
+/* `ror` with `right` 0, `rol` with 1 */
+static int pn_rotate(int right) {
+    pn_data a, b, c;
+    if (pn_pop(&a)) return 1;
+    if (pn_pop(&b)) {
+        pn_release(a);
+        return 1;
+    }
+    if (pn_pop(&c)) {
+        pn_release(a);
+        pn_release(b);
+        return 1;
+    }
+    if (right) {
+        pn_push(b);
+        pn_push(a);
+        pn_push(c);
+    } else {
+        pn_push(a);
+        pn_push(c);
+        pn_push(b);
+    }
+    return 0;
+}
+
+static int w_clr(void) {
+    while (pn.len > 0) pn_release(pn.stack[--pn.len]);
+    return 0;
+}
+
+static int w_debug(void) {
+    pn_buf b = {0};
+    pn_buf_str(&b, "Stack debug:\n");
+    for (size_t i = 0; i < pn.len; i++) {
+        if (i > 0) pn_buf_str(&b, ", ");
+        pn_buf_data(&b, pn.stack[i]);
+    }
+    pn_buf_str(&b, "\n");
+    fwrite(b.bytes, 1, b.len, stdout);
+    free(b.bytes);
+    return 0;
+}
+
+static int w_exit(void) {
+    pn_data a;
+    if (pn_pop(&a)) return 1;
+    if (a.tag != PN_INT) {
+        pn_push(a);
+        return pn_fail("Exit requires an integer value");
+    }
+    pn_exit((int)a.as.i);
+    return 0;
+}
+
+static int w_equal(void) {
+    pn_data a, b;
+    if (pn_pop(&a)) return 1;
+    if (pn_pop(&b)) {
+        pn_release(a);
+        return 1;
+    }
+    if (pn_is_number(a) && pn_is_number(b)) {
+        if (a.tag == PN_INT && b.tag == PN_INT) {
+            pn_push(pn_bool(b.as.i == a.as.i));
+        } else if (a.tag == PN_FLOAT && b.tag == PN_FLOAT) {
+            pn_push(pn_bool(b.as.f == a.as.f));
+        } else {
+            return pn_fail("Data is not a float");
+        }
+    } else if (a.tag == b.tag) {
+        pn_push(pn_bool(pn_equal(b, a)));
+        pn_release(a);
+        pn_release(b);
+    } else {
+        pn_push(b);
+        pn_push(a);
+        return pn_fail("Cannot compare different types");
+    }
+    return 0;
+}
+
+static int w_not(void) {
+    pn_data a;
+    if (pn_pop(&a)) return 1;
+    pn_push(pn_bool(pn_is_false(a)));
+    pn_release(a);
+    return 0;
+}
+
+/* `<` with `greater` 0, `>` with 1, comparing the top value with the one under it */
+static int pn_compare(int greater) {
+    pn_data b, a;
+    if (pn_pop(&b)) return 1;
+    if (pn_pop(&a)) {
+        pn_release(b);
+        return 1;
+    }
+    if (!pn_is_number(a) || !pn_is_number(b)) {
+        pn_push(b);
+        pn_push(a);
+        return pn_fail("Cannot compare non-number values");
+    }
+    if (a.tag == PN_INT && b.tag == PN_INT) {
+        pn_push(pn_bool(greater ? b.as.i > a.as.i : b.as.i < a.as.i));
+    } else if (a.tag == PN_FLOAT && b.tag == PN_FLOAT) {
+        pn_push(pn_bool(greater ? b.as.f > a.as.f : b.as.f < a.as.f));
+    } else {
+        return pn_fail("Data is not a float");
+    }
+    return 0;
+}
+
+static int w_len(void) {
+    pn_push(pn_int((int64_t)pn.len));
+    return 0;
+}
+
+static int w_throw(void) {
+    pn_data a;
+    if (pn_pop(&a)) return 1;
+    pn_release(pn.error);
+    pn.error = a;
+    pn.thrown = 1;
+    return 1;
+}
+
+/* Variables */
+
+static int w_store(int scope, int slot) {
+    pn_data a;
+    if (pn_pop(&a)) return 1;
+    if (pn.set[scope][slot]) pn_release(pn.vars[scope][slot]);
+    pn.vars[scope][slot] = a;
+    pn.set[scope][slot] = 1;
+    return 0;
+}
+
+static int w_load(int scope, int slot) {
+    if (!pn.set[scope][slot]) {
+        pn_push(pn_cstring(pn_names[scope][slot]));
+        return pn_fail("Variable not found");
+    }
+    pn_retain(pn.vars[scope][slot]);
+    pn_push(pn.vars[scope][slot]);
+    return 0;
+}
+
+static int w_remove(int scope, int slot) {
+    if (pn.set[scope][slot]) pn_release(pn.vars[scope][slot]);
+    pn.set[scope][slot] = 0;
+    return 0;
+}
+
+/* Looks up a variable by name for `{<name}` in a string literal */
+static const pn_data *pn_variable(const char *name, size_t len, int scope) {
+    for (int slot = 0; slot < pn_name_count[scope]; slot++) {
+        const char *known = pn_names[scope][slot];
+        if (strlen(known) == len && memcmp(known, name, len) == 0) {
+            return pn.set[scope][slot] ? &pn.vars[scope][slot] : NULL;
+        }
+    }
+    return NULL;
+}
+
+/* Pushes a string literal, replacing `{<name}` and `{<<name}` with the variables */
+static int pn_push_literal(const char *bytes, size_t len) {
+    pn_buf b = {0};
+    size_t i = 0;
+    while (i < len) {
+        const char *start = NULL;
+        for (size_t j = i; j + 1 < len; j++) {
+            if (bytes[j] == '{' && bytes[j + 1] == '<') {
+                start = bytes + j;
+                break;
+            }
+        }
+        if (!start) break;
+        size_t at = (size_t)(start - bytes);
+        if (at > i && bytes[at - 1] == '{') {
+            pn_buf_add(&b, bytes + i, at - 1 - i);
+            pn_buf_str(&b, "{<");
+            i = at + 2;
+            continue;
+        }
+        pn_buf_add(&b, bytes + i, at - i);
+        size_t name = at + 2;
+        int scope = 0;
+        if (name < len && bytes[name] == '<') {
+            scope = 1;
+            name++;
+        }
+        size_t end = name;
+        while (end < len && bytes[end] != '}') end++;
+        if (end == len) {
+            free(b.bytes);
+            return pn_fail("Unclosed {< in string literal");
+        }
+        const pn_data *value = pn_variable(bytes + name, end - name, scope);
+        if (!value) {
+            free(b.bytes);
+            return pn_fail("Variable not found");
+        }
+        pn_buf_data(&b, *value);
+        i = end + 1;
+    }
+    pn_buf_add(&b, bytes + i, len - i);
+    pn_push(pn_buf_take(&b));
+    return 0;
+}
+
+/* Procs */
+
+static int w_proc(long pc, int effect) {
+    pn_data name;
+    if (pn_pop(&name)) return 1;
+    if (name.tag != PN_STRING) return pn_fail("Data is not a string");
+    PN_GROW(pn.marks, pn.marks_len, pn.marks_cap);
+    pn.marks[pn.marks_len++] = (pn_mark){name.as.s, pc, effect};
+    return 0;
+}
+
+static int pn_find_mark(const char *name, size_t len, long *pc) {
+    for (size_t i = 0; i < pn.marks_len; i++) {
+        pn_str *mark = pn.marks[i].name;
+        if (mark->len == len && memcmp(mark->bytes, name, len) == 0) {
+            *pc = pn.marks[i].pc;
+            return 1;
+        }
+    }
+    return 0;
+}
+
+static int w_call(long pc, const char *name, size_t len) {
+    PN_GROW(pn.returns, pn.returns_len, pn.returns_cap);
+    pn.returns[pn.returns_len++] = pc;
+    long target;
+    if (!pn_find_mark(name, len, &target)) return pn_fail("Proc not found: %.*s", (int)len, name);
+    pn.jump = target + 1;
+    return 0;
+}
+
+/* A call whose name is not a string */
+static int w_call_invalid(long pc) {
+    PN_GROW(pn.returns, pn.returns_len, pn.returns_cap);
+    pn.returns[pn.returns_len++] = pc;
+    return pn_fail("Data is not a string");
+}
+
+static int w_jmp(void) {
+    pn_data location;
+    if (pn_pop(&location)) return 1;
+    if (location.tag != PN_STRING) return pn_fail("Data is not a string");
+    long target;
+    if (!pn_find_mark(location.as.s->bytes, location.as.s->len, &target)) {
+        pn_push(location);
+        return pn_fail("Proc not found");
+    }
+    pn_release(location);
+    pn.jump = target + 1;
+    return 0;
+}
+
+static void pn_buf_effect(pn_buf *b, const pn_effect *effect) {
+    pn_buf_str(b, effect->text);
+}
+
+static int pn_check_slots(size_t base, const pn_slot *slots, int count, pn_str *name,
+                          const pn_effect *effect) {
+    for (int i = 0; i < count && base + (size_t)i < pn.len; i++) {
+        pn_data value = pn.stack[base + (size_t)i];
+        if (slots[i].type != PN_ANY && (int)value.tag != slots[i].type) {
+            pn_buf message = {0};
+            pn_buf_printf(&message, "Proc %s ", name->bytes);
+            pn_buf_effect(&message, effect);
+            pn_buf_printf(&message, " expects %s to be %s but got %s", slots[i].name,
+                          pn_type_name(slots[i].type), pn_type_name((int)value.tag));
+            return pn_fail_with(&message);
+        }
+    }
+    return 0;
+}
+
+/* The stack effect after the `proc` at `proc_pc`, checked when the proc is called */
+static int w_effect(long proc_pc) {
+    if (!PN_CHECK_EFFECTS) return 0;
+    pn_mark *mark = NULL;
+    for (size_t i = pn.marks_len; i > 0; i--) {
+        if (pn.marks[i - 1].pc == proc_pc) {
+            mark = &pn.marks[i - 1];
+            break;
+        }
+    }
+    if (!mark || mark->effect < 0) return 0;
+    const pn_effect *effect = &pn_effects[mark->effect];
+    if (pn.len < (size_t)effect->ninputs) {
+        pn_buf message = {0};
+        pn_buf_printf(&message, "Proc %s ", mark->name->bytes);
+        pn_buf_effect(&message, effect);
+        pn_buf_printf(&message, " needs %d values but the stack has %zu", effect->ninputs, pn.len);
+        return pn_fail_with(&message);
+    }
+    size_t base = pn.len - (size_t)effect->ninputs;
+    if (pn_check_slots(base, effect->inputs, effect->ninputs, mark->name, effect)) return 1;
+    PN_GROW(pn.frames, pn.frames_len, pn.frames_cap);
+    pn.frames[pn.frames_len++] = (pn_frame){mark->name, mark->effect, base, pn.returns_len};
+    return 0;
+}
+
+static int w_ret(void) {
+    if (pn.frames_len > 0) {
+        pn_frame *frame = &pn.frames[pn.frames_len - 1];
+        if (frame->return_depth == pn.returns_len) {
+            const pn_effect *effect = &pn_effects[frame->effect];
+            long declared = (long)effect->noutputs - (long)effect->ninputs;
+            long changed = (long)pn.len - (long)(frame->base + (size_t)effect->ninputs);
+            if (changed != declared) {
+                pn_buf message = {0};
+                pn_buf_printf(&message, "Proc %s ", frame->name->bytes);
+                pn_buf_effect(&message, effect);
+                pn_buf_printf(&message, " should change the stack depth by %+ld but changed it by %+ld",
+                              declared, changed);
+                return pn_fail_with(&message);
+            }
+            if (pn_check_slots(frame->base, effect->outputs, effect->noutputs, frame->name, effect)) {
+                return 1;
+            }
+            pn.frames_len--;
+        }
+    }
+    if (pn.returns_len == 0) return pn_fail("Cannot pop from an empty stack");
+    pn.jump = pn.returns[--pn.returns_len] + 1;
+    return 0;
+}
+
+static void w_try(long catch_pc) {
+    PN_GROW(pn.handlers, pn.handlers_len, pn.handlers_cap);
+    pn.handlers[pn.handlers_len++] = (pn_handler){catch_pc, pn.len, pn.returns_len};
+}
+
+static void w_catch(void) {
+    if (pn.handlers_len > 0) pn.handlers_len--;
+}
+
+/* Input */
+
+static int w_read_line(void) {
+    pn_buf line = {0};
+    int c, read = 0;
+    while ((c = getchar()) != EOF) {
+        read = 1;
+        if (c == '\n') break;
+        char byte = (char)c;
+        pn_buf_add(&line, &byte, 1);
+    }
+    if (!read) {
+        pn_push(pn_none());
+        return 0;
+    }
+    if (c == '\n' && line.len > 0 && line.bytes[line.len - 1] == '\r') line.len--;
+    pn_push(pn_buf_take(&line));
+    return 0;
+}
+
+static int w_read_all(void) {
+    pn_buf all = {0};
+    char chunk[4096];
+    size_t n;
+    while ((n = fread(chunk, 1, sizeof(chunk), stdin)) > 0) pn_buf_add(&all, chunk, n);
+    pn_push(pn_buf_take(&all));
+    return 0;
+}
+
+static int w_eof(void) {
+    int c = getchar();
+    if (c != EOF) ungetc(c, stdin);
+    pn_push(pn_bool(c == EOF));
+    return 0;
+}
+
+/* Testing */
+
+static void pn_buf_describe(pn_buf *b, pn_data d) {
+    pn_buf_data(b, d);
+    pn_buf_printf(b, " (%s)", pn_type_name((int)d.tag));
+}
+
+static int w_assert(void) {
+    pn_data value;
+    if (pn_pop(&value)) return 1;
+    if (pn_is_false(value)) {
+        pn_buf message = {0};
+        pn_buf_str(&message, "Assertion failed: expected a true value, got ");
+        pn_buf_describe(&message, value);
+        pn_release(value);
+        return pn_fail_with(&message);
+    }
+    pn_release(value);
+    return 0;
+}
+
+static int w_assert_eq(void) {
+    pn_data expected, actual;
+    if (pn_pop(&expected)) return 1;
+    if (pn_pop(&actual)) {
+        pn_release(expected);
+        return 1;
+    }
+    int equal;
+    if (actual.tag == PN_INT && expected.tag == PN_FLOAT) {
+        equal = (double)actual.as.i == (double)expected.as.f;
+    } else if (actual.tag == PN_FLOAT && expected.tag == PN_INT) {
+        equal = (double)expected.as.i == (double)actual.as.f;
+    } else {
+        equal = pn_equal(actual, expected);
+    }
+    if (!equal) {
+        pn_buf message = {0};
+        pn_buf_str(&message, "Assertion failed: expected ");
+        pn_buf_describe(&message, expected);
+        pn_buf_str(&message, ", got ");
+        pn_buf_describe(&message, actual);
+        pn_release(expected);
+        pn_release(actual);
+        return pn_fail_with(&message);
+    }
+    pn_release(expected);
+    pn_release(actual);
+    return 0;
+}
+
+/* Formatting, `{}` and `{:[[fill]align][0][width][.precision]}` placeholders */
+
+typedef struct {
+    char fill[5];
+    char align;
+    int zero;
+    size_t width;
+    long precision;
+} pn_spec;
+
+static size_t pn_utf8_len(const char *bytes, size_t len) {
+    size_t count = 0;
+    for (size_t i = 0; i < len; i++) {
+        if (((unsigned char)bytes[i] & 0xc0) != 0x80) count++;
+    }
+    return count;
+}
+
+/* Length in bytes of the UTF-8 character starting at `c` */
+static size_t pn_utf8_char(const char *c) {
+    unsigned char first = (unsigned char)*c;
+    if (first < 0x80) return 1;
+    if (first < 0xe0) return 2;
+    if (first < 0xf0) return 3;
+    return 4;
+}
+
+static int pn_is_align(const char *c) {
+    return *c == '<' || *c == '>' || *c == '^';
+}
+
+static int pn_parse_spec(const char *spec, size_t len, pn_spec *result) {
+    memcpy(result->fill, " ", 2);
+    result->align = 0;
+    result->zero = 0;
+    result->width = 0;
+    result->precision = -1;
+    size_t i = 0;
+    size_t first = len > 0 ? pn_utf8_char(spec) : 0;
+    if (len > first && pn_is_align(spec + first)) {
+        memcpy(result->fill, spec, first);
+        result->fill[first] = 0;
+        result->align = spec[first];
+        i = first + 1;
+    } else if (len > 0 && pn_is_align(spec)) {
+        result->align = spec[0];
+        i = 1;
+    }
+    if (i < len && spec[i] == '0') {
+        result->zero = 1;
+        i++;
+    }
+    while (i < len && spec[i] >= '0' && spec[i] <= '9') result->width = result->width * 10 + (size_t)(spec[i++] - '0');
+    if (i < len && spec[i] == '.') {
+        size_t start = ++i;
+        long precision = 0;
+        while (i < len && spec[i] >= '0' && spec[i] <= '9') precision = precision * 10 + (spec[i++] - '0');
+        if (i == start) return pn_fail("Missing precision in format spec: %.*s", (int)len, spec);
+        result->precision = precision;
+    }
+    if (i != len) return pn_fail("Invalid format spec: %.*s", (int)len, spec);
+    return 0;
+}
+
+/* Goes through a template, formatting the values into `out` when there are any. Counts the
+ * placeholders in `count` */
+static int pn_format_template(const char *t, size_t len, const pn_data *values, size_t *count,
+                              pn_buf *out) {
+    *count = 0;
+    for (size_t i = 0; i < len; i++) {
+        if (t[i] == '{' && i + 1 < len && t[i + 1] == '{') {
+            if (out) pn_buf_str(out, "{");
+            i++;
+        } else if (t[i] == '}' && i + 1 < len && t[i + 1] == '}') {
+            if (out) pn_buf_str(out, "}");
+            i++;
+        } else if (t[i] == '{') {
+            size_t start = i + 1, end = start;
+            while (end < len && t[end] != '}') end++;
+            if (end == len) return pn_fail("Unclosed { in format string");
+            const char *spec = t + start;
+            size_t spec_len = end - start;
+            if (spec_len > 0) {
+                if (spec[0] != ':') {
+                    return pn_fail("Invalid placeholder in format string: {%.*s}", (int)spec_len, spec);
+                }
+                spec++;
+                spec_len--;
+            }
+            pn_spec parsed;
+            if (pn_parse_spec(spec, spec_len, &parsed)) return 1;
+            if (out) {
+                pn_data value = values[*count];
+                pn_buf text = {0};
+                if (parsed.precision >= 0 && value.tag == PN_INT) {
+                    pn_buf_printf(&text, "%.*f", (int)parsed.precision, (double)value.as.i);
+                } else if (parsed.precision >= 0 && value.tag == PN_FLOAT) {
+                    pn_buf_printf(&text, "%.*f", (int)parsed.precision, (double)value.as.f);
+                } else {
+                    pn_buf_data(&text, value);
+                    if (parsed.precision >= 0) {
+                        size_t chars = 0, at = 0;
+                        while (at < text.len && chars < (size_t)parsed.precision) {
+                            at += pn_utf8_char(text.bytes + at);
+                            chars++;
+                        }
+                        text.len = at;
+                    }
+                }
+                size_t chars = pn_utf8_len(text.bytes ? text.bytes : "", text.len);
+                size_t padding = chars >= parsed.width ? 0 : parsed.width - chars;
+                if (padding > 0 && parsed.zero && !parsed.align && pn_is_number(value)) {
+                    /* Zeros go between the sign and the digits */
+                    size_t sign = text.bytes[0] == '-';
+                    pn_buf_add(out, text.bytes, sign);
+                    for (size_t p = 0; p < padding; p++) pn_buf_str(out, "0");
+                    pn_buf_add(out, text.bytes + sign, text.len - sign);
+                } else {
+                    char align = parsed.align ? parsed.align : pn_is_number(value) ? '>' : '<';
+                    size_t before = align == '<' ? 0 : align == '>' ? padding : padding / 2;
+                    for (size_t p = 0; p < before; p++) pn_buf_str(out, parsed.fill);
+                    if (text.len) pn_buf_add(out, text.bytes, text.len);
+                    for (size_t p = before; p < padding; p++) pn_buf_str(out, parsed.fill);
+                }
+                free(text.bytes);
+            }
+            (*count)++;
+            i = end;
+        } else if (t[i] == '}') {
+            return pn_fail("Unmatched } in format string");
+        } else if (out) {
+            pn_buf_add(out, t + i, 1);
+        }
+    }
+    return 0;
+}
+
+/* Pops the template and its values, leaving the formatted string in `out` */
+static int pn_format_from_stack(pn_buf *out) {
+    pn_data template;
+    if (pn_pop(&template)) return 1;
+    pn_buf text = {0};
+    pn_buf_data(&text, template);
+    size_t count;
+    if (pn_format_template(text.bytes ? text.bytes : "", text.len, NULL, &count, NULL)) {
+        free(text.bytes);
+        pn_push(template);
+        return 1;
+    }
+    if (count > pn.len) {
+        free(text.bytes);
+        pn_push(template);
+        return pn_fail("Format string needs %zu values, but the stack has %zu", count, pn.len);
+    }
+    pn.len -= count;
+    int failed = pn_format_template(text.bytes ? text.bytes : "", text.len, pn.stack + pn.len, &count, out);
+    for (size_t i = 0; i < count; i++) pn_release(pn.stack[pn.len + i]);
+    pn_release(template);
+    free(text.bytes);
+    return failed;
+}
+
+static int w_format(void) {
+    pn_buf out = {0};
+    if (pn_format_from_stack(&out)) {
+        free(out.bytes);
+        return 1;
+    }
+    pn_push(pn_buf_take(&out));
+    return 0;
+}
+
+static int w_printf(void) {
+    pn_buf out = {0};
+    if (pn_format_from_stack(&out)) {
+        free(out.bytes);
+        return 1;
+    }
+    if (out.len) fwrite(out.bytes, 1, out.len, stdout);
+    free(out.bytes);
+    return 0;
+}
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use super::data::Data;
 
 pub struct Variable {
@@ -17,101 +19,159 @@ impl Variable {
     }
 }
 
+/// Variables of one kind, stored in slots that names are resolved to once.
+#[derive(Default)]
+struct Scope {
+    slots: HashMap<String, usize>,
+    names: Vec<String>,
+    /// The variable in every slot, `None` until it is stored and after it is removed
+    variables: Vec<Option<Variable>>,
+}
+
+impl Scope {
+    fn slot(&mut self, name: &str) -> usize {
+        if let Some(&slot) = self.slots.get(name) {
+            return slot;
+        }
+        let slot = self.names.len();
+        self.slots.insert(name.to_string(), slot);
+        self.names.push(name.to_string());
+        self.variables.push(None);
+        slot
+    }
+
+    fn store(&mut self, slot: usize, value: Data) {
+        match &mut self.variables[slot] {
+            Some(variable) => variable.value = value,
+            empty => *empty = Some(Variable::new(self.names[slot].clone(), value)),
+        }
+    }
+
+    fn stored(&self) -> Vec<&Variable> {
+        self.variables.iter().flatten().collect()
+    }
+}
+
+/// Local and global variables. Words that use a variable have its name resolved to a slot when
+/// the program is linked and access it by slot, names are only looked up for the debugger and
+/// string interpolation.
 pub struct Variables {
-    variables: Vec<Variable>,
-    local: Vec<Variable>,
+    variables: Scope,
+    local: Scope,
+}
+
+impl Default for Variables {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Variables {
     pub fn new() -> Variables {
         Variables {
-            variables: Vec::new(),
-            local: Vec::new(),
+            variables: Scope::default(),
+            local: Scope::default(),
         }
     }
 
-    pub fn add(&mut self, name: String, value: Data, local: bool) {
+    fn scope(&self, local: bool) -> &Scope {
         if local {
-            self.local.push(Variable::new(name, value));
+            &self.local
         } else {
-            self.variables.push(Variable::new(name, value));
+            &self.variables
         }
     }
 
-    pub fn get(&self, name: &str, local: bool) -> Option<&Data> {
+    fn scope_mut(&mut self, local: bool) -> &mut Scope {
         if local {
-            for variable in &self.local {
-                if variable.name == name {
-                    return Some(&variable.value);
-                }
-            }
+            &mut self.local
         } else {
-            for variable in &self.variables {
-                if variable.name == name {
-                    return Some(&variable.value);
-                }
-            }
+            &mut self.variables
         }
-        None
     }
 
-    pub fn set(&mut self, name: &str, value: Data, local: bool) {
-        if local {
-            for variable in &mut self.local {
-                if variable.name == name {
-                    variable.value = value;
-                    return;
-                }
-            }
-        } else {
-            for variable in &mut self.variables {
-                if variable.name == name {
-                    variable.value = value;
-                    return;
-                }
-            }
+    /// Slot of the variable `name`, the same one every time it is asked for.
+    pub fn slot(&mut self, name: &str, local: bool) -> usize {
+        self.scope_mut(local).slot(name)
+    }
+
+    /// Name of the variable in `slot`.
+    pub fn name(&self, slot: usize, local: bool) -> Option<&str> {
+        self.scope(local).names.get(slot).map(|name| name.as_str())
+    }
+
+    pub fn load(&self, slot: usize, local: bool) -> Option<&Data> {
+        match self.scope(local).variables.get(slot) {
+            Some(Some(variable)) => Some(&variable.value),
+            _ => None,
+        }
+    }
+
+    pub fn store(&mut self, slot: usize, value: Data, local: bool) {
+        self.scope_mut(local).store(slot, value);
+    }
+
+    pub fn unset(&mut self, slot: usize, local: bool) {
+        if let Some(variable) = self.scope_mut(local).variables.get_mut(slot) {
+            *variable = None;
         }
-        self.add(name.to_string(), value, local);
+    }
+
+    /// Stores a variable by name, replacing its value if it exists.
+    pub fn add(&mut self, name: String, value: Data, local: bool) {
+        self.set(&name, value, local);
+    }
+
+    pub fn get(&self, name: &str, local: bool) -> Option<&Data> {
+        let slot = *self.scope(local).slots.get(name)?;
+        self.load(slot, local)
+    }
+
+    pub fn set(&mut self, name: &str, value: Data, local: bool) {
+        let slot = self.slot(name, local);
+        self.store(slot, value, local);
     }
 
     pub fn remove(&mut self, name: &str, local: bool) {
-        if local {
-            self.local.retain(|variable| variable.name != name);
-        } else {
-            self.variables.retain(|variable| variable.name != name);
+        if let Some(&slot) = self.scope(local).slots.get(name) {
+            self.unset(slot, local);
         }
     }
 
+    /// Removes the values of the globals, their slots stay resolved.
     pub fn remove_globals(&mut self) {
-        self.variables.clear();
+        self.variables.variables.fill_with(|| None);
     }
 
+    /// Removes the values of the locals, their slots stay resolved.
     pub fn remove_locals(&mut self) {
-        self.local.clear();
+        self.local.variables.fill_with(|| None);
     }
 
     pub fn remove_all(&mut self) {
-        self.local.clear();
-        self.variables.clear();
+        self.remove_locals();
+        self.remove_globals();
     }
 
-    pub fn locals(&self) -> &Vec<Variable> {
-        &self.local
+    /// The local variables that are stored, in the order they were first stored.
+    pub fn locals(&self) -> Vec<&Variable> {
+        self.local.stored()
     }
 
-    pub fn globals(&self) -> &Vec<Variable> {
-        &self.variables
+    /// The global variables that are stored, in the order they were first stored.
+    pub fn globals(&self) -> Vec<&Variable> {
+        self.variables.stored()
     }
 }
 
 impl std::fmt::Display for Variables {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         writeln!(f, "Local variables:")?;
-        for variable in self.local.iter().rev() {
+        for variable in self.locals().iter().rev() {
             writeln!(f, "  {}", variable)?;
         }
         writeln!(f, "Global variables:")?;
-        for variable in self.variables.iter().rev() {
+        for variable in self.globals().iter().rev() {
             writeln!(f, "  {}", variable)?;
         }
         Ok(())
@@ -149,8 +209,8 @@ mod tests {
             fn add() {
                 let mut variables = Variables::new();
                 variables.add("name".to_string(), Data::Float(42.0), true);
-                assert_eq!(variables.local.len(), 1);
-                assert_eq!(variables.variables.len(), 0);
+                assert_eq!(variables.locals().len(), 1);
+                assert_eq!(variables.globals().len(), 0);
             }
 
             #[test]
@@ -194,8 +254,8 @@ mod tests {
             fn add() {
                 let mut variables = Variables::new();
                 variables.add("name".to_string(), Data::Float(42.0), false);
-                assert_eq!(variables.local.len(), 0);
-                assert_eq!(variables.variables.len(), 1);
+                assert_eq!(variables.locals().len(), 0);
+                assert_eq!(variables.globals().len(), 1);
             }
 
             #[test]
@@ -232,5 +292,60 @@ mod tests {
                 assert_eq!(variables.get("name2", false), None);
             }
         }
+
+        mod slots {
+            use super::*;
+
+            #[test]
+            fn resolved_once() {
+                let mut variables = Variables::new();
+                let slot = variables.slot("name", true);
+                assert_eq!(variables.slot("other", true), slot + 1);
+                assert_eq!(variables.slot("name", true), slot);
+                // Locals and globals have their own slots
+                assert_eq!(variables.slot("name", false), 0);
+                assert_eq!(variables.name(slot, true), Some("name"));
+            }
+
+            #[test]
+            fn store_replaces() {
+                let mut variables = Variables::new();
+                let slot = variables.slot("name", true);
+                assert_eq!(variables.load(slot, true), None);
+                variables.store(slot, Data::Int(1), true);
+                variables.store(slot, Data::Int(2), true);
+                assert_eq!(variables.load(slot, true), Some(&Data::Int(2)));
+                assert_eq!(variables.get("name", true), Some(&Data::Int(2)));
+                assert_eq!(variables.locals().len(), 1);
+
+                variables.add("name".to_string(), Data::Int(3), true);
+                assert_eq!(variables.load(slot, true), Some(&Data::Int(3)));
+            }
+
+            #[test]
+            fn unset_keeps_slot() {
+                let mut variables = Variables::new();
+                variables.add("name".to_string(), Data::Int(1), true);
+                variables.remove("name", true);
+                assert!(variables.locals().is_empty());
+                let slot = variables.slot("name", true);
+                assert_eq!(slot, 0);
+                variables.store(slot, Data::Int(2), true);
+                assert_eq!(variables.get("name", true), Some(&Data::Int(2)));
+            }
+
+            #[test]
+            fn display() {
+                let mut variables = Variables::new();
+                variables.add("a".to_string(), Data::Int(1), true);
+                variables.add("b".to_string(), Data::Int(2), true);
+                variables.add("g".to_string(), Data::from_str("x"), false);
+                variables.remove("b", true);
+                assert_eq!(
+                    variables.to_string(),
+                    "Local variables:\n  a = 1\nGlobal variables:\n  g = x\n"
+                );
+            }
+        }
     }
 }
@@ -1,23 +1,95 @@
 pub mod args;
+pub mod commands;
 pub mod engine;
 pub mod tokens;
 
 use log::{error, info};
 
+use crate::args::Command;
 use crate::engine::machine::Machine;
+use crate::engine::runtime::Exit;
 use crate::tokens::tokens;
 
 fn main() {
     pretty_env_logger::init();
     let mut args = args::parse_args();
+    if let Some(Command::Test { paths }) = &args.command {
+        match commands::test::run(&args, paths) {
+            Ok(true) => std::process::exit(0),
+            Ok(false) => std::process::exit(1),
+            Err(e) => {
+                error!("Error running tests: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(Command::Check { paths, types }) = &args.command {
+        match commands::check::run(&args, paths, *types) {
+            Ok(true) => std::process::exit(0),
+            Ok(false) => std::process::exit(1),
+            Err(e) => {
+                error!("Error checking: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(Command::Fmt { paths, check }) = &args.command {
+        match commands::fmt::run(&args, paths, *check) {
+            Ok(true) => std::process::exit(0),
+            Ok(false) => std::process::exit(1),
+            Err(e) => {
+                error!("Error formatting: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(Command::Build { file, output }) = &args.command {
+        if let Err(e) = commands::build::run(&args, file, output.as_deref()) {
+            error!("Error building: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(Command::EmitC { file, output }) = &args.command {
+        if let Err(e) = commands::emit_c::run(&args, file, output.as_deref()) {
+            error!("Error emitting C: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(Command::Bench { against, runs }) = &args.command {
+        if let Err(e) = commands::bench::run(&args, against.as_deref(), *runs) {
+            error!("Error benchmarking: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(Command::Dap) = &args.command {
+        if let Err(e) = commands::dap::run(&args) {
+            error!("Error in debug adapter: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(Command::Lsp { types }) = &args.command {
+        if let Err(e) = commands::lsp::run(&args, *types) {
+            error!("Error in language server: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
     if args.file == "NONE" {
         if args.args.is_empty() {
-            error!("No file or arguments provided");
-            std::process::exit(1);
+            if let Err(e) = commands::repl::run(&args) {
+                error!("Error in REPL: {}", e);
+                std::process::exit(1);
+            }
+            return;
         }
         args.file = args.args[0].clone();
     }
     info!("Starting interpreter on file: {}", args.file);
+    let optimize = args.optimize;
 
     let mut machine = Machine::new(args);
     info!("Machine created");
@@ -32,33 +104,50 @@ fn main() {
         }
     };
 
-    match machine.lex() {
-        Ok(_) => {}
-        Err(e) => {
-            error!("Error during lexing: {}", e);
+    if machine.is_compiled() {
+        if let Err(e) = machine.load_bytecode() {
+            error!("Error loading bytecode: {}", e);
             std::process::exit(1);
         }
-    };
+    } else {
+        match machine.lex() {
+            Ok(_) => {}
+            Err(e) => {
+                error!("Error during lexing: {}", e);
+                std::process::exit(1);
+            }
+        };
 
-    match machine.after_lex() {
-        Ok(_) => {}
-        Err(e) => {
-            error!("Error after lexing: {}", e);
-            std::process::exit(1);
-        }
-    };
+        match machine.after_lex() {
+            Ok(_) => {}
+            Err(e) => {
+                error!("Error after lexing: {}", e);
+                std::process::exit(1);
+            }
+        };
 
-    match machine.after_lex() {
-        Ok(_) => {}
-        Err(e) => {
-            error!("Error after lexing: {}", e);
-            std::process::exit(1);
+        match machine.after_lex() {
+            Ok(_) => {}
+            Err(e) => {
+                error!("Error after lexing: {}", e);
+                std::process::exit(1);
+            }
+        };
+
+        if optimize {
+            if let Err(e) = machine.optimize() {
+                error!("Error optimizing: {}", e);
+                std::process::exit(1);
+            }
         }
-    };
+    }
 
     match machine.interpret() {
         Ok(_) => {}
         Err(e) => {
+            if let Some(Exit(code)) = e.downcast_ref::<Exit>() {
+                std::process::exit(*code);
+            }
             error!("Error during interpretation: {}", e);
             std::process::exit(1);
         }
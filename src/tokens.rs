@@ -1,166 +1,322 @@
+pub mod env;
+pub mod format;
+pub mod fs;
+pub mod input;
+pub mod path;
+pub mod process;
+pub mod random;
+pub mod testing;
+pub mod time;
+
 use anyhow::Result;
+use std::io::Write;
 
-use super::engine::data::Data;
-use super::engine::lexer::token::{TokenKind, TokenType};
-use super::engine::mark::MarkList;
+use super::engine::data::{Data, Type};
+use super::engine::lexer::token::{Effect, Signature, TokenKind, TokenType, VariableScope};
+use super::engine::mark::{MarkList, Slot, StackEffect};
+use super::engine::runtime::{Exit, Frame, Handler, Runtime, Thrown};
 use super::engine::stack::Stack;
 use super::engine::variables::Variables;
 
+/// Slot the name of a variable word was resolved to when linking, see `Machine::resolve`.
+fn variable_slot(data: &Data) -> Result<usize> {
+    match data {
+        Data::Int(slot) => Ok(*slot as usize),
+        _ => Err(anyhow::anyhow!("Variable name must be a string")),
+    }
+}
+
+/// Pushes the result of the integer operation `word` on `b` and `a`, or pushes them back and fails
+/// when there is none because it overflowed, divided by zero or had a negative exponent.
+fn push_checked(
+    stack: &mut Stack,
+    word: &str,
+    b: Data,
+    a: Data,
+    result: Option<i64>,
+) -> Result<()> {
+    match result {
+        Some(result) => {
+            stack.push(Data::from_int(result));
+            Ok(())
+        }
+        None => {
+            let message = match (word, a.as_int()?) {
+                ("/" | "%", 0) => "Division by zero",
+                ("^", a) if a < 0 => "Negative exponent",
+                _ => "Integer overflow",
+            };
+            stack.push(b);
+            stack.push(a);
+            Err(anyhow::anyhow!(message))
+        }
+    }
+}
+
+/// Pops the element count `n` and then `n` elements, returning them in stack order.
+pub(crate) fn pop_n(stack: &mut Stack) -> Result<Vec<Data>> {
+    let count = stack.pop()?;
+    if !count.is_int() || count.as_int()? < 0 {
+        stack.push(count);
+        return Err(anyhow::anyhow!(
+            "Element count must be a non-negative integer"
+        ));
+    }
+    let count = count.as_int()? as usize;
+    if count > stack.len() {
+        stack.push(Data::from_int(count as i64));
+        return Err(anyhow::anyhow!("Not enough elements on the stack"));
+    }
+    let mut elements = Vec::with_capacity(count);
+    for _ in 0..count {
+        elements.push(stack.pop()?);
+    }
+    elements.reverse();
+    Ok(elements)
+}
+
+/// Pops a string, leaving any other value on the stack. `what` names it in the error.
+pub(crate) fn pop_string(stack: &mut Stack, what: &str) -> Result<String> {
+    let value = stack.pop()?;
+    if !value.is_string() {
+        stack.push(value);
+        return Err(anyhow::anyhow!("{} must be a string", what));
+    }
+    value.as_string()
+}
+
+/// Pushes the elements followed by their count, the inverse of `pop_n`.
+pub(crate) fn push_n(stack: &mut Stack, elements: Vec<Data>) {
+    let count = elements.len();
+    for element in elements {
+        stack.push(element);
+    }
+    stack.push(Data::from_int(count as i64));
+}
+
+/// Types of the numeric operators: two ints or two floats, which are never mixed.
+fn arithmetic(types: &[Type]) -> std::result::Result<Vec<Type>, String> {
+    match (types[0], types[1]) {
+        (Type::Int | Type::Unknown, Type::Int | Type::Unknown)
+        | (Type::Float | Type::Unknown, Type::Float | Type::Unknown) => {
+            Ok(vec![types[0].merge(types[1])])
+        }
+        (Type::Int, Type::Float) | (Type::Float, Type::Int) => {
+            Err("mixes int and float".to_string())
+        }
+        (Type::Int | Type::Float | Type::Unknown, other) | (other, _) => {
+            Err(format!("needs numbers but got {}", other))
+        }
+    }
+}
+
+fn compare(types: &[Type]) -> std::result::Result<Vec<Type>, String> {
+    arithmetic(types).map(|_| vec![Type::Bool])
+}
+
+fn equal(types: &[Type]) -> std::result::Result<Vec<Type>, String> {
+    match (types[0], types[1]) {
+        (Type::Int, Type::Float) | (Type::Float, Type::Int) => {
+            Err("mixes int and float".to_string())
+        }
+        (a, b) if a.fits(b) => Ok(vec![Type::Bool]),
+        (a, b) => Err(format!("compares {} with {}", a, b)),
+    }
+}
+
+fn negate(types: &[Type]) -> std::result::Result<Vec<Type>, String> {
+    match types[0] {
+        Type::Int | Type::Float | Type::Unknown => Ok(vec![types[0]]),
+        other => Err(format!("needs a number but got {}", other)),
+    }
+}
+
+/// Checks the values from `base` up against the declared types of a proc's inputs or outputs.
+fn check_slots(
+    stack: &Stack,
+    base: usize,
+    slots: &[Slot],
+    name: &str,
+    effect: &StackEffect,
+) -> Result<()> {
+    for (slot, value) in slots.iter().zip(&stack.elements()[base..]) {
+        if !value.type_of().fits(slot.type_) {
+            return Err(anyhow::anyhow!(
+                "Proc {} {} expects {} to be {} but got {}",
+                name,
+                effect,
+                slot.name,
+                slot.type_,
+                value.type_name()
+            ));
+        }
+    }
+    Ok(())
+}
+
 pub fn tokens() -> Vec<TokenType> {
-    vec![
-        // Push a value to the stack
+    let mut tokens = vec![
         TokenType::reg(
             TokenKind::Push,
             "push",
             "^\\((.+)\\)", // Captures anything exept whitespace inside ()
-            |stack: &mut Stack, _, _, _, _, add_value| -> Result<()> {
-                stack.push(add_value);
+            |stack: &mut Stack, _, variables: &mut Variables, _, _, _, add_value| -> Result<()> {
+                // String literals can reference variables as {<name} and {<<name}
+                if let Data::String(literal) = &add_value {
+                    if literal.contains("{<") {
+                        stack.push(Data::from_string(format::interpolate(literal, variables)?));
+                        return Ok(());
+                    }
+                }
+                stack.push(add_value.clone());
                 Ok(())
             },
-        ),
-        // Store a variable from name on the stack
+        )
+        .doc("Push a value to the stack")
+        .effect(Effect::Fixed(0, 1)),
         TokenType::reg(
             TokenKind::Push,
-            "push",
+            "store",
             "^>\\((.+)\\)", // Captures anything exept whitespace inside >{}
-            |stack: &mut Stack, _, variables: &mut Variables, _, _, name| -> Result<()> {
-                if !name.is_string() {
-                    stack.push(name);
-                    return Err(anyhow::anyhow!("Variable name must be a string"));
-                }
+            |stack: &mut Stack, _, variables: &mut Variables, _, _, _, slot| -> Result<()> {
+                let slot = variable_slot(slot)?;
                 let value = stack.pop()?;
-                variables.add(name.as_string()?, value, true);
+                variables.store(slot, value, true);
                 Ok(())
             },
-        ),
-        // Load a variable from the name on the stack
+        )
+        .doc("Store a variable from name on the stack")
+        .effect(Effect::Fixed(1, 0))
+        .variable(VariableScope::Local),
         TokenType::reg(
             TokenKind::Push,
             "load",
             "^<\\((.+)\\)", // Captures anything exept whitespace inside <{}
-            |stack: &mut Stack, _, variables: &mut Variables, _, _, name| -> Result<()> {
-                if !name.is_string() {
-                    stack.push(name);
-                    return Err(anyhow::anyhow!("Variable name must be a string"));
-                }
-                if let Some(value) = variables.get(&name.as_string()?, true) {
-                    stack.push(value.clone());
-                } else {
-                    stack.push(name);
-                    return Err(anyhow::anyhow!("Variable not found"));
+            |stack: &mut Stack, _, variables: &mut Variables, _, _, _, slot| -> Result<()> {
+                let slot = variable_slot(slot)?;
+                match variables.load(slot, true) {
+                    Some(value) => stack.push(value.clone()),
+                    None => {
+                        let name = variables.name(slot, true).unwrap_or_default();
+                        stack.push(Data::from_str(name));
+                        return Err(anyhow::anyhow!("Variable not found"));
+                    }
                 }
                 Ok(())
             },
-        ),
-        // Remove a variable from the name on the stack
+        )
+        .doc("Load a variable from the name on the stack")
+        .effect(Effect::Fixed(0, 1))
+        .variable(VariableScope::Local),
         TokenType::reg(
             TokenKind::Push,
             "remove",
             "^\\^\\((.+)\\)", // Captures anything exept whitespace inside ^{}
-            |stack: &mut Stack, _, variables: &mut Variables, _, _, name| -> Result<()> {
-                if !name.is_string() {
-                    stack.push(name);
-                    return Err(anyhow::anyhow!("Variable name must be a string"));
-                }
-                variables.remove(&name.as_string()?, true);
+            |_, _, variables: &mut Variables, _, _, _, slot| -> Result<()> {
+                variables.unset(variable_slot(slot)?, true);
                 Ok(())
             },
-        ),
-        // Store a global variable from name on the stack
+        )
+        .doc("Remove a variable from the name on the stack")
+        .effect(Effect::Fixed(0, 0))
+        .variable(VariableScope::Local),
         TokenType::reg(
             TokenKind::Push,
-            "push",
+            "store-global",
             "^>>\\((.+)\\)", // Captures anything exept whitespace inside >>{}
-            |stack: &mut Stack, _, variables: &mut Variables, _, _, name| -> Result<()> {
-                if !name.is_string() {
-                    stack.push(name);
-                    return Err(anyhow::anyhow!("Variable name must be a string"));
-                }
+            |stack: &mut Stack, _, variables: &mut Variables, _, _, _, slot| -> Result<()> {
+                let slot = variable_slot(slot)?;
                 let value = stack.pop()?;
-                variables.add(name.as_string()?, value, false);
+                variables.store(slot, value, false);
                 Ok(())
             },
-        ),
-        // Load a global variable from the name on the stack
+        )
+        .doc("Store a global variable from name on the stack")
+        .effect(Effect::Fixed(1, 0))
+        .variable(VariableScope::Global),
         TokenType::reg(
             TokenKind::Push,
-            "load",
+            "load-global",
             "^<<\\((.+)\\)", // Captures anything exept whitespace inside <<{}
-            |stack: &mut Stack, _, variables: &mut Variables, _, _, name| -> Result<()> {
-                if !name.is_string() {
-                    stack.push(name);
-                    return Err(anyhow::anyhow!("Variable name must be a string"));
-                }
-                if let Some(value) = variables.get(&name.as_string()?, false) {
-                    stack.push(value.clone());
-                } else {
-                    stack.push(name);
-                    return Err(anyhow::anyhow!("Variable not found"));
+            |stack: &mut Stack, _, variables: &mut Variables, _, _, _, slot| -> Result<()> {
+                let slot = variable_slot(slot)?;
+                match variables.load(slot, false) {
+                    Some(value) => stack.push(value.clone()),
+                    None => {
+                        let name = variables.name(slot, false).unwrap_or_default();
+                        stack.push(Data::from_str(name));
+                        return Err(anyhow::anyhow!("Variable not found"));
+                    }
                 }
                 Ok(())
             },
-        ),
-        // Remove a global variable from the name on the stack
+        )
+        .doc("Load a global variable from the name on the stack")
+        .effect(Effect::Fixed(0, 1))
+        .variable(VariableScope::Global),
         TokenType::reg(
             TokenKind::Push,
-            "remove",
+            "remove-global",
             "^\\^\\^\\((.+)\\)", // Captures anything exept whitespace inside ^^{}
-            |stack: &mut Stack, _, variables: &mut Variables, _, _, name| -> Result<()> {
-                if !name.is_string() {
-                    stack.push(name);
-                    return Err(anyhow::anyhow!("Variable name must be a string"));
-                }
-                variables.remove(&name.as_string()?, false);
+            |_, _, variables: &mut Variables, _, _, _, slot| -> Result<()> {
+                variables.unset(variable_slot(slot)?, false);
                 Ok(())
             },
-        ),
-        // Pop and print the top value from the stack
+        )
+        .doc("Remove a global variable from the name on the stack")
+        .effect(Effect::Fixed(0, 0))
+        .variable(VariableScope::Global),
         TokenType::reg(
             TokenKind::Function,
             ".",
             "\\.",
-            |stack: &mut Stack, _, _, _, _, _| -> Result<()> {
-                print!("{}", stack.pop()?);
+            |stack: &mut Stack, _, _, _, _, runtime: &mut Runtime, _| -> Result<()> {
+                write!(runtime.output, "{}", stack.pop()?)?;
                 Ok(())
             },
-        ),
-        // Print the top value from the stack
+        )
+        .doc("Pop and print the top value from the stack")
+        .effect(Effect::Fixed(1, 0)),
         TokenType::reg(
             TokenKind::Function,
             ",",
             ",",
-            |stack: &mut Stack, _, _, _, _, _| -> Result<()> {
+            |stack: &mut Stack, _, _, _, _, runtime: &mut Runtime, _| -> Result<()> {
                 let a = match stack.last() {
                     Some(a) => a,
                     None => {
                         return Err(anyhow::anyhow!("Cannot print empty stack"));
                     }
                 };
-                print!("{}", a);
+                write!(runtime.output, "{}", a)?;
                 Ok(())
             },
-        ),
-        // Print \n
+        )
+        .doc("Print the top value from the stack")
+        .effect(Effect::Fixed(1, 1))
+        .signature(Signature::Rule(|types| Ok(vec![types[0]]))),
         TokenType::reg(
             TokenKind::Function,
             "nl",
             "nl",
-            |_, _, _, _, _, _| -> Result<()> {
-                println!();
+            |_, _, _, _, _, runtime: &mut Runtime, _| -> Result<()> {
+                writeln!(runtime.output)?;
                 Ok(())
             },
-        ),
-        // Add the top two values from the stack
+        )
+        .doc("Print a newline")
+        .effect(Effect::Fixed(0, 0)),
         TokenType::reg(
             TokenKind::Function,
             "+",
             "\\+",
-            |stack: &mut Stack, _, _, _, _, _| -> Result<()> {
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
                 let a = stack.pop()?;
                 let b = stack.pop()?;
                 if a.is_number() && b.is_number() {
                     if a.is_int() && b.is_int() {
-                        stack.push(Data::from_int(b.as_int()? + a.as_int()?));
+                        let result = b.as_int()?.checked_add(a.as_int()?);
+                        push_checked(stack, "+", b, a, result)?;
                     } else {
                         stack.push(Data::from_float(b.as_float()? + a.as_float()?));
                     }
@@ -171,18 +327,44 @@ pub fn tokens() -> Vec<TokenType> {
                 }
                 Ok(())
             },
-        ),
-        // Subtract the top two values from the stack
+        )
+        .doc("Add the top two values from the stack")
+        .effect(Effect::Fixed(2, 1))
+        .signature(Signature::Rule(arithmetic)),
+        TokenType::reg(
+            TokenKind::Function,
+            "inc",
+            "inc",
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
+                let a = stack.pop()?;
+                // Fails like `(1) +`, which the optimizer turns into this word
+                if a.is_int() {
+                    let result = a.as_int()?.checked_add(1);
+                    push_checked(stack, "+", a, Data::from_int(1), result)?;
+                } else if a.is_float() {
+                    return Err(anyhow::anyhow!("Data is not a float"));
+                } else {
+                    stack.push(a);
+                    stack.push(Data::from_int(1));
+                    return Err(anyhow::anyhow!("Cannot add non-number values"));
+                }
+                Ok(())
+            },
+        )
+        .doc("Add one to the top value from the stack, what `-O` turns `(1) +` into")
+        .effect(Effect::Fixed(1, 1))
+        .signature(Signature::Rule(|types| arithmetic(&[types[0], Type::Int]))),
         TokenType::reg(
             TokenKind::Function,
             "-",
             "-",
-            |stack: &mut Stack, _, _, _, _, _| -> Result<()> {
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
                 let a = stack.pop()?;
                 let b = stack.pop()?;
                 if a.is_number() && b.is_number() {
                     if a.is_int() && b.is_int() {
-                        stack.push(Data::from_int(b.as_int()? - a.as_int()?));
+                        let result = b.as_int()?.checked_sub(a.as_int()?);
+                        push_checked(stack, "-", b, a, result)?;
                     } else {
                         stack.push(Data::from_float(b.as_float()? - a.as_float()?));
                     }
@@ -193,18 +375,21 @@ pub fn tokens() -> Vec<TokenType> {
                 }
                 Ok(())
             },
-        ),
-        // Multiply the top two values from the stack
+        )
+        .doc("Subtract the top two values from the stack")
+        .effect(Effect::Fixed(2, 1))
+        .signature(Signature::Rule(arithmetic)),
         TokenType::reg(
             TokenKind::Function,
             "*",
             "\\*",
-            |stack: &mut Stack, _, _, _, _, _| -> Result<()> {
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
                 let a = stack.pop()?;
                 let b = stack.pop()?;
                 if a.is_number() && b.is_number() {
                     if a.is_int() && b.is_int() {
-                        stack.push(Data::from_int(b.as_int()? * a.as_int()?));
+                        let result = b.as_int()?.checked_mul(a.as_int()?);
+                        push_checked(stack, "*", b, a, result)?;
                     } else {
                         stack.push(Data::from_float(b.as_float()? * a.as_float()?));
                     }
@@ -215,18 +400,21 @@ pub fn tokens() -> Vec<TokenType> {
                 }
                 Ok(())
             },
-        ),
-        // Divide the top two values from the stack
+        )
+        .doc("Multiply the top two values from the stack")
+        .effect(Effect::Fixed(2, 1))
+        .signature(Signature::Rule(arithmetic)),
         TokenType::reg(
             TokenKind::Function,
             "/",
             "/",
-            |stack: &mut Stack, _, _, _, _, _| -> Result<()> {
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
                 let a = stack.pop()?;
                 let b = stack.pop()?;
                 if a.is_number() && b.is_number() {
                     if a.is_int() && b.is_int() {
-                        stack.push(Data::from_int(b.as_int()? / a.as_int()?));
+                        let result = b.as_int()?.checked_div(a.as_int()?);
+                        push_checked(stack, "/", b, a, result)?;
                     } else {
                         stack.push(Data::from_float(b.as_float()? / a.as_float()?));
                     }
@@ -237,18 +425,21 @@ pub fn tokens() -> Vec<TokenType> {
                 }
                 Ok(())
             },
-        ),
-        // Modulo the top two values from the stack
+        )
+        .doc("Divide the top two values from the stack")
+        .effect(Effect::Fixed(2, 1))
+        .signature(Signature::Rule(arithmetic)),
         TokenType::reg(
             TokenKind::Function,
             "%",
             "%",
-            |stack: &mut Stack, _, _, _, _, _| -> Result<()> {
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
                 let a = stack.pop()?;
                 let b = stack.pop()?;
                 if a.is_number() && b.is_number() {
                     if a.is_int() && b.is_int() {
-                        stack.push(Data::from_int(b.as_int()? % a.as_int()?));
+                        let result = b.as_int()?.checked_rem(a.as_int()?);
+                        push_checked(stack, "%", b, a, result)?;
                     } else {
                         stack.push(Data::from_float(b.as_float()? % a.as_float()?));
                     }
@@ -259,18 +450,23 @@ pub fn tokens() -> Vec<TokenType> {
                 }
                 Ok(())
             },
-        ),
-        // Exponentiate the top two values from the stack
+        )
+        .doc("Modulo the top two values from the stack")
+        .effect(Effect::Fixed(2, 1))
+        .signature(Signature::Rule(arithmetic)),
         TokenType::reg(
             TokenKind::Function,
             "^",
             "\\^",
-            |stack: &mut Stack, _, _, _, _, _| -> Result<()> {
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
                 let a = stack.pop()?;
                 let b = stack.pop()?;
                 if a.is_number() && b.is_number() {
                     if a.is_int() && b.is_int() {
-                        stack.push(Data::from_int(b.as_int()?.pow(a.as_int()? as u32)));
+                        let result = u32::try_from(a.as_int()?)
+                            .ok()
+                            .and_then(|exponent| b.as_int().ok()?.checked_pow(exponent));
+                        push_checked(stack, "^", b, a, result)?;
                     } else {
                         stack.push(Data::from_float(b.as_float()?.powf(a.as_float()?)));
                     }
@@ -281,17 +477,25 @@ pub fn tokens() -> Vec<TokenType> {
                 }
                 Ok(())
             },
-        ),
-        // Negate the top value from the stack
+        )
+        .doc("Exponentiate the top two values from the stack")
+        .effect(Effect::Fixed(2, 1))
+        .signature(Signature::Rule(arithmetic)),
         TokenType::reg(
             TokenKind::Function,
             "~",
             "~",
-            |stack: &mut Stack, _, _, _, _, _| -> Result<()> {
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
                 let a = stack.pop()?;
                 if a.is_number() {
                     if a.is_int() {
-                        stack.push(Data::from_int(-a.as_int()?));
+                        match a.as_int()?.checked_neg() {
+                            Some(negated) => stack.push(Data::from_int(negated)),
+                            None => {
+                                stack.push(a);
+                                return Err(anyhow::anyhow!("Integer overflow"));
+                            }
+                        }
                     } else {
                         stack.push(Data::from_float(-a.as_float()?));
                     }
@@ -301,38 +505,44 @@ pub fn tokens() -> Vec<TokenType> {
                 }
                 Ok(())
             },
-        ),
-        // Duplicate the top value from the stack
+        )
+        .doc("Negate the top value from the stack")
+        .effect(Effect::Fixed(1, 1))
+        .signature(Signature::Rule(negate)),
         TokenType::reg(
             TokenKind::Function,
             ":",
             ":",
-            |stack: &mut Stack, _, _, _, _, _| -> Result<()> {
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
                 let a = stack.pop()?;
                 stack.push(a.clone());
                 stack.push(a);
                 Ok(())
             },
-        ),
-        // Swap the top two values from the stack
+        )
+        .doc("Duplicate the top value from the stack")
+        .effect(Effect::Fixed(1, 2))
+        .signature(Signature::Rule(|types| Ok(vec![types[0], types[0]]))),
         TokenType::reg(
             TokenKind::Function,
             "swp",
             "swp",
-            |stack: &mut Stack, _, _, _, _, _| -> Result<()> {
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
                 let a = stack.pop()?;
                 let b = stack.pop()?;
                 stack.push(a);
                 stack.push(b);
                 Ok(())
             },
-        ),
-        // Rotate the top three values from the stack to the left
+        )
+        .doc("Swap the top two values from the stack")
+        .effect(Effect::Fixed(2, 2))
+        .signature(Signature::Rule(|types| Ok(vec![types[1], types[0]]))),
         TokenType::reg(
             TokenKind::Function,
             "ror",
             "ror",
-            |stack: &mut Stack, _, _, _, _, _| -> Result<()> {
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
                 let a = stack.pop()?;
                 let b = stack.pop()?;
                 let c = stack.pop()?;
@@ -341,13 +551,17 @@ pub fn tokens() -> Vec<TokenType> {
                 stack.push(b);
                 Ok(())
             },
-        ),
-        // Rotate the top three values from the stack to the right
+        )
+        .doc("Rotate the top three values from the stack to the left")
+        .effect(Effect::Fixed(3, 3))
+        .signature(Signature::Rule(|types| {
+            Ok(vec![types[2], types[0], types[1]])
+        })),
         TokenType::reg(
             TokenKind::Function,
             "rol",
             "rol",
-            |stack: &mut Stack, _, _, _, _, _| -> Result<()> {
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
                 let a = stack.pop()?;
                 let b = stack.pop()?;
                 let c = stack.pop()?;
@@ -356,57 +570,64 @@ pub fn tokens() -> Vec<TokenType> {
                 stack.push(c);
                 Ok(())
             },
-        ),
-        // Clear the stack
+        )
+        .doc("Rotate the top three values from the stack to the right")
+        .effect(Effect::Fixed(3, 3))
+        .signature(Signature::Rule(|types| {
+            Ok(vec![types[1], types[2], types[0]])
+        })),
         TokenType::reg(
             TokenKind::Function,
             "clr",
             "clr",
-            |stack: &mut Stack, _, _, _, _, _| -> Result<()> {
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
                 stack.clear();
                 Ok(())
             },
-        ),
-        // Debug the stack
+        )
+        .doc("Clear the stack")
+        .effect(Effect::Clear),
         TokenType::reg(
             TokenKind::Function,
             "`",
             "`",
-            |stack: &mut Stack, _, _, _, _, _| -> Result<()> {
-                println!("Stack debug:\n{}", stack);
+            |stack: &mut Stack, _, _, _, _, runtime: &mut Runtime, _| -> Result<()> {
+                writeln!(runtime.output, "Stack debug:\n{}", stack)?;
                 Ok(())
             },
-        ),
-        // Exit the program with code from the stack
+        )
+        .doc("Print every value on the stack")
+        .effect(Effect::Fixed(0, 0)),
         TokenType::reg(
             TokenKind::Function,
             "exit",
             "exit",
-            |stack: &mut Stack, _, _, _, _, _| -> Result<()> {
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
                 let a = stack.pop()?;
                 if a.is_int() {
-                    std::process::exit(a.as_int()? as i32);
+                    Err(Exit(a.as_int()? as i32).into())
                 } else {
                     stack.push(a);
-                    return Err(anyhow::anyhow!("Exit requires an integer value"));
+                    Err(anyhow::anyhow!("Exit requires an integer value"))
                 }
             },
-        ),
-        // Exit the program with 0 code
+        )
+        .doc("Exit the program with code from the stack")
+        .effect(Effect::Ends(1))
+        .signature(Signature::Fixed(&[Type::Int], &[])),
         TokenType::reg(
             TokenKind::Function,
             "quit",
             "quit",
-            |_, _, _, _, _, _| -> Result<()> {
-                std::process::exit(0);
-            },
-        ),
-        // Pushes true if last two elements are equal
+            |_, _, _, _, _, _, _| -> Result<()> { Err(Exit(0).into()) },
+        )
+        .doc("Exit the program with 0 code")
+        .effect(Effect::Ends(0)),
         TokenType::reg(
             TokenKind::Function,
             "=",
             "^=",
-            |stack: &mut Stack, _, _, _, _, _| -> Result<()> {
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
                 let a = stack.pop()?;
                 let b = stack.pop()?;
                 if a.is_number() && b.is_number() {
@@ -424,24 +645,28 @@ pub fn tokens() -> Vec<TokenType> {
                 }
                 Ok(())
             },
-        ),
-        // Inverts the last element
+        )
+        .doc("Pushes true if last two elements are equal")
+        .effect(Effect::Fixed(2, 1))
+        .signature(Signature::Rule(equal)),
         TokenType::reg(
             TokenKind::Function,
             "!",
             "!",
-            |stack: &mut Stack, _, _, _, _, _| -> Result<()> {
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
                 let a = stack.pop()?;
                 stack.push(Data::from_bool(a.is_false()));
                 Ok(())
             },
-        ),
-        // Pushes true if last element is less than the second to last element
+        )
+        .doc("Inverts the last element")
+        .effect(Effect::Fixed(1, 1))
+        .signature(Signature::Fixed(&[Type::Unknown], &[Type::Bool])),
         TokenType::reg(
             TokenKind::Function,
             "<",
             "<",
-            |stack: &mut Stack, _, _, _, _, _| -> Result<()> {
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
                 let b = stack.pop()?;
                 let a = stack.pop()?;
                 if a.is_number() && b.is_number() {
@@ -457,13 +682,15 @@ pub fn tokens() -> Vec<TokenType> {
                 }
                 Ok(())
             },
-        ),
-        // Pushes true if last element is greater than the second to last element
+        )
+        .doc("Pushes true if last element is less than the second to last element")
+        .effect(Effect::Fixed(2, 1))
+        .signature(Signature::Rule(compare)),
         TokenType::reg(
             TokenKind::Function,
             ">",
             ">",
-            |stack: &mut Stack, _, _, _, _, _| -> Result<()> {
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
                 let b = stack.pop()?;
                 let a = stack.pop()?;
                 if a.is_number() && b.is_number() {
@@ -479,25 +706,29 @@ pub fn tokens() -> Vec<TokenType> {
                 }
                 Ok(())
             },
-        ),
-        // Push current pc to the stack
+        )
+        .doc("Pushes true if last element is greater than the second to last element")
+        .effect(Effect::Fixed(2, 1))
+        .signature(Signature::Rule(compare)),
         TokenType::reg(
             TokenKind::Function,
             "here",
             "here",
-            |stack: &mut Stack, _, _, _, pc: &mut usize, _| -> Result<()> {
-                stack.push(Data::from_int(*pc as i32));
+            |stack: &mut Stack, _, _, _, pc: &mut usize, _, _| -> Result<()> {
+                stack.push(Data::from_int(*pc as i64));
                 Ok(())
             },
-        ),
-        // Jump to a mark
+        )
+        .doc("Push current pc to the stack")
+        .effect(Effect::Fixed(0, 1))
+        .signature(Signature::Fixed(&[], &[Type::Int])),
         TokenType::reg(
             TokenKind::Function,
             "jmp",
             "jmp",
-            |stack: &mut Stack, _, _, marks: &mut MarkList, pc: &mut usize, _| -> Result<()> {
+            |stack: &mut Stack, _, _, marks: &mut MarkList, pc: &mut usize, _, _| -> Result<()> {
                 let location = stack.pop()?;
-                if let Some(new_pc) = marks.get_pc(&location.as_string()?) {
+                if let Some(new_pc) = marks.get_pc(location.as_str()?) {
                     *pc = new_pc;
                 } else {
                     stack.push(Data::String(location.as_string()?.to_string()));
@@ -505,23 +736,25 @@ pub fn tokens() -> Vec<TokenType> {
                 }
                 Ok(())
             },
-        ),
-        // Returns the number of elements in the stack
+        )
+        .doc("Jump to the proc named by the value on the stack"),
         TokenType::reg(
             TokenKind::Function,
             "len",
             "len",
-            |stack: &mut Stack, _, _, _, _, _| -> Result<()> {
-                stack.push(Data::from_int(stack.len() as i32));
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
+                stack.push(Data::from_int(stack.len() as i64));
                 Ok(())
             },
-        ),
-        // If removes one element from the stack, if that is 0, it skips to the end token
+        )
+        .doc("Returns the number of elements in the stack")
+        .effect(Effect::Fixed(0, 1))
+        .signature(Signature::Fixed(&[], &[Type::Int])),
         TokenType::reg(
             TokenKind::If,
             "if",
             "^if",
-            |stack: &mut Stack, _, _, _, pc: &mut usize, data: Data| -> Result<()> {
+            |stack: &mut Stack, _, _, _, pc: &mut usize, _, data: &Data| -> Result<()> {
                 let last_element = stack.pop()?;
                 if last_element.is_false() {
                     if !data.is_number() {
@@ -530,31 +763,35 @@ pub fn tokens() -> Vec<TokenType> {
                             "If statement requires a number as the offset. Were tokens linked?"
                         ));
                     }
-                    *pc += data.as_int()? as usize - 1;
+                    // Lands on the endif, so the whole body is skipped even when it is empty
+                    *pc += data.as_int()? as usize;
                 }
                 Ok(())
             },
-        ),
-        // End token for if
+        )
+        .doc("Pop a value and skip to the matching endif if it is false")
+        .effect(Effect::Fixed(1, 0)),
         TokenType::reg(
             TokenKind::EndIf,
             "endif",
             "endif",
-            |_, _, _, _, _, _| -> Result<()> { Ok(()) },
-        ),
-        // While token
+            |_, _, _, _, _, _, _| -> Result<()> { Ok(()) },
+        )
+        .doc("End an if block")
+        .effect(Effect::Fixed(0, 0)),
         TokenType::reg(
             TokenKind::While,
             "while",
             "while",
-            |_, _, _, _, _, _| -> Result<()> { Ok(()) },
-        ),
-        // Works just as if
+            |_, _, _, _, _, _, _| -> Result<()> { Ok(()) },
+        )
+        .doc("Start a while loop, its condition runs up to do")
+        .effect(Effect::Fixed(0, 0)),
         TokenType::reg(
             TokenKind::Do,
             "do",
             "do",
-            |stack: &mut Stack, _, _, _, pc: &mut usize, data: Data| -> Result<()> {
+            |stack: &mut Stack, _, _, _, pc: &mut usize, _, data: &Data| -> Result<()> {
                 let last_element = stack.pop()?;
                 if last_element.is_false() {
                     if !data.is_number() {
@@ -567,13 +804,14 @@ pub fn tokens() -> Vec<TokenType> {
                 }
                 Ok(())
             },
-        ),
-        // End for while returns to the while token in data
+        )
+        .doc("Pop a value and leave the loop if it is false")
+        .effect(Effect::Fixed(1, 0)),
         TokenType::reg(
             TokenKind::End,
             "end",
             "end",
-            |_, _, _, _, pc: &mut usize, data: Data| -> Result<()> {
+            |_, _, _, _, pc: &mut usize, _, data: &Data| -> Result<()> {
                 if !data.is_number() {
                     return Err(anyhow::anyhow!(
                         "Endwhile statement requires a number as the offset. Were tokens linked?"
@@ -582,8 +820,9 @@ pub fn tokens() -> Vec<TokenType> {
                 *pc -= data.as_int()? as usize + 1;
                 Ok(())
             },
-        ),
-        // Register a value from the stack to a mark
+        )
+        .doc("Jump back to the matching while")
+        .effect(Effect::Fixed(0, 0)),
         TokenType::reg(
             TokenKind::Proc,
             "proc",
@@ -593,7 +832,8 @@ pub fn tokens() -> Vec<TokenType> {
              _,
              marks: &mut MarkList,
              pc: &mut usize,
-             data: Data|
+             _,
+             data: &Data|
              -> Result<()> {
                 let location = stack.pop()?;
                 marks.push(location.as_string()?.to_string(), *pc);
@@ -606,8 +846,55 @@ pub fn tokens() -> Vec<TokenType> {
                 *pc += data.as_int()? as usize + 1;
                 Ok(())
             },
-        ),
-        // Calling a procedure (same as a push but with curlies)
+        )
+        .doc("Define a proc named by the value on the stack, its body runs up to ret")
+        .effect(Effect::Fixed(1, 0)),
+        TokenType::reg(
+            TokenKind::StackEffect,
+            "effect",
+            "\\(",
+            |stack: &mut Stack,
+             return_stack: &mut Stack,
+             _,
+             marks: &mut MarkList,
+             pc: &mut usize,
+             runtime: &mut Runtime,
+             _|
+             -> Result<()> {
+                if !runtime.check_effects {
+                    return Ok(());
+                }
+                // Calls start right after `proc`, which is where the mark of the proc points
+                let mark = match marks.at(*pc - 1) {
+                    Some(mark) => mark,
+                    None => return Ok(()),
+                };
+                let effect = match &mark.effect {
+                    Some(effect) => effect.clone(),
+                    None => return Ok(()),
+                };
+                if stack.len() < effect.inputs.len() {
+                    return Err(anyhow::anyhow!(
+                        "Proc {} {} needs {} values but the stack has {}",
+                        mark.name,
+                        effect,
+                        effect.inputs.len(),
+                        stack.len()
+                    ));
+                }
+                let base = stack.len() - effect.inputs.len();
+                check_slots(stack, base, &effect.inputs, &mark.name, &effect)?;
+                runtime.frames.push(Frame {
+                    name: mark.name.clone(),
+                    base,
+                    return_depth: return_stack.len(),
+                    effect,
+                });
+                Ok(())
+            },
+        )
+        .doc("Declare the effect ( inputs -- outputs ) of the proc before it, checked at ret")
+        .effect(Effect::Fixed(0, 0)),
         TokenType::reg(
             TokenKind::Function,
             "call",
@@ -617,10 +904,11 @@ pub fn tokens() -> Vec<TokenType> {
              _,
              marks: &mut MarkList,
              pc: &mut usize,
-             data: Data|
+             _,
+             data: &Data|
              -> Result<()> {
-                return_stack.push(Data::from_int(*pc as i32));
-                let location = match marks.get_pc(&data.as_string()?) {
+                return_stack.push(Data::from_int(*pc as i64));
+                let location = match marks.get_pc(data.as_str()?) {
                     Some(a) => a,
                     None => {
                         return Err(anyhow::anyhow!("Proc not found: {}", data));
@@ -629,13 +917,46 @@ pub fn tokens() -> Vec<TokenType> {
                 *pc = location as usize;
                 Ok(())
             },
-        ),
-        // Return from a procedure
+        )
+        .doc("Call a proc by name, {name}"),
         TokenType::reg(
             TokenKind::ProcRet,
             "ret",
             "ret",
-            |_, return_stack: &mut Stack, _, _, pc: &mut usize, _| -> Result<()> {
+            |stack: &mut Stack,
+             return_stack: &mut Stack,
+             _,
+             _,
+             pc: &mut usize,
+             runtime: &mut Runtime,
+             _|
+             -> Result<()> {
+                // Only the frame of this call, procs without a declared effect have none
+                if let Some(frame) = runtime.frames.last() {
+                    if frame.return_depth == return_stack.len() {
+                        let inputs = frame.effect.inputs.len();
+                        let declared = frame.effect.outputs.len() as i64 - inputs as i64;
+                        let changed = stack.len() as i64 - (frame.base + inputs) as i64;
+                        if changed != declared {
+                            return Err(anyhow::anyhow!(
+                                "Proc {} {} should change the stack depth by {:+} but changed \
+                                 it by {:+}",
+                                frame.name,
+                                frame.effect,
+                                declared,
+                                changed
+                            ));
+                        }
+                        check_slots(
+                            stack,
+                            frame.base,
+                            &frame.effect.outputs,
+                            &frame.name,
+                            &frame.effect,
+                        )?;
+                        runtime.frames.pop();
+                    }
+                }
                 let location = return_stack.pop()?;
                 if location.is_int() {
                     *pc = location.as_int()? as usize;
@@ -644,6 +965,80 @@ pub fn tokens() -> Vec<TokenType> {
                 }
                 Ok(())
             },
-        ),
-    ]
+        )
+        .doc("Return from a procedure")
+        .effect(Effect::Fixed(0, 0)),
+        TokenType::reg(
+            TokenKind::Try,
+            "try",
+            "try",
+            |stack: &mut Stack,
+             return_stack: &mut Stack,
+             _,
+             _,
+             pc: &mut usize,
+             runtime: &mut Runtime,
+             data: &Data|
+             -> Result<()> {
+                if !data.is_number() {
+                    return Err(anyhow::anyhow!(
+                        "Try statement requires a number as the offset. Were tokens linked?"
+                    ));
+                }
+                runtime.handlers.push(Handler {
+                    catch_pc: *pc + data.as_int()? as usize + 1,
+                    depth: stack.len(),
+                    return_depth: return_stack.len(),
+                });
+                Ok(())
+            },
+        )
+        .doc("Start a try block, errors until its catch jump there with the stack unwound")
+        .effect(Effect::Fixed(0, 0)),
+        TokenType::reg(
+            TokenKind::Catch,
+            "catch",
+            "catch",
+            |_, _, _, _, pc: &mut usize, runtime: &mut Runtime, data: &Data| -> Result<()> {
+                if !data.is_number() {
+                    return Err(anyhow::anyhow!(
+                        "Catch statement requires a number as the offset. Were tokens linked?"
+                    ));
+                }
+                runtime.handlers.pop();
+                *pc += data.as_int()? as usize;
+                Ok(())
+            },
+        )
+        .doc("Reached only when the try block finished without an error, skips to endtry")
+        .effect(Effect::Fixed(0, 0)),
+        TokenType::reg(
+            TokenKind::EndTry,
+            "endtry",
+            "endtry",
+            |_, _, _, _, _, _, _| -> Result<()> { Ok(()) },
+        )
+        .doc("End a try block")
+        .effect(Effect::Fixed(0, 0)),
+        TokenType::reg(
+            TokenKind::Function,
+            "throw",
+            "throw",
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
+                Err(Thrown(stack.pop()?).into())
+            },
+        )
+        .doc("Raise the value on the stack as an error, catch pushes it instead of a message")
+        .effect(Effect::Ends(1)),
+    ];
+    tokens.extend(random::tokens());
+    tokens.extend(input::tokens());
+    tokens.extend(fs::tokens());
+    tokens.extend(env::tokens());
+    tokens.extend(path::tokens());
+    tokens.extend(process::tokens());
+    tokens.extend(time::tokens());
+    tokens.extend(format::tokens());
+    tokens.extend(testing::tokens());
+    tokens
 }
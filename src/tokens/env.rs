@@ -0,0 +1,146 @@
+use anyhow::Result;
+
+use crate::engine::data::{Data, Type};
+use crate::engine::lexer::token::{Effect, Signature, TokenKind, TokenType};
+use crate::engine::stack::Stack;
+
+use super::{pop_string, push_n};
+
+pub fn tokens() -> Vec<TokenType> {
+    vec![
+        TokenType::reg(
+            TokenKind::Function,
+            "getenv",
+            "getenv",
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
+                let name = pop_string(stack, "Environment variable name")?;
+                match std::env::var(&name) {
+                    Ok(value) => stack.push(Data::from_string(value)),
+                    Err(std::env::VarError::NotPresent) => stack.push(Data::None),
+                    Err(e) => {
+                        stack.push(Data::from_string(name.clone()));
+                        return Err(anyhow::anyhow!("Error reading {}: {}", name, e));
+                    }
+                }
+                Ok(())
+            },
+        )
+        .doc(
+            "Push the value of the environment variable named on the stack, or None if it is unset",
+        )
+        .effect(Effect::Fixed(1, 1))
+        .signature(Signature::Fixed(&[Type::String], &[Type::Unknown])),
+        TokenType::reg(
+            TokenKind::Function,
+            "setenv",
+            "setenv",
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
+                let name = pop_string(stack, "Environment variable name")?;
+                if name.is_empty() || name.contains('=') || name.contains('\0') {
+                    stack.push(Data::from_string(name));
+                    return Err(anyhow::anyhow!("Invalid environment variable name"));
+                }
+                let value = match stack.pop() {
+                    Ok(value) => value.to_string(),
+                    Err(e) => {
+                        stack.push(Data::from_string(name));
+                        return Err(e);
+                    }
+                };
+                if value.contains('\0') {
+                    stack.push(Data::from_string(value));
+                    stack.push(Data::from_string(name));
+                    return Err(anyhow::anyhow!("Environment variable value contains NUL"));
+                }
+                std::env::set_var(name, value);
+                Ok(())
+            },
+        )
+        .doc("Set the environment variable named on top to the second element")
+        .effect(Effect::Fixed(2, 0))
+        .signature(Signature::Fixed(&[Type::Unknown, Type::String], &[])),
+        TokenType::reg(
+            TokenKind::Function,
+            "env-list",
+            "env-list",
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
+                let mut variables = std::env::vars_os()
+                    .map(|(name, value)| {
+                        format!("{}={}", name.to_string_lossy(), value.to_string_lossy())
+                    })
+                    .collect::<Vec<String>>();
+                variables.sort();
+                push_n(
+                    stack,
+                    variables.into_iter().map(Data::from_string).collect(),
+                );
+                Ok(())
+            },
+        )
+        .doc("Push every environment variable as \"NAME=value\", sorted, followed by their count"),
+        TokenType::reg(
+            TokenKind::Function,
+            "cwd",
+            "cwd",
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
+                match std::env::current_dir() {
+                    Ok(path) => stack.push(Data::from_string(path.to_string_lossy().to_string())),
+                    Err(e) => {
+                        return Err(anyhow::anyhow!("Error getting working directory: {}", e));
+                    }
+                }
+                Ok(())
+            },
+        )
+        .doc("Push the current working directory")
+        .effect(Effect::Fixed(0, 1))
+        .signature(Signature::Fixed(&[], &[Type::String])),
+        TokenType::reg(
+            TokenKind::Function,
+            "cd",
+            "cd",
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
+                let path = pop_string(stack, "Path")?;
+                if let Err(e) = std::env::set_current_dir(&path) {
+                    stack.push(Data::from_string(path.clone()));
+                    return Err(anyhow::anyhow!(
+                        "Error changing directory to {}: {}",
+                        path,
+                        e
+                    ));
+                }
+                Ok(())
+            },
+        )
+        .doc("Change the working directory to the path on the stack")
+        .effect(Effect::Fixed(1, 0))
+        .signature(Signature::Fixed(&[Type::String], &[])),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::mark::MarkList;
+    use crate::engine::runtime::Runtime;
+    use crate::engine::variables::Variables;
+
+    #[test]
+    fn setenv_keeps_the_name_on_error() {
+        let setenv = tokens().into_iter().find(|t| t.name == "setenv").unwrap();
+        let mut stack = Stack::new();
+        stack.push(Data::from_str("PINNIPED_SETENV_TEST"));
+        let result = (setenv.func)(
+            &mut stack,
+            &mut Stack::new(),
+            &mut Variables::new(),
+            &mut MarkList::new(),
+            &mut 0,
+            &mut Runtime::new(),
+            &Data::None,
+        );
+        assert!(result.is_err());
+        assert_eq!(stack.len(), 1);
+        assert_eq!(stack.pop().unwrap().to_string(), "PINNIPED_SETENV_TEST");
+    }
+}
@@ -0,0 +1,360 @@
+use anyhow::Result;
+use std::io::Write;
+
+use crate::engine::data::Data;
+use crate::engine::lexer::token::{TokenKind, TokenType};
+use crate::engine::runtime::Runtime;
+use crate::engine::stack::Stack;
+use crate::engine::variables::Variables;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Spec {
+    fill: char,
+    align: Option<Align>,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Value(Spec),
+}
+
+fn parse_align(c: char) -> Option<Align> {
+    match c {
+        '<' => Some(Align::Left),
+        '>' => Some(Align::Right),
+        '^' => Some(Align::Center),
+        _ => None,
+    }
+}
+
+/// Parses the part of a placeholder after the `:`, `[[fill]align][0][width][.precision]`.
+fn parse_spec(spec: &str) -> Result<Spec> {
+    let mut result = Spec {
+        fill: ' ',
+        align: None,
+        zero: false,
+        width: 0,
+        precision: None,
+    };
+    let chars = spec.chars().collect::<Vec<char>>();
+    let mut i = 0;
+    if chars.len() >= 2 && parse_align(chars[1]).is_some() {
+        result.fill = chars[0];
+        result.align = parse_align(chars[1]);
+        i = 2;
+    } else if !chars.is_empty() && parse_align(chars[0]).is_some() {
+        result.align = parse_align(chars[0]);
+        i = 1;
+    }
+    if i < chars.len() && chars[i] == '0' {
+        result.zero = true;
+        i += 1;
+    }
+    let start = i;
+    while i < chars.len() && chars[i].is_ascii_digit() {
+        i += 1;
+    }
+    if i > start {
+        result.width = chars[start..i].iter().collect::<String>().parse()?;
+    }
+    if i < chars.len() && chars[i] == '.' {
+        i += 1;
+        let start = i;
+        while i < chars.len() && chars[i].is_ascii_digit() {
+            i += 1;
+        }
+        if i == start {
+            return Err(anyhow::anyhow!(
+                "Missing precision in format spec: {}",
+                spec
+            ));
+        }
+        result.precision = Some(chars[start..i].iter().collect::<String>().parse()?);
+    }
+    if i != chars.len() {
+        return Err(anyhow::anyhow!("Invalid format spec: {}", spec));
+    }
+    Ok(result)
+}
+
+fn parse(template: &str) -> Result<Vec<Segment>> {
+    let mut segments = Vec::new();
+    let mut literal = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '{' => {
+                let mut placeholder = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => placeholder.push(c),
+                        None => return Err(anyhow::anyhow!("Unclosed {{ in format string")),
+                    }
+                }
+                let spec = match placeholder.strip_prefix(':') {
+                    Some(spec) => spec,
+                    None if placeholder.is_empty() => "",
+                    None => {
+                        return Err(anyhow::anyhow!(
+                            "Invalid placeholder in format string: {{{}}}",
+                            placeholder
+                        ))
+                    }
+                };
+                if !literal.is_empty() {
+                    segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                }
+                segments.push(Segment::Value(parse_spec(spec)?));
+            }
+            '}' => return Err(anyhow::anyhow!("Unmatched }} in format string")),
+            c => literal.push(c),
+        }
+    }
+    if !literal.is_empty() {
+        segments.push(Segment::Literal(literal));
+    }
+    Ok(segments)
+}
+
+fn format_value(value: &Data, spec: &Spec) -> String {
+    let text = match (value, spec.precision) {
+        (Data::Int(i), Some(precision)) => format!("{:.*}", precision, *i as f64),
+        (Data::Float(f), Some(precision)) => format!("{:.*}", precision, f),
+        (_, Some(precision)) => value.to_string().chars().take(precision).collect(),
+        (_, None) => value.to_string(),
+    };
+    let len = text.chars().count();
+    if len >= spec.width {
+        return text;
+    }
+    let padding = spec.width - len;
+    if spec.zero && spec.align.is_none() && value.is_number() {
+        // Zeros go between the sign and the digits
+        let (sign, digits) = match text.strip_prefix('-') {
+            Some(digits) => ("-", digits),
+            None => ("", text.as_str()),
+        };
+        return format!("{}{}{}", sign, "0".repeat(padding), digits);
+    }
+    let align = spec.align.unwrap_or(if value.is_number() {
+        Align::Right
+    } else {
+        Align::Left
+    });
+    let fill = |n: usize| spec.fill.to_string().repeat(n);
+    match align {
+        Align::Left => format!("{}{}", text, fill(padding)),
+        Align::Right => format!("{}{}", fill(padding), text),
+        Align::Center => format!(
+            "{}{}{}",
+            fill(padding / 2),
+            text,
+            fill(padding - padding / 2)
+        ),
+    }
+}
+
+/// Formats `template`, replacing every `{}`/`{:spec}` placeholder with the next value.
+pub fn format(template: &str, values: &[Data]) -> Result<String> {
+    let segments = parse(template)?;
+    let mut result = String::new();
+    let mut values = values.iter();
+    for segment in &segments {
+        match segment {
+            Segment::Literal(text) => result.push_str(text),
+            Segment::Value(spec) => match values.next() {
+                Some(value) => result.push_str(&format_value(value, spec)),
+                None => return Err(anyhow::anyhow!("Not enough values for format string")),
+            },
+        }
+    }
+    Ok(result)
+}
+
+/// Returns the number of values `template` consumes.
+pub fn placeholders(template: &str) -> Result<usize> {
+    Ok(parse(template)?
+        .iter()
+        .filter(|segment| matches!(segment, Segment::Value(_)))
+        .count())
+}
+
+/// Replaces `{<name}` with the local and `{<<name}` with the global variable `name` in a string
+/// literal. `{{<` stands for a literal `{<`, every other brace is kept as is for `format`.
+pub fn interpolate(literal: &str, variables: &Variables) -> Result<String> {
+    let mut result = String::with_capacity(literal.len());
+    let mut rest = literal;
+    while let Some(start) = rest.find("{<") {
+        if rest[..start].ends_with('{') {
+            result.push_str(&rest[..start - 1]);
+            result.push_str("{<");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        result.push_str(&rest[..start]);
+        let reference = &rest[start + 2..];
+        let (name, local) = match reference.strip_prefix('<') {
+            Some(name) => (name, false),
+            None => (reference, true),
+        };
+        let end = match name.find('}') {
+            Some(end) => end,
+            None => return Err(anyhow::anyhow!("Unclosed {{< in string literal")),
+        };
+        match variables.get(&name[..end], local) {
+            Some(value) => result.push_str(&value.to_string()),
+            None => return Err(anyhow::anyhow!("Variable not found")),
+        }
+        rest = &name[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Pops the template and the values it needs, returning the formatted string.
+fn format_from_stack(stack: &mut Stack) -> Result<String> {
+    let template = stack.pop()?;
+    let count = match placeholders(&template.to_string()) {
+        Ok(count) => count,
+        Err(e) => {
+            stack.push(template);
+            return Err(e);
+        }
+    };
+    if count > stack.len() {
+        stack.push(template);
+        return Err(anyhow::anyhow!(
+            "Format string needs {} values, but the stack has {}",
+            count,
+            stack.len()
+        ));
+    }
+    let mut values = Vec::with_capacity(count);
+    for _ in 0..count {
+        values.push(stack.pop()?);
+    }
+    values.reverse();
+    format(&template.to_string(), &values)
+}
+
+pub fn tokens() -> Vec<TokenType> {
+    vec![
+        TokenType::reg(
+            TokenKind::Function,
+            "format",
+            "format",
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
+                let formatted = format_from_stack(stack)?;
+                stack.push(Data::from_string(formatted));
+                Ok(())
+            },
+        )
+        .doc("Format the template on top with as many values from under it as it has placeholders"),
+        TokenType::reg(
+            TokenKind::Function,
+            "printf",
+            "printf",
+            |stack: &mut Stack, _, _, _, _, runtime: &mut Runtime, _| -> Result<()> {
+                write!(runtime.output, "{}", format_from_stack(stack)?)?;
+                Ok(())
+            },
+        )
+        .doc("Same as format, but print the result"),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain() {
+        let values = [Data::Int(2), Data::Int(3), Data::Float(5.0)];
+        assert_eq!(format("{} + {} = {:.2}", &values).unwrap(), "2 + 3 = 5.00");
+        assert_eq!(format("{{}} {}", &[Data::None]).unwrap(), "{} None");
+    }
+
+    #[test]
+    fn width_and_alignment() {
+        assert_eq!(format("[{:5}]", &[Data::Int(42)]).unwrap(), "[   42]");
+        assert_eq!(
+            format("[{:5}]", &[Data::from_str("ab")]).unwrap(),
+            "[ab   ]"
+        );
+        assert_eq!(format("[{:<5}]", &[Data::Int(42)]).unwrap(), "[42   ]");
+        assert_eq!(
+            format("[{:^6}]", &[Data::from_str("ab")]).unwrap(),
+            "[  ab  ]"
+        );
+        assert_eq!(format("[{:*>4}]", &[Data::Bool(true)]).unwrap(), "[true]");
+        assert_eq!(format("[{:*>6}]", &[Data::Bool(true)]).unwrap(), "[**true]");
+    }
+
+    #[test]
+    fn zero_padding() {
+        assert_eq!(format("{:05}", &[Data::Int(-42)]).unwrap(), "-0042");
+        assert_eq!(
+            format("{:07.2}", &[Data::Float(1.23456)]).unwrap(),
+            "0001.23"
+        );
+    }
+
+    #[test]
+    fn precision() {
+        assert_eq!(format("{:.1}", &[Data::Int(3)]).unwrap(), "3.0");
+        assert_eq!(format("{:.3}", &[Data::from_str("abcdef")]).unwrap(), "abc");
+    }
+
+    #[test]
+    fn placeholder_count() {
+        assert_eq!(placeholders("no values").unwrap(), 0);
+        assert_eq!(placeholders("{} {{}} {:>3}").unwrap(), 2);
+    }
+
+    #[test]
+    fn interpolation() {
+        let mut variables = Variables::new();
+        variables.add("name".to_string(), Data::from_str("world"), true);
+        variables.add("count".to_string(), Data::Int(3), false);
+        assert_eq!(
+            interpolate("Hello, {<name}! {<<count} {}", &variables).unwrap(),
+            "Hello, world! 3 {}"
+        );
+        assert_eq!(interpolate("{{<name}", &variables).unwrap(), "{<name}");
+        assert_eq!(
+            interpolate("no references", &variables).unwrap(),
+            "no references"
+        );
+        assert!(interpolate("{<count}", &variables).is_err());
+        assert!(interpolate("{<name", &variables).is_err());
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(format("{", &[]).is_err());
+        assert!(format("}", &[]).is_err());
+        assert!(format("{name}", &[Data::None]).is_err());
+        assert!(format("{:.}", &[Data::None]).is_err());
+        assert!(format("{}", &[]).is_err());
+    }
+}
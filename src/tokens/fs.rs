@@ -0,0 +1,190 @@
+use anyhow::Result;
+
+use crate::engine::data::{Data, Type};
+use crate::engine::lexer::token::{Effect, Signature, TokenKind, TokenType};
+use crate::engine::stack::Stack;
+
+use super::{pop_string, push_n};
+
+pub fn tokens() -> Vec<TokenType> {
+    vec![
+        TokenType::reg(
+            TokenKind::Function,
+            "read-file",
+            "read-file",
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
+                let path = pop_string(stack, "Path")?;
+                match std::fs::read_to_string(&path) {
+                    Ok(contents) => stack.push(Data::from_string(contents)),
+                    Err(e) => {
+                        stack.push(Data::from_string(path.clone()));
+                        return Err(anyhow::anyhow!("Error reading file {}: {}", path, e));
+                    }
+                }
+                Ok(())
+            },
+        )
+        .doc("Push the contents of the file at the path on the stack")
+        .effect(Effect::Fixed(1, 1))
+        .signature(Signature::Fixed(&[Type::String], &[Type::String])),
+        TokenType::reg(
+            TokenKind::Function,
+            "read-lines",
+            "read-lines",
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
+                let path = pop_string(stack, "Path")?;
+                match std::fs::read_to_string(&path) {
+                    Ok(contents) => push_n(stack, contents.lines().map(Data::from_str).collect()),
+                    Err(e) => {
+                        stack.push(Data::from_string(path.clone()));
+                        return Err(anyhow::anyhow!("Error reading file {}: {}", path, e));
+                    }
+                }
+                Ok(())
+            },
+        )
+        .doc("Push every line of the file at the path on the stack, followed by the line count"),
+        TokenType::reg(
+            TokenKind::Function,
+            "write-file",
+            "write-file",
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
+                let path = pop_string(stack, "Path")?;
+                let contents = stack.pop()?;
+                if let Err(e) = std::fs::write(&path, contents.to_string()) {
+                    stack.push(contents);
+                    stack.push(Data::from_string(path.clone()));
+                    return Err(anyhow::anyhow!("Error writing file {}: {}", path, e));
+                }
+                Ok(())
+            },
+        )
+        .doc("Write the second element to the file at the path on top, replacing its contents")
+        .effect(Effect::Fixed(2, 0))
+        .signature(Signature::Fixed(&[Type::Unknown, Type::String], &[])),
+        TokenType::reg(
+            TokenKind::Function,
+            "append-file",
+            "append-file",
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
+                use std::io::Write;
+
+                let path = pop_string(stack, "Path")?;
+                let contents = stack.pop()?;
+                let result = std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .and_then(|mut file| file.write_all(contents.to_string().as_bytes()));
+                if let Err(e) = result {
+                    stack.push(contents);
+                    stack.push(Data::from_string(path.clone()));
+                    return Err(anyhow::anyhow!("Error appending to file {}: {}", path, e));
+                }
+                Ok(())
+            },
+        )
+        .doc("Append the second element to the file at the path on top, creating it if needed")
+        .effect(Effect::Fixed(2, 0))
+        .signature(Signature::Fixed(&[Type::Unknown, Type::String], &[])),
+        TokenType::reg(
+            TokenKind::Function,
+            "exists",
+            "exists",
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
+                let path = pop_string(stack, "Path")?;
+                stack.push(Data::from_bool(std::path::Path::new(&path).exists()));
+                Ok(())
+            },
+        )
+        .doc("Push true if the path exists")
+        .effect(Effect::Fixed(1, 1))
+        .signature(Signature::Fixed(&[Type::String], &[Type::Bool])),
+        TokenType::reg(
+            TokenKind::Function,
+            "delete",
+            "delete",
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
+                let path = pop_string(stack, "Path")?;
+                let result = if std::path::Path::new(&path).is_dir() {
+                    std::fs::remove_dir(&path)
+                } else {
+                    std::fs::remove_file(&path)
+                };
+                if let Err(e) = result {
+                    stack.push(Data::from_string(path.clone()));
+                    return Err(anyhow::anyhow!("Error deleting {}: {}", path, e));
+                }
+                Ok(())
+            },
+        )
+        .doc("Delete the file or empty directory at the path")
+        .effect(Effect::Fixed(1, 0))
+        .signature(Signature::Fixed(&[Type::String], &[])),
+        TokenType::reg(
+            TokenKind::Function,
+            "rename",
+            "rename",
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
+                let to = pop_string(stack, "Path")?;
+                let from = match pop_string(stack, "Path") {
+                    Ok(from) => from,
+                    Err(e) => {
+                        stack.push(Data::from_string(to));
+                        return Err(e);
+                    }
+                };
+                if let Err(e) = std::fs::rename(&from, &to) {
+                    stack.push(Data::from_string(from.clone()));
+                    stack.push(Data::from_string(to.clone()));
+                    return Err(anyhow::anyhow!("Error renaming {} to {}: {}", from, to, e));
+                }
+                Ok(())
+            },
+        )
+        .doc("Rename the second element path to the path on top")
+        .effect(Effect::Fixed(2, 0))
+        .signature(Signature::Fixed(&[Type::String, Type::String], &[])),
+        TokenType::reg(
+            TokenKind::Function,
+            "mkdir",
+            "mkdir",
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
+                let path = pop_string(stack, "Path")?;
+                if let Err(e) = std::fs::create_dir_all(&path) {
+                    stack.push(Data::from_string(path.clone()));
+                    return Err(anyhow::anyhow!("Error creating directory {}: {}", path, e));
+                }
+                Ok(())
+            },
+        )
+        .doc("Create the directory at the path together with its missing parents")
+        .effect(Effect::Fixed(1, 0))
+        .signature(Signature::Fixed(&[Type::String], &[])),
+        TokenType::reg(
+            TokenKind::Function,
+            "list-dir",
+            "list-dir",
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
+                let path = pop_string(stack, "Path")?;
+                let entries = std::fs::read_dir(&path).and_then(|entries| {
+                    entries
+                        .map(|entry| entry.map(|e| e.file_name().to_string_lossy().to_string()))
+                        .collect::<std::io::Result<Vec<String>>>()
+                });
+                match entries {
+                    Ok(mut names) => {
+                        names.sort();
+                        push_n(stack, names.into_iter().map(Data::from_string).collect());
+                    }
+                    Err(e) => {
+                        stack.push(Data::from_string(path.clone()));
+                        return Err(anyhow::anyhow!("Error listing directory {}: {}", path, e));
+                    }
+                }
+                Ok(())
+            },
+        )
+        .doc("Push the sorted entry names of the directory at the path, followed by their count"),
+    ]
+}
@@ -0,0 +1,63 @@
+use anyhow::Result;
+
+use crate::engine::data::{Data, Type};
+use crate::engine::lexer::token::{Effect, Signature, TokenKind, TokenType};
+use crate::engine::runtime::Runtime;
+use crate::engine::stack::Stack;
+
+pub fn tokens() -> Vec<TokenType> {
+    vec![
+        TokenType::reg(
+            TokenKind::Function,
+            "read-line",
+            "read-line",
+            |stack: &mut Stack, _, _, _, _, runtime: &mut Runtime, _| -> Result<()> {
+                match runtime.input.read_line()? {
+                    Some(line) => stack.push(Data::from_string(line)),
+                    None => stack.push(Data::None),
+                }
+                Ok(())
+            },
+        )
+        .doc("Push the next line of input without the line ending, or None at the end of input")
+        .effect(Effect::Fixed(0, 1)),
+        TokenType::reg(
+            TokenKind::Function,
+            "read-all",
+            "read-all",
+            |stack: &mut Stack, _, _, _, _, runtime: &mut Runtime, _| -> Result<()> {
+                stack.push(Data::from_string(runtime.input.read_all()?));
+                Ok(())
+            },
+        )
+        .doc("Push the rest of the input as one string")
+        .effect(Effect::Fixed(0, 1))
+        .signature(Signature::Fixed(&[], &[Type::String])),
+        TokenType::reg(
+            TokenKind::Function,
+            "read-char",
+            "read-char",
+            |stack: &mut Stack, _, _, _, _, runtime: &mut Runtime, _| -> Result<()> {
+                match runtime.input.read_char()? {
+                    Some(c) => stack.push(Data::from_string(c.to_string())),
+                    None => stack.push(Data::None),
+                }
+                Ok(())
+            },
+        )
+        .doc("Push the next character of input, or None at the end of input")
+        .effect(Effect::Fixed(0, 1)),
+        TokenType::reg(
+            TokenKind::Function,
+            "eof",
+            "eof",
+            |stack: &mut Stack, _, _, _, _, runtime: &mut Runtime, _| -> Result<()> {
+                stack.push(Data::from_bool(runtime.input.is_eof()?));
+                Ok(())
+            },
+        )
+        .doc("Push true if there is nothing left to read")
+        .effect(Effect::Fixed(0, 1))
+        .signature(Signature::Fixed(&[], &[Type::Bool])),
+    ]
+}
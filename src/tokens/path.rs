@@ -0,0 +1,84 @@
+use anyhow::Result;
+use std::path::Path;
+
+use crate::engine::data::{Data, Type};
+use crate::engine::lexer::token::{Effect, Signature, TokenKind, TokenType};
+use crate::engine::stack::Stack;
+
+use super::pop_string;
+
+/// Pushes `Some` as a string and `None` as `Data::None`.
+fn push_part(stack: &mut Stack, part: Option<&std::ffi::OsStr>) {
+    match part {
+        Some(part) => stack.push(Data::from_string(part.to_string_lossy().to_string())),
+        None => stack.push(Data::None),
+    }
+}
+
+pub fn tokens() -> Vec<TokenType> {
+    vec![
+        TokenType::reg(
+            TokenKind::Function,
+            "path-join",
+            "path-join",
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
+                let tail = pop_string(stack, "Path")?;
+                let head = match pop_string(stack, "Path") {
+                    Ok(head) => head,
+                    Err(e) => {
+                        stack.push(Data::from_string(tail));
+                        return Err(e);
+                    }
+                };
+                let joined = Path::new(&head).join(tail);
+                stack.push(Data::from_string(joined.to_string_lossy().to_string()));
+                Ok(())
+            },
+        )
+        .doc("Join the path on top onto the second element path")
+        .effect(Effect::Fixed(2, 1))
+        .signature(Signature::Fixed(
+            &[Type::String, Type::String],
+            &[Type::String],
+        )),
+        TokenType::reg(
+            TokenKind::Function,
+            "basename",
+            "basename",
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
+                let path = pop_string(stack, "Path")?;
+                push_part(stack, Path::new(&path).file_name());
+                Ok(())
+            },
+        )
+        .doc("Push the last component of the path, or None if there is none")
+        .effect(Effect::Fixed(1, 1))
+        .signature(Signature::Fixed(&[Type::String], &[Type::Unknown])),
+        TokenType::reg(
+            TokenKind::Function,
+            "dirname",
+            "dirname",
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
+                let path = pop_string(stack, "Path")?;
+                push_part(stack, Path::new(&path).parent().map(|p| p.as_os_str()));
+                Ok(())
+            },
+        )
+        .doc("Push the path without its last component, or None if there is none")
+        .effect(Effect::Fixed(1, 1))
+        .signature(Signature::Fixed(&[Type::String], &[Type::Unknown])),
+        TokenType::reg(
+            TokenKind::Function,
+            "extension",
+            "extension",
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
+                let path = pop_string(stack, "Path")?;
+                push_part(stack, Path::new(&path).extension());
+                Ok(())
+            },
+        )
+        .doc("Push the extension of the path without the dot, or None if there is none")
+        .effect(Effect::Fixed(1, 1))
+        .signature(Signature::Fixed(&[Type::String], &[Type::Unknown])),
+    ]
+}
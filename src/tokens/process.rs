@@ -0,0 +1,137 @@
+use anyhow::Result;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use crate::engine::data::Data;
+use crate::engine::lexer::token::{TokenKind, TokenType};
+use crate::engine::runtime::Runtime;
+use crate::engine::stack::Stack;
+
+use super::{pop_n, push_n};
+
+/// Runs the command in `argv[0]` with the rest as its arguments and pushes its stdout, stderr and
+/// exit status. On failure the command group is pushed back so the stack is left untouched.
+fn run(stack: &mut Stack, runtime: &Runtime, argv: Vec<Data>, input: Option<&str>) -> Result<()> {
+    let restore = |stack: &mut Stack, argv: Vec<Data>| {
+        if let Some(input) = input {
+            stack.push(Data::from_str(input));
+        }
+        push_n(stack, argv);
+    };
+    if !runtime.allow_exec {
+        restore(stack, argv);
+        return Err(anyhow::anyhow!("Running processes is disabled"));
+    }
+    if argv.is_empty() {
+        restore(stack, argv);
+        return Err(anyhow::anyhow!("No command given"));
+    }
+
+    let program = argv[0].to_string();
+    let output = Command::new(&program)
+        .args(argv[1..].iter().map(|arg| arg.to_string()))
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .and_then(|mut child| {
+            // Written from another thread, a child echoing more than a pipe buffer back would
+            // otherwise block on its stdout while we block on its stdin
+            let writer = match (input, child.stdin.take()) {
+                (Some(input), Some(mut stdin)) => {
+                    let input = input.to_string();
+                    Some(std::thread::spawn(move || {
+                        stdin.write_all(input.as_bytes())
+                    }))
+                }
+                _ => None,
+            };
+            let output = child.wait_with_output()?;
+            if let Some(writer) = writer {
+                match writer.join().expect("stdin writer panicked") {
+                    // The child does not have to read all of its input
+                    Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => return Err(e),
+                    _ => {}
+                }
+            }
+            Ok(output)
+        });
+    let output = match output {
+        Ok(output) => output,
+        Err(e) => {
+            restore(stack, argv);
+            return Err(anyhow::anyhow!("Error running {}: {}", program, e));
+        }
+    };
+
+    stack.push(Data::from_string(
+        String::from_utf8_lossy(&output.stdout).to_string(),
+    ));
+    stack.push(Data::from_string(
+        String::from_utf8_lossy(&output.stderr).to_string(),
+    ));
+    // Processes killed by a signal have no exit code
+    stack.push(match output.status.code() {
+        Some(code) => Data::from_int(code as i64),
+        None => Data::None,
+    });
+    Ok(())
+}
+
+pub fn tokens() -> Vec<TokenType> {
+    vec![
+        TokenType::reg(
+            TokenKind::Function,
+            "run",
+            "run",
+            |stack: &mut Stack, _, _, _, _, runtime: &mut Runtime, _| -> Result<()> {
+                let argv = pop_n(stack)?;
+                run(stack, runtime, argv, None)
+            },
+        )
+        .doc("Run the command and arguments under the count, push stdout, stderr and exit status"),
+        TokenType::reg(
+            TokenKind::Function,
+            "run-input",
+            "run-input",
+            |stack: &mut Stack, _, _, _, _, runtime: &mut Runtime, _| -> Result<()> {
+                let argv = pop_n(stack)?;
+                let input = match stack.pop() {
+                    Ok(input) => input.to_string(),
+                    Err(e) => {
+                        push_n(stack, argv);
+                        return Err(e);
+                    }
+                };
+                run(stack, runtime, argv, Some(&input))
+            },
+        )
+        .doc("Same as run, but feed the element under the command group to the process stdin"),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn large_input() {
+        let input = "seal\n".repeat(60_000);
+        let mut stack = Stack::new();
+        run(
+            &mut stack,
+            &Runtime::new(),
+            vec![Data::from_str("cat")],
+            Some(&input),
+        )
+        .unwrap();
+        assert_eq!(stack.pop().unwrap().as_int().unwrap(), 0);
+        assert_eq!(stack.pop().unwrap().to_string(), "");
+        assert_eq!(stack.pop().unwrap().to_string(), input);
+    }
+}
@@ -0,0 +1,126 @@
+use anyhow::Result;
+
+use crate::engine::data::{Data, Type};
+use crate::engine::lexer::token::{Effect, Signature, TokenKind, TokenType};
+use crate::engine::runtime::Runtime;
+use crate::engine::stack::Stack;
+
+use super::pop_n;
+
+pub fn tokens() -> Vec<TokenType> {
+    vec![
+        TokenType::reg(
+            TokenKind::Function,
+            "rand",
+            "rand",
+            |stack: &mut Stack, _, _, _, _, runtime: &mut Runtime, _| -> Result<()> {
+                stack.push(Data::from_float(runtime.rng.next_float()));
+                Ok(())
+            },
+        )
+        .doc("Push a random float in [0, 1)")
+        .effect(Effect::Fixed(0, 1))
+        .signature(Signature::Fixed(&[], &[Type::Float])),
+        TokenType::reg(
+            TokenKind::Function,
+            "rand-int",
+            "rand-int",
+            |stack: &mut Stack, _, _, _, _, runtime: &mut Runtime, _| -> Result<()> {
+                let high = stack.pop()?;
+                let low = stack.pop()?;
+                if !low.is_int() || !high.is_int() || low.as_int()? > high.as_int()? {
+                    stack.push(low);
+                    stack.push(high);
+                    return Err(anyhow::anyhow!(
+                        "rand-int requires two integers with low <= high"
+                    ));
+                }
+                stack.push(Data::from_int(
+                    runtime.rng.range_int(low.as_int()?, high.as_int()?),
+                ));
+                Ok(())
+            },
+        )
+        .doc("Push a random int between the last two elements, both included")
+        .effect(Effect::Fixed(2, 1))
+        .signature(Signature::Fixed(&[Type::Int, Type::Int], &[Type::Int])),
+        TokenType::reg(
+            TokenKind::Function,
+            "rand-float",
+            "rand-float",
+            |stack: &mut Stack, _, _, _, _, runtime: &mut Runtime, _| -> Result<()> {
+                let high = stack.pop()?;
+                let low = stack.pop()?;
+                if !low.is_number() || !high.is_number() {
+                    stack.push(low);
+                    stack.push(high);
+                    return Err(anyhow::anyhow!("rand-float requires two numbers"));
+                }
+                let low_value = match low {
+                    Data::Int(i) => i as f32,
+                    _ => low.as_float()?,
+                };
+                let high_value = match high {
+                    Data::Int(i) => i as f32,
+                    _ => high.as_float()?,
+                };
+                let value = low_value + runtime.rng.next_float() * (high_value - low_value);
+                stack.push(Data::from_float(value));
+                Ok(())
+            },
+        )
+        .doc("Push a random float between the last two elements, the upper one excluded")
+        .effect(Effect::Fixed(2, 1))
+        .signature(Signature::Fixed(
+            &[Type::Unknown, Type::Unknown],
+            &[Type::Float],
+        )),
+        TokenType::reg(
+            TokenKind::Function,
+            "shuffle",
+            "shuffle",
+            |stack: &mut Stack, _, _, _, _, runtime: &mut Runtime, _| -> Result<()> {
+                let mut elements = pop_n(stack)?;
+                runtime.rng.shuffle(&mut elements);
+                for element in elements {
+                    stack.push(element);
+                }
+                Ok(())
+            },
+        )
+        .doc("Shuffle the n elements under the count on top of the stack"),
+        TokenType::reg(
+            TokenKind::Function,
+            "choose",
+            "choose",
+            |stack: &mut Stack, _, _, _, _, runtime: &mut Runtime, _| -> Result<()> {
+                let mut elements = pop_n(stack)?;
+                if elements.is_empty() {
+                    stack.push(Data::from_int(0));
+                    return Err(anyhow::anyhow!("Cannot choose from zero elements"));
+                }
+                let index = runtime.rng.below(elements.len() as u64) as usize;
+                stack.push(elements.swap_remove(index));
+                Ok(())
+            },
+        )
+        .doc("Replace the n elements under the count on top of the stack with one of them"),
+        TokenType::reg(
+            TokenKind::Function,
+            "seed",
+            "seed",
+            |stack: &mut Stack, _, _, _, _, runtime: &mut Runtime, _| -> Result<()> {
+                let seed = stack.pop()?;
+                if !seed.is_int() {
+                    stack.push(seed);
+                    return Err(anyhow::anyhow!("Seed must be an integer"));
+                }
+                runtime.rng.seed(seed.as_int()? as u64);
+                Ok(())
+            },
+        )
+        .doc("Reseed the generator with the int on top of the stack")
+        .effect(Effect::Fixed(1, 0))
+        .signature(Signature::Fixed(&[Type::Int], &[])),
+    ]
+}
@@ -0,0 +1,74 @@
+use anyhow::Result;
+
+use crate::engine::data::Data;
+use crate::engine::lexer::token::{Effect, TokenKind, TokenType};
+use crate::engine::stack::Stack;
+
+/// Compares ints and floats numerically, unlike `=` which fails on different types. Other
+/// values must match in type and value.
+fn equal(a: &Data, b: &Data) -> bool {
+    match (a, b) {
+        (Data::Int(a), Data::Float(b)) | (Data::Float(b), Data::Int(a)) => *a as f64 == *b as f64,
+        _ => a == b,
+    }
+}
+
+/// Shows a value with its type, so `1` and `"1"` can be told apart in a failure.
+fn describe(value: &Data) -> String {
+    format!("{} ({})", value, value.type_name())
+}
+
+pub fn tokens() -> Vec<TokenType> {
+    vec![
+        TokenType::reg(
+            TokenKind::Function,
+            "assert",
+            "assert",
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
+                let value = stack.pop()?;
+                if value.is_false() {
+                    return Err(anyhow::anyhow!(
+                        "Assertion failed: expected a true value, got {}",
+                        describe(&value)
+                    ));
+                }
+                Ok(())
+            },
+        )
+        .doc("Fail unless the value on the stack is true")
+        .effect(Effect::Fixed(1, 0)),
+        TokenType::reg(
+            TokenKind::Function,
+            "assert-eq",
+            "assert-eq",
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
+                let expected = stack.pop()?;
+                let actual = stack.pop()?;
+                if !equal(&actual, &expected) {
+                    return Err(anyhow::anyhow!(
+                        "Assertion failed: expected {}, got {}",
+                        describe(&expected),
+                        describe(&actual)
+                    ));
+                }
+                Ok(())
+            },
+        )
+        .doc("Fail unless the actual value under the top equals the expected value on top")
+        .effect(Effect::Fixed(2, 0)),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equality() {
+        assert!(equal(&Data::Int(1), &Data::Float(1.0)));
+        assert!(equal(&Data::Float(1.0), &Data::Int(1)));
+        assert!(equal(&Data::from_str("a"), &Data::from_str("a")));
+        assert!(!equal(&Data::Int(1), &Data::from_str("1")));
+        assert!(!equal(&Data::None, &Data::Bool(false)));
+    }
+}
@@ -0,0 +1,281 @@
+use anyhow::Result;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::engine::data::{Data, Type};
+use crate::engine::lexer::token::{Effect, Signature, TokenKind, TokenType};
+use crate::engine::runtime::Runtime;
+use crate::engine::stack::Stack;
+
+/// Days since 1970-01-01 for a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Proleptic Gregorian date for days since 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Formats Unix milliseconds as an ISO-8601 UTC timestamp, e.g. `2024-03-01T12:30:00.000Z`.
+pub fn format_iso8601(ms: i64) -> String {
+    let days = ms.div_euclid(86_400_000);
+    let ms_of_day = ms.rem_euclid(86_400_000);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        ms_of_day / 3_600_000,
+        ms_of_day / 60_000 % 60,
+        ms_of_day / 1000 % 60,
+        ms_of_day % 1000
+    )
+}
+
+/// Parses an ISO-8601 date (`2024-03-01`) or date-time (`2024-03-01T12:30:00.5+02:00`) into Unix
+/// milliseconds. A date-time without an offset is taken as UTC.
+pub fn parse_iso8601(s: &str) -> Result<i64> {
+    let invalid = || anyhow::anyhow!("Invalid ISO-8601 date: {}", s);
+    let number = |part: &str| -> Result<i64> {
+        if part.is_empty() || !part.chars().all(|c| c.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        part.parse::<i64>().map_err(|_| invalid())
+    };
+
+    let (date, time) = match s.find(['T', 't', ' ']) {
+        Some(i) => (&s[..i], Some(&s[i + 1..])),
+        None => (s, None),
+    };
+    let date_parts = date.split('-').collect::<Vec<&str>>();
+    if date_parts.len() != 3 || date_parts[0].len() != 4 {
+        return Err(invalid());
+    }
+    let (year, month, day) = (
+        number(date_parts[0])?,
+        number(date_parts[1])?,
+        number(date_parts[2])?,
+    );
+    let days_in_month = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        _ => return Err(invalid()),
+    };
+    if day < 1 || day > days_in_month {
+        return Err(invalid());
+    }
+    let mut ms = days_from_civil(year, month, day) * 86_400_000;
+
+    let time = match time {
+        Some(time) => time,
+        None => return Ok(ms),
+    };
+    let (clock, offset) = if let Some(clock) = time.strip_suffix(['Z', 'z']) {
+        (clock, 0)
+    } else if let Some(i) = time.rfind(['+', '-']) {
+        let sign = if time[i..].starts_with('-') { -1 } else { 1 };
+        let offset = time[i + 1..].split(':').collect::<Vec<&str>>();
+        let (hours, minutes) = match offset.as_slice() {
+            [hours, minutes] => (number(hours)?, number(minutes)?),
+            [hhmm] if hhmm.len() == 4 => (number(&hhmm[..2])?, number(&hhmm[2..])?),
+            [hh] if hh.len() == 2 => (number(hh)?, 0),
+            _ => return Err(invalid()),
+        };
+        (&time[..i], sign * (hours * 60 + minutes) * 60_000)
+    } else {
+        (time, 0)
+    };
+    let (clock, fraction) = match clock.split_once(['.', ',']) {
+        Some((clock, fraction)) => (clock, Some(fraction)),
+        None => (clock, None),
+    };
+    let clock_parts = clock.split(':').collect::<Vec<&str>>();
+    let (hours, minutes, seconds) = match clock_parts.as_slice() {
+        [hours, minutes] => (number(hours)?, number(minutes)?, 0),
+        [hours, minutes, seconds] => (number(hours)?, number(minutes)?, number(seconds)?),
+        _ => return Err(invalid()),
+    };
+    if hours > 23 || minutes > 59 || seconds > 59 {
+        return Err(invalid());
+    }
+    ms += (hours * 3600 + minutes * 60 + seconds) * 1000;
+    if let Some(fraction) = fraction {
+        number(fraction)?;
+        let digits = format!("{:0<3}", &fraction[..fraction.len().min(3)]);
+        ms += number(&digits)?;
+    }
+    Ok(ms - offset)
+}
+
+fn since_epoch() -> Result<Duration> {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| anyhow::anyhow!("System clock is before the Unix epoch: {}", e))
+}
+
+pub fn tokens() -> Vec<TokenType> {
+    vec![
+        TokenType::reg(
+            TokenKind::Function,
+            "now",
+            "now",
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
+                stack.push(Data::from_int(since_epoch()?.as_millis() as i64));
+                Ok(())
+            },
+        )
+        .doc("Push the Unix time in milliseconds")
+        .effect(Effect::Fixed(0, 1))
+        .signature(Signature::Fixed(&[], &[Type::Int])),
+        TokenType::reg(
+            TokenKind::Function,
+            "now-ns",
+            "now-ns",
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
+                stack.push(Data::from_int(since_epoch()?.as_nanos() as i64));
+                Ok(())
+            },
+        )
+        .doc("Push the Unix time in nanoseconds")
+        .effect(Effect::Fixed(0, 1))
+        .signature(Signature::Fixed(&[], &[Type::Int])),
+        TokenType::reg(
+            TokenKind::Function,
+            "monotonic",
+            "monotonic",
+            |stack: &mut Stack, _, _, _, _, runtime: &mut Runtime, _| -> Result<()> {
+                stack.push(Data::from_int(runtime.started.elapsed().as_nanos() as i64));
+                Ok(())
+            },
+        )
+        .doc("Push the nanoseconds since the program started, never goes backwards")
+        .effect(Effect::Fixed(0, 1))
+        .signature(Signature::Fixed(&[], &[Type::Int])),
+        TokenType::reg(
+            TokenKind::Function,
+            "sleep",
+            "sleep",
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
+                let ms = stack.pop()?;
+                if !ms.is_int() || ms.as_int()? < 0 {
+                    stack.push(ms);
+                    return Err(anyhow::anyhow!("Sleep requires a non-negative integer"));
+                }
+                std::thread::sleep(Duration::from_millis(ms.as_int()? as u64));
+                Ok(())
+            },
+        )
+        .doc("Sleep for the number of milliseconds on the stack")
+        .effect(Effect::Fixed(1, 0))
+        .signature(Signature::Fixed(&[Type::Int], &[])),
+        TokenType::reg(
+            TokenKind::Function,
+            "format-time",
+            "format-time",
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
+                let ms = stack.pop()?;
+                if !ms.is_int() {
+                    stack.push(ms);
+                    return Err(anyhow::anyhow!("Time must be an integer"));
+                }
+                stack.push(Data::from_string(format_iso8601(ms.as_int()?)));
+                Ok(())
+            },
+        )
+        .doc("Format Unix milliseconds as an ISO-8601 UTC timestamp")
+        .effect(Effect::Fixed(1, 1))
+        .signature(Signature::Fixed(&[Type::Int], &[Type::String])),
+        TokenType::reg(
+            TokenKind::Function,
+            "parse-time",
+            "parse-time",
+            |stack: &mut Stack, _, _, _, _, _, _| -> Result<()> {
+                let time = stack.pop()?;
+                match parse_iso8601(&time.to_string()) {
+                    Ok(ms) => stack.push(Data::from_int(ms)),
+                    Err(e) => {
+                        stack.push(time);
+                        return Err(e);
+                    }
+                }
+                Ok(())
+            },
+        )
+        .doc("Parse an ISO-8601 date or timestamp into Unix milliseconds")
+        .effect(Effect::Fixed(1, 1))
+        .signature(Signature::Fixed(&[Type::Unknown], &[Type::Int])),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format() {
+        assert_eq!(format_iso8601(0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(format_iso8601(951_782_400_000), "2000-02-29T00:00:00.000Z");
+        assert_eq!(
+            format_iso8601(1_709_296_200_123),
+            "2024-03-01T12:30:00.123Z"
+        );
+        assert_eq!(format_iso8601(-1), "1969-12-31T23:59:59.999Z");
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(parse_iso8601("1970-01-01").unwrap(), 0);
+        assert_eq!(parse_iso8601("2000-02-29").unwrap(), 951_782_400_000);
+        assert_eq!(
+            parse_iso8601("2024-03-01T12:30:00.123Z").unwrap(),
+            1_709_296_200_123
+        );
+        assert_eq!(
+            parse_iso8601("2024-03-01T14:30:00.123+02:00").unwrap(),
+            1_709_296_200_123
+        );
+        assert_eq!(
+            parse_iso8601("2024-03-01T12:30").unwrap(),
+            1_709_296_200_000
+        );
+        assert_eq!(parse_iso8601("1970-01-01T00:00:00.5").unwrap(), 500);
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(parse_iso8601("").is_err());
+        assert!(parse_iso8601("2023-02-29").is_err());
+        assert!(parse_iso8601("2024-13-01").is_err());
+        assert!(parse_iso8601("2024-03-01T25:00:00Z").is_err());
+        assert!(parse_iso8601("2024-03-01Tnoon").is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        for ms in [0, 1, -86_400_001, 4_102_444_800_000, 1_709_296_200_123] {
+            assert_eq!(parse_iso8601(&format_iso8601(ms)).unwrap(), ms);
+        }
+    }
+}
@@ -0,0 +1,28 @@
+//! `Content-Length` framing shared by the protocol tests.
+
+use serde_json::Value;
+use std::io::{BufRead, Write};
+
+pub fn send(writer: &mut impl Write, message: &Value) {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+    writer.flush().unwrap();
+}
+
+pub fn receive(reader: &mut impl BufRead) -> Value {
+    let mut length = 0;
+    loop {
+        let mut header = String::new();
+        assert!(reader.read_line(&mut header).unwrap() > 0, "server closed");
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length: ") {
+            length = value.parse().unwrap();
+        }
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
@@ -0,0 +1,250 @@
+//! Drives `pinniped dap` with a scripted client over stdio.
+
+mod common;
+
+use serde_json::{json, Value};
+use std::io::BufReader;
+use std::path::Path;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+struct Client {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    seq: i64,
+    /// Events received while waiting for responses, oldest first
+    events: Vec<Value>,
+    /// Program output from every `output` event so far
+    output: String,
+}
+
+impl Client {
+    fn start() -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_pinniped"))
+            .arg("dap")
+            .current_dir(Path::new(env!("CARGO_MANIFEST_DIR")).join("examples"))
+            .env_remove("RUST_LOG")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        Self {
+            stdin: child.stdin.take().unwrap(),
+            stdout: BufReader::new(child.stdout.take().unwrap()),
+            child,
+            seq: 0,
+            events: Vec::new(),
+            output: String::new(),
+        }
+    }
+
+    fn read(&mut self) -> Value {
+        let message = common::receive(&mut self.stdout);
+        if message["event"] == "output" {
+            self.output
+                .push_str(message["body"]["output"].as_str().unwrap());
+        }
+        message
+    }
+
+    /// Sends a request and returns its response, keeping the events that came before it.
+    fn request(&mut self, command: &str, arguments: Value) -> Value {
+        self.seq += 1;
+        let request = json!({
+            "seq": self.seq,
+            "type": "request",
+            "command": command,
+            "arguments": arguments,
+        });
+        common::send(&mut self.stdin, &request);
+        loop {
+            let message = self.read();
+            if message["type"] == "response" && message["request_seq"] == self.seq {
+                assert_eq!(message["command"], command);
+                return message;
+            }
+            self.events.push(message);
+        }
+    }
+
+    /// Returns the next event called `name`, dropping the events before it.
+    fn event(&mut self, name: &str) -> Value {
+        loop {
+            let event = if self.events.is_empty() {
+                self.read()
+            } else {
+                self.events.remove(0)
+            };
+            if event["event"] == name {
+                return event;
+            }
+        }
+    }
+
+    /// Waits for the program to end, returning its output and exit code.
+    fn output_until_exit(&mut self) -> (String, i64) {
+        let code = self.event("exited")["body"]["exitCode"].as_i64().unwrap();
+        (std::mem::take(&mut self.output), code)
+    }
+
+    fn top_frame(&mut self) -> Value {
+        let trace = self.request("stackTrace", json!({"threadId": 1}));
+        trace["body"]["stackFrames"][0].clone()
+    }
+
+    fn finish(mut self) {
+        self.event("terminated");
+        assert_eq!(self.request("disconnect", json!({}))["success"], true);
+        assert!(self.child.wait().unwrap().success());
+    }
+}
+
+fn values(variables: &Value) -> Vec<&str> {
+    variables["body"]["variables"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|variable| variable["value"].as_str().unwrap())
+        .collect()
+}
+
+#[test]
+fn breakpoints_and_stepping() {
+    let mut client = Client::start();
+    let initialize = client.request("initialize", json!({"adapterID": "pinniped"}));
+    assert_eq!(initialize["body"]["supportsConfigurationDoneRequest"], true);
+    assert_eq!(
+        client.request("launch", json!({"program": "proc.seal"}))["success"],
+        true
+    );
+    client.event("initialized");
+
+    let breakpoints = client.request(
+        "setBreakpoints",
+        json!({"source": {"path": "proc.seal"}, "breakpoints": [{"line": 2}, {"line": 4}]}),
+    );
+    let verified = breakpoints["body"]["breakpoints"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|breakpoint| breakpoint["verified"].as_bool().unwrap())
+        .collect::<Vec<bool>>();
+    assert_eq!(verified, [true, false]);
+
+    client.request("configurationDone", json!({}));
+    assert_eq!(client.event("stopped")["body"]["reason"], "breakpoint");
+    let trace = client.request("stackTrace", json!({"threadId": 1}));
+    let frames = trace["body"]["stackFrames"].as_array().unwrap();
+    assert_eq!(frames.len(), 2);
+    assert_eq!(
+        (&frames[0]["name"], &frames[0]["line"]),
+        (&json!("print"), &json!(2))
+    );
+    assert_eq!(
+        (&frames[1]["name"], &frames[1]["line"]),
+        (&json!("main"), &json!(6))
+    );
+    assert!(frames[0]["source"]["path"]
+        .as_str()
+        .unwrap()
+        .ends_with("proc.seal"));
+
+    let scopes = client.request("scopes", json!({"frameId": 0}));
+    let names = scopes["body"]["scopes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|scope| scope["name"].as_str().unwrap())
+        .collect::<Vec<&str>>();
+    assert_eq!(
+        names,
+        ["Stack", "Return stack", "Locals", "Globals", "Marks"]
+    );
+    let stack = client.request("variables", json!({"variablesReference": 1}));
+    assert_eq!(values(&stack), ["1"]);
+    let marks = client.request("variables", json!({"variablesReference": 5}));
+    assert_eq!(marks["body"]["variables"][0]["name"], "print");
+
+    client.request("next", json!({"threadId": 1}));
+    assert_eq!(client.event("stopped")["body"]["reason"], "step");
+    let stack = client.request("variables", json!({"variablesReference": 1}));
+    assert_eq!(values(&stack), ["1", "1"]);
+
+    // Out of the proc, back to the loop after the call
+    client.request("stepOut", json!({"threadId": 1}));
+    client.event("stopped");
+    let frame = client.top_frame();
+    assert_eq!(
+        (&frame["name"], &frame["line"]),
+        (&json!("main"), &json!(7))
+    );
+
+    client.request(
+        "setBreakpoints",
+        json!({"source": {"path": "proc.seal"}, "breakpoints": []}),
+    );
+    client.request("continue", json!({"threadId": 1}));
+    let (output, code) = client.output_until_exit();
+    assert_eq!(output, "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n");
+    assert_eq!(code, 0);
+    client.finish();
+}
+
+#[test]
+fn entry_function_breakpoints_and_evaluate() {
+    let mut client = Client::start();
+    client.request("initialize", json!({}));
+    client.request(
+        "launch",
+        json!({"program": "vars.seal", "stopOnEntry": true}),
+    );
+    let breakpoints = client.request(
+        "setFunctionBreakpoints",
+        json!({"breakpoints": [{"name": "missing"}]}),
+    );
+    assert_eq!(breakpoints["body"]["breakpoints"][0]["verified"], false);
+    client.request(
+        "setBreakpoints",
+        json!({"source": {"path": "vars.seal"}, "breakpoints": [{"line": 5}]}),
+    );
+    client.request("configurationDone", json!({}));
+    assert_eq!(client.event("stopped")["body"]["reason"], "entry");
+    assert_eq!(client.top_frame()["line"], 1);
+
+    client.request("continue", json!({"threadId": 1}));
+    assert_eq!(client.event("stopped")["body"]["reason"], "breakpoint");
+    let locals = client.request("variables", json!({"variablesReference": 3}));
+    assert_eq!(locals["body"]["variables"][0]["name"], "var_name");
+    assert_eq!(
+        client.request("evaluate", json!({"expression": "var_name"}))["body"]["result"],
+        "2"
+    );
+    let missing = client.request("evaluate", json!({"expression": "<<var_name"}));
+    assert_eq!(missing["success"], false);
+
+    client.request("continue", json!({"threadId": 1}));
+    let (output, code) = client.output_until_exit();
+    assert_eq!(
+        output,
+        "Stack debug:\n1, 2\nStack debug:\n1\nStack debug:\n1, 2\nStack debug:\n1, 2, 4\n10\n"
+    );
+    assert_eq!(code, 0);
+    client.finish();
+}
+
+#[test]
+fn errors_end_the_session() {
+    let program = std::env::temp_dir().join(format!("pinniped-dap-{}.seal", std::process::id()));
+    std::fs::write(&program, "(1) . nl\n(1) +\n").unwrap();
+    let mut client = Client::start();
+    client.request("initialize", json!({}));
+    client.request("launch", json!({"program": program}));
+    client.request("configurationDone", json!({}));
+    let (output, code) = client.output_until_exit();
+    std::fs::remove_file(&program).unwrap();
+    assert!(output.starts_with("1\nError interpreting token"));
+    assert_eq!(code, 1);
+    assert_eq!(client.request("threads", json!({}))["success"], true);
+    assert_eq!(client.request("stackTrace", json!({}))["success"], false);
+    client.finish();
+}
@@ -0,0 +1,205 @@
+//! Golden-output tests for the programs in `examples/`.
+//!
+//! Every `examples/<name>.seal` is run with the arguments in `<name>.args` (one per line) and the
+//! input in `<name>.stdin`, both optional. Its exit code and stdout are compared to
+//! `<name>.expected`. Every example is also run with `-O`, built with `pinniped build` and, when
+//! it only uses words the C runtime supports and `cc` is installed, transpiled with
+//! `pinniped emit-c` and compiled, and all of them must behave the same. Run
+//! `cargo test --test examples -- --update` to regenerate the expectations, any other non-flag
+//! argument only runs the examples whose name contains it.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+fn examples_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("examples")
+}
+
+/// Where compiled examples are written and the `$TMPDIR` of the examples, removed after the run.
+fn build_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("pinniped-examples-{}", std::process::id()))
+}
+
+/// Compiles the example to a temporary `.sealc` file and returns its path.
+fn build(example: &Path) -> PathBuf {
+    let dir = build_dir();
+    std::fs::create_dir_all(&dir).unwrap();
+    let output = dir.join(example.with_extension("sealc").file_name().unwrap());
+    let status = Command::new(env!("CARGO_BIN_EXE_pinniped"))
+        .arg("build")
+        .arg(example.file_name().unwrap())
+        .arg("-o")
+        .arg(&output)
+        .current_dir(examples_dir())
+        .status()
+        .unwrap();
+    assert!(status.success(), "building {} failed", example.display());
+    output
+}
+
+/// Runs `program` with `options`, and the arguments and input of the example from inside
+/// `examples/`, so locations in its output are repo-independent.
+fn run(example: &Path, program: &Path, options: &[&str]) -> String {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_pinniped"));
+    command.args(options).arg("-f").arg(program);
+    execute(example, command)
+}
+
+/// Transpiles the example with `pinniped emit-c` and compiles it with `cc`. Returns why not
+/// when it uses words emit-c does not support or when there is no C compiler.
+fn compile(example: &Path) -> Result<PathBuf, String> {
+    let dir = build_dir();
+    std::fs::create_dir_all(&dir).unwrap();
+    let source = dir.join(example.with_extension("c").file_name().unwrap());
+    let output = Command::new(env!("CARGO_BIN_EXE_pinniped"))
+        .arg("emit-c")
+        .arg(example.file_name().unwrap())
+        .arg("-o")
+        .arg(&source)
+        .current_dir(examples_dir())
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap();
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("is not supported by emit-c"),
+            "emitting C for {} failed: {}",
+            example.display(),
+            stderr
+        );
+        let reason = stderr
+            .rsplit("Error emitting C: ")
+            .next()
+            .unwrap_or_default();
+        return Err(reason.trim().to_string());
+    }
+
+    let executable = dir.join(example.file_stem().unwrap());
+    let status = match Command::new("cc")
+        .arg("-O2")
+        .arg("-o")
+        .arg(&executable)
+        .arg(&source)
+        .arg("-lm")
+        .status()
+    {
+        Ok(status) => status,
+        Err(_) => return Err("no cc".to_string()),
+    };
+    assert!(status.success(), "compiling {} failed", source.display());
+    Ok(executable)
+}
+
+/// Runs `command` with the arguments and input of the example from inside `examples/`.
+fn execute(example: &Path, mut command: Command) -> String {
+    if let Ok(args) = std::fs::read_to_string(example.with_extension("args")) {
+        command.args(args.lines());
+    }
+    let input = std::fs::read(example.with_extension("stdin")).unwrap_or_default();
+
+    let mut child = command
+        .current_dir(examples_dir())
+        .env("NO_COLOR", "1")
+        .env("TMPDIR", build_dir())
+        .env_remove("RUST_LOG")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(&input).unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    format!(
+        "# exit: {}\n{}",
+        output
+            .status
+            .code()
+            .map_or("signal".to_string(), |c| c.to_string()),
+        String::from_utf8_lossy(&output.stdout)
+    )
+}
+
+fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    let update = args.iter().any(|a| a == "--update");
+    let filters = args
+        .iter()
+        .filter(|a| !a.starts_with('-'))
+        .collect::<Vec<&String>>();
+
+    let mut examples = std::fs::read_dir(examples_dir())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|e| e == "seal"))
+        .filter(|path| {
+            let name = path.file_stem().unwrap().to_string_lossy();
+            filters.is_empty() || filters.iter().any(|f| name.contains(f.as_str()))
+        })
+        .collect::<Vec<PathBuf>>();
+    examples.sort();
+
+    std::fs::create_dir_all(build_dir()).unwrap();
+    let mut failures = 0;
+    for example in &examples {
+        let name = example.file_name().unwrap().to_string_lossy();
+        let source = Path::new(example.file_name().unwrap());
+        let actual = run(example, source, &[]);
+        let expected_path = example.with_extension("expected");
+        if update {
+            std::fs::write(&expected_path, &actual).unwrap();
+            println!("example {} ... updated", name);
+            continue;
+        }
+        match std::fs::read_to_string(&expected_path) {
+            Ok(expected) if expected == actual => {
+                let optimized = run(example, source, &["-O"]);
+                let compiled = run(example, &build(example), &[]);
+                if optimized != actual {
+                    println!("example {} ... FAILED (-O)", name);
+                    println!("--- expected\n{}--- actual\n{}---", expected, optimized);
+                    failures += 1;
+                } else if compiled != actual {
+                    println!("example {} ... FAILED (bytecode)", name);
+                    println!("--- expected\n{}--- actual\n{}---", expected, compiled);
+                    failures += 1;
+                } else {
+                    match compile(example) {
+                        Ok(executable) => {
+                            let native = execute(example, Command::new(executable));
+                            if native != actual {
+                                println!("example {} ... FAILED (C)", name);
+                                println!("--- expected\n{}--- actual\n{}---", expected, native);
+                                failures += 1;
+                            } else {
+                                println!("example {} ... ok", name);
+                            }
+                        }
+                        Err(reason) => println!("example {} ... ok (no C: {})", name, reason),
+                    }
+                }
+            }
+            Ok(expected) => {
+                println!("example {} ... FAILED", name);
+                println!("--- expected\n{}--- actual\n{}---", expected, actual);
+                failures += 1;
+            }
+            Err(_) => {
+                println!(
+                    "example {} ... FAILED (no {})",
+                    name,
+                    expected_path.display()
+                );
+                failures += 1;
+            }
+        }
+    }
+
+    let _ = std::fs::remove_dir_all(build_dir());
+    println!("\nexamples: {} run, {} failed", examples.len(), failures);
+    if failures > 0 {
+        std::process::exit(1);
+    }
+}
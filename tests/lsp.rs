@@ -0,0 +1,115 @@
+//! Drives `pinniped lsp` with a scripted client over stdio.
+
+mod common;
+
+use serde_json::{json, Value};
+use std::io::BufReader;
+use std::process::{ChildStdout, Command, Stdio};
+
+const URI: &str = "file:///project/main.seal";
+
+/// Reads messages until the response to `id`, returning it and the notifications before it.
+fn response(stdout: &mut BufReader<ChildStdout>, id: i64) -> (Value, Vec<Value>) {
+    let mut notifications = Vec::new();
+    loop {
+        let message = common::receive(stdout);
+        if message["id"] == id {
+            return (message, notifications);
+        }
+        notifications.push(message);
+    }
+}
+
+#[test]
+fn session() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_pinniped"))
+        .arg("lsp")
+        .env_remove("RUST_LOG")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut request = |message: Value| {
+        let mut message = message;
+        message["jsonrpc"] = json!("2.0");
+        common::send(&mut stdin, &message);
+    };
+
+    request(json!({"id": 1, "method": "initialize", "params": {"capabilities": {}}}));
+    let (initialize, _) = response(&mut stdout, 1);
+    assert_eq!(
+        initialize["result"]["capabilities"]["definitionProvider"],
+        true
+    );
+    assert_eq!(
+        initialize["result"]["capabilities"]["positionEncoding"],
+        "utf-16"
+    );
+
+    let text = "(twice) proc ( n -- n )\n  : +\nret\n(2) {twice} . nl\n(3) swap\n";
+    request(json!({
+        "method": "textDocument/didOpen",
+        "params": {"textDocument": {"uri": URI, "languageId": "seal", "version": 1, "text": text}},
+    }));
+    request(json!({
+        "id": 3,
+        "method": "textDocument/hover",
+        "params": {"textDocument": {"uri": URI}, "position": {"line": 1, "character": 4}},
+    }));
+    let (hover, notifications) = response(&mut stdout, 3);
+    // Diagnostics for the opened document come before the hover
+    let diagnostics = &notifications[0]["params"]["diagnostics"];
+    assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+    assert_eq!(
+        diagnostics[0]["range"]["start"],
+        json!({"line": 4, "character": 4})
+    );
+    assert!(hover["result"]["contents"]["value"]
+        .as_str()
+        .unwrap()
+        .contains("Add the top two values"));
+
+    request(json!({
+        "id": 7,
+        "method": "textDocument/hover",
+        "params": {"textDocument": {"uri": URI}, "position": {"line": 3, "character": 6}},
+    }));
+    assert!(response(&mut stdout, 7).0["result"]["contents"]["value"]
+        .as_str()
+        .unwrap()
+        .ends_with("Proc `twice` defined on line 1 as `( n -- n )`"));
+
+    request(json!({
+        "id": 4,
+        "method": "textDocument/definition",
+        "params": {"textDocument": {"uri": URI}, "position": {"line": 3, "character": 6}},
+    }));
+    let (definition, _) = response(&mut stdout, 4);
+    assert_eq!(
+        definition["result"]["range"],
+        json!({"start": {"line": 0, "character": 0}, "end": {"line": 0, "character": 7}})
+    );
+
+    request(json!({
+        "id": 5,
+        "method": "textDocument/completion",
+        "params": {"textDocument": {"uri": URI}, "position": {"line": 4, "character": 0}},
+    }));
+    let (completion, _) = response(&mut stdout, 5);
+    let labels = completion["result"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["label"].as_str().unwrap())
+        .collect::<Vec<&str>>();
+    assert!(labels.contains(&"swp"));
+    assert!(labels.contains(&"{twice}"));
+    assert!(!labels.contains(&"push"));
+
+    request(json!({"id": 6, "method": "shutdown"}));
+    assert_eq!(response(&mut stdout, 6).0["result"], Value::Null);
+    request(json!({"method": "exit"}));
+    assert!(child.wait().unwrap().success());
+}